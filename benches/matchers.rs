@@ -0,0 +1,82 @@
+use std::hint::black_box;
+
+use anymock::{
+    matchers::{BinaryMatcher, binary_all_of, binary_contains, binary_len_lt},
+    ws::builders::on_message,
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use regex::Regex;
+
+const FRAME_SIZE: usize = 1024 * 1024;
+const STUBS: usize = 100;
+
+fn frame() -> Vec<u8> {
+    (0..FRAME_SIZE).map(|idx| (idx % 251) as u8).collect()
+}
+
+// Needles that are not in the frame, so every stub scans it whole.
+fn needles() -> impl Iterator<Item = Vec<u8>> {
+    (0..STUBS).map(|idx| vec![255, idx as u8, 254, 253])
+}
+
+fn binary_contains_stubs(c: &mut Criterion) {
+    let frame = frame();
+    let matchers: Vec<BinaryMatcher> = needles().map(binary_contains).collect();
+
+    c.bench_function("binary_contains_100_stubs_1mb", |b| {
+        b.iter(|| {
+            matchers
+                .iter()
+                .map(|matcher| matcher.score(Some(black_box(&frame))))
+                .max()
+        })
+    });
+}
+
+fn binary_all_of_stubs(c: &mut Criterion) {
+    let frame = frame();
+    let matchers: Vec<BinaryMatcher> = needles()
+        .map(|needle| binary_all_of([binary_contains(needle), binary_len_lt(1024)]))
+        .collect();
+
+    c.bench_function("binary_all_of_contains_and_len_100_stubs_1mb", |b| {
+        b.iter(|| {
+            matchers
+                .iter()
+                .map(|matcher| matcher.score(Some(black_box(&frame))))
+                .max()
+        })
+    });
+}
+
+const PATTERN: &str = r"^(?:order|trade)-[0-9]{4,8}\.(?:created|updated|cancelled)$";
+
+fn regex_stubs(c: &mut Criterion) {
+    c.bench_function("regex_new_1000_same_pattern", |b| {
+        b.iter(|| {
+            (0..1000)
+                .map(|_| Regex::new(black_box(PATTERN)).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+
+    c.bench_function("with_text_matching_1000_stubs_same_pattern", |b| {
+        b.iter(|| {
+            (0..1000)
+                .map(|_| {
+                    on_message()
+                        .with_text_matching(black_box(PATTERN))
+                        .returning_text("ok")
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    binary_contains_stubs,
+    binary_all_of_stubs,
+    regex_stubs
+);
+criterion_main!(benches);
@@ -0,0 +1,150 @@
+use std::{
+    net::TcpStream,
+    sync::{
+        Arc, Barrier,
+        atomic::{AtomicBool, AtomicU16, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anymock::{
+    matchers::{text_contains, text_eq},
+    ws::{Server, ServerHandle, builders::on_message},
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use tungstenite::{Message, WebSocket, stream::MaybeTlsStream};
+
+const CONNECTIONS: usize = 50;
+const REGISTERED: usize = 100;
+const FRAME_SIZE: usize = 1024 * 1024;
+
+static NEXT_PORT_ID: AtomicU16 = AtomicU16::new(19080);
+
+type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn listen() -> ServerHandle {
+    loop {
+        if let Ok(handle) = Server::default()
+            .port(NEXT_PORT_ID.fetch_add(1, Ordering::SeqCst))
+            .poll_interval(Duration::from_millis(1))
+            .start()
+        {
+            return handle;
+        }
+    }
+}
+
+fn register(handle: &ServerHandle, idx: usize) {
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(format!("request-{idx}")))
+                .returning_text(format!("response-{idx}")),
+        )
+        .unwrap();
+}
+
+// Every connection sends `iters` messages and waits for each reply while one more thread keeps
+// registering stubs, so matching and registration compete for the stub list.
+fn round_trips_while_registering(iters: u64) -> Duration {
+    let handle = listen();
+    (0..REGISTERED).for_each(|idx| register(&handle, idx));
+    let clients: Vec<Client> = (0..CONNECTIONS)
+        .map(|_| tungstenite::connect(handle.url()).unwrap().0)
+        .collect();
+
+    let start = Arc::new(Barrier::new(CONNECTIONS + 1));
+    let done = Arc::new(AtomicBool::new(false));
+    let registrar = {
+        let handle = handle.clone();
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let mut idx = REGISTERED;
+            while !done.load(Ordering::SeqCst) {
+                register(&handle, idx);
+                idx += 1;
+                thread::sleep(Duration::from_micros(100));
+            }
+        })
+    };
+    let readers: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(conn, mut client)| {
+            let start = Arc::clone(&start);
+            thread::spawn(move || {
+                let idx = conn % REGISTERED;
+                start.wait();
+                for _ in 0..iters {
+                    client
+                        .send(Message::text(format!("request-{idx}")))
+                        .unwrap();
+                    client.read().unwrap();
+                }
+            })
+        })
+        .collect();
+
+    start.wait();
+    let begin = Instant::now();
+    readers
+        .into_iter()
+        .for_each(|reader| reader.join().unwrap());
+    let elapsed = begin.elapsed();
+    done.store(true, Ordering::SeqCst);
+    registrar.join().unwrap();
+    elapsed
+}
+
+fn concurrent_registration(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stubs");
+    group.sample_size(10);
+    group.bench_function("50_connections_matching_while_registering", |b| {
+        b.iter_custom(round_trips_while_registering)
+    });
+    group.finish();
+}
+
+// An exact match answers the frame, so the contains stubs, which cannot outscore it, are not
+// scanned.
+fn large_frame_against_contains_stubs(c: &mut Criterion) {
+    let handle = listen();
+    let frame: String = (0..FRAME_SIZE)
+        .map(|idx| char::from(b'a' + (idx % 26) as u8))
+        .collect();
+    for idx in 0..REGISTERED {
+        handle
+            .register(
+                on_message()
+                    .with_text_like(text_contains(format!("missing-{idx}")))
+                    .returning_text("contains"),
+            )
+            .unwrap();
+    }
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(frame.clone()))
+                .returning_text("eq"),
+        )
+        .unwrap();
+    let mut client: Client = tungstenite::connect(handle.url()).unwrap().0;
+
+    let mut group = c.benchmark_group("stubs");
+    group.sample_size(20);
+    group.bench_function("text_frame_1mb_100_contains_stubs_and_eq", |b| {
+        b.iter(|| {
+            client.send(Message::text(frame.clone())).unwrap();
+            client.read().unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    concurrent_registration,
+    large_frame_against_contains_stubs
+);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+// Runs a WebSocket mock server from a YAML or JSON config file until SIGINT or SIGTERM.
+// SIGHUP reloads the stubs from the same file, the address, port and path stay as started.
+//
+//     anymock-server config.yaml
+
+use std::{env, fs, net::IpAddr, process::ExitCode};
+
+use anymock::ws::{
+    Server, Stub,
+    spec::{SpecError, StubSpec},
+};
+use serde::Deserialize;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default = "default_addr")]
+    addr: IpAddr,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default = "default_path")]
+    path: String,
+    #[serde(default)]
+    stubs: Vec<StubSpec>,
+}
+
+fn default_addr() -> IpAddr {
+    IpAddr::from([127, 0, 0, 1])
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+fn main() -> ExitCode {
+    let Some(config_path) = env::args().nth(1) else {
+        eprintln!("usage: anymock-server <config.yaml>");
+        return ExitCode::FAILURE;
+    };
+
+    match run(&config_path) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("anymock-server: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(config_path: &str) -> Result<(), String> {
+    let config = load(config_path)?;
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])
+        .map_err(|err| format!("failed to listen for signals: {err}"))?;
+    let handle = Server::default()
+        .addr(config.addr)
+        .port(config.port)
+        .path(config.path)
+        .start_with_stubs(build(config.stubs)?)
+        .map_err(|err| format!("failed to start server: {err}"))?;
+    eprintln!("anymock-server: listening on {}", handle.url());
+
+    for signal in signals.forever() {
+        if signal != SIGHUP {
+            break;
+        }
+
+        match load(config_path).and_then(|config| build(config.stubs)) {
+            Ok(stubs) => match handle.replace_stubs(stubs) {
+                Ok(_) => eprintln!("anymock-server: reloaded {config_path}"),
+                Err(errors) => {
+                    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                    eprintln!(
+                        "anymock-server: keeping previous stubs, {}",
+                        errors.join(", ")
+                    );
+                }
+            },
+            Err(err) => eprintln!("anymock-server: keeping previous stubs, {err}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn load(config_path: &str) -> Result<Config, String> {
+    let content = fs::read_to_string(config_path)
+        .map_err(|err| format!("failed to read {config_path}: {err}"))?;
+    serde_norway::from_str(&content).map_err(|err| format!("invalid config {config_path}: {err}"))
+}
+
+// Every stub is built before any is registered, so a broken spec keeps the previous ones.
+fn build(specs: Vec<StubSpec>) -> Result<Vec<Stub>, String> {
+    specs
+        .into_iter()
+        .map(Stub::try_from)
+        .collect::<Result<Vec<_>, SpecError>>()
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,158 @@
+use std::{fmt, io};
+
+use crate::matchers::HexError;
+#[cfg(feature = "json-schema")]
+use crate::matchers::SchemaError;
+#[cfg(feature = "spec")]
+use crate::ws::spec::SpecError;
+#[cfg(feature = "ws")]
+use crate::ws::{
+    SendToError, Timeout,
+    builders::{BuildError, DelayError},
+};
+
+/// Error of the fallible APIs of the crate. APIs with an error type of their own, like
+/// [`HexError`], convert into it, so `?` works across all of them.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AnymockError {
+    /// The server could not listen on its address.
+    Bind(io::Error),
+    InvalidRegex(regex::Error),
+    InvalidJson(serde_json::Error),
+    InvalidHex(HexError),
+    /// Bytes that do not decode with a binary codec, or a value it cannot encode.
+    InvalidEncoding(Box<dyn std::error::Error + Send + Sync>),
+    /// The errors `ServerHandle::register` found in a stub.
+    #[cfg(feature = "ws")]
+    Build(Vec<BuildError>),
+    #[cfg(feature = "ws")]
+    SendTo(SendToError),
+    #[cfg(feature = "ws")]
+    Delay(DelayError),
+    /// Nothing matching arrived in time, like from `ServerHandle::wait_for_message`.
+    #[cfg(feature = "ws")]
+    Timeout(Timeout),
+    #[cfg(feature = "spec")]
+    Spec(SpecError),
+    #[cfg(feature = "json-schema")]
+    Schema(SchemaError),
+}
+
+impl fmt::Display for AnymockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnymockError::Bind(source) => write!(f, "cannot start server: {source}"),
+            AnymockError::InvalidRegex(source) => write!(f, "invalid regex: {source}"),
+            AnymockError::InvalidJson(source) => write!(f, "invalid JSON: {source}"),
+            AnymockError::InvalidHex(source) => write!(f, "invalid hex: {source}"),
+            AnymockError::InvalidEncoding(source) => write!(f, "invalid encoded value: {source}"),
+            #[cfg(feature = "ws")]
+            AnymockError::Build(errors) => {
+                write!(f, "invalid stub: ")?;
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "ws")]
+            AnymockError::SendTo(source) => write!(f, "{source}"),
+            #[cfg(feature = "ws")]
+            AnymockError::Delay(source) => write!(f, "{source}"),
+            #[cfg(feature = "ws")]
+            AnymockError::Timeout(source) => write!(f, "{source}"),
+            #[cfg(feature = "spec")]
+            AnymockError::Spec(source) => write!(f, "{source}"),
+            #[cfg(feature = "json-schema")]
+            AnymockError::Schema(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for AnymockError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AnymockError::Bind(source) => Some(source),
+            AnymockError::InvalidRegex(source) => Some(source),
+            AnymockError::InvalidJson(source) => Some(source),
+            AnymockError::InvalidHex(source) => Some(source),
+            AnymockError::InvalidEncoding(source) => Some(source.as_ref()),
+            // Several errors, none of them is the cause.
+            #[cfg(feature = "ws")]
+            AnymockError::Build(_) => None,
+            #[cfg(feature = "ws")]
+            AnymockError::SendTo(source) => Some(source),
+            #[cfg(feature = "ws")]
+            AnymockError::Delay(source) => Some(source),
+            #[cfg(feature = "ws")]
+            AnymockError::Timeout(source) => Some(source),
+            #[cfg(feature = "spec")]
+            AnymockError::Spec(source) => Some(source),
+            #[cfg(feature = "json-schema")]
+            AnymockError::Schema(source) => Some(source),
+        }
+    }
+}
+
+impl From<regex::Error> for AnymockError {
+    fn from(source: regex::Error) -> Self {
+        AnymockError::InvalidRegex(source)
+    }
+}
+
+impl From<serde_json::Error> for AnymockError {
+    fn from(source: serde_json::Error) -> Self {
+        AnymockError::InvalidJson(source)
+    }
+}
+
+impl From<HexError> for AnymockError {
+    fn from(source: HexError) -> Self {
+        AnymockError::InvalidHex(source)
+    }
+}
+
+#[cfg(feature = "ws")]
+impl From<Vec<BuildError>> for AnymockError {
+    fn from(errors: Vec<BuildError>) -> Self {
+        AnymockError::Build(errors)
+    }
+}
+
+#[cfg(feature = "ws")]
+impl From<SendToError> for AnymockError {
+    fn from(source: SendToError) -> Self {
+        AnymockError::SendTo(source)
+    }
+}
+
+#[cfg(feature = "ws")]
+impl From<DelayError> for AnymockError {
+    fn from(source: DelayError) -> Self {
+        AnymockError::Delay(source)
+    }
+}
+
+#[cfg(feature = "ws")]
+impl From<Timeout> for AnymockError {
+    fn from(source: Timeout) -> Self {
+        AnymockError::Timeout(source)
+    }
+}
+
+#[cfg(feature = "spec")]
+impl From<SpecError> for AnymockError {
+    fn from(source: SpecError) -> Self {
+        AnymockError::Spec(source)
+    }
+}
+
+#[cfg(feature = "json-schema")]
+impl From<SchemaError> for AnymockError {
+    fn from(source: SchemaError) -> Self {
+        AnymockError::Schema(source)
+    }
+}
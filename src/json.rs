@@ -1,7 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use serde_json::{Number, Value};
 
+use crate::AnymockError;
+
+/// Wire format used to serialize a [`JsonValue`] response.
+#[derive(Debug, Clone)]
+pub enum Codec {
+    Json,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+#[derive(Debug, Clone)]
 pub enum JsonValue {
     Null,
     Bool(bool),
@@ -18,8 +31,22 @@ impl From<&JsonValue> for Value {
             JsonValue::Null => Value::Null,
             JsonValue::Bool(val) => Value::Bool(*val),
             JsonValue::Str(val) => Value::String(val.to_string()),
-            JsonValue::Float(val) => Value::Number(Number::from_f64(*val).unwrap()),
-            JsonValue::Int(val) => Value::Number(Number::from_i128(*val).unwrap()),
+            // JSON has no representation for NaN or infinity.
+            JsonValue::Float(val) => Number::from_f64(*val).map_or_else(
+                || {
+                    trace_event!(warn, value = %val, "non-finite float serialized as null");
+                    Value::Null
+                },
+                Value::Number,
+            ),
+            // Beyond 64 bits only the float approximation fits.
+            JsonValue::Int(val) => Number::from_i128(*val).map_or_else(
+                || {
+                    trace_event!(warn, value = %val, "integer beyond 64 bits serialized as a float");
+                    Number::from_f64(*val as f64).map_or(Value::Null, Value::Number)
+                },
+                Value::Number,
+            ),
             JsonValue::List(list) => Value::Array(list.iter().map(Value::from).collect()),
             JsonValue::Object(map) => Value::Object(
                 map.iter()
@@ -30,11 +57,79 @@ impl From<&JsonValue> for Value {
     }
 }
 
+/// Renders the value as compact JSON.
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Value::from(self))
+    }
+}
+
+impl JsonValue {
+    /// Looks up a dot-separated path such as `user.tags.0`. Numeric segments index lists.
+    pub fn get_path(&self, path: &str) -> Option<&JsonValue> {
+        path.split('.').try_fold(self, |value, key| match value {
+            JsonValue::Object(map) => map.get(key),
+            JsonValue::List(list) => key.parse::<usize>().ok().and_then(|idx| list.get(idx)),
+            _ => None,
+        })
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::Str(value)
+    }
+}
+
+impl From<i32> for JsonValue {
+    fn from(value: i32) -> Self {
+        JsonValue::Int(value.into())
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self {
+        JsonValue::Int(value.into())
+    }
+}
+
+impl From<u64> for JsonValue {
+    fn from(value: u64) -> Self {
+        JsonValue::Int(value.into())
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Float(value)
+    }
+}
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self {
+        JsonValue::List(value.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Builds a [`JsonValue`] with the same syntax as `serde_json::json!`, e.g.
+/// `json_value!({"ok": true, "items": [1, 2.5, null]})`.
+#[macro_export]
+macro_rules! json_value {
+    ($($json:tt)+) => {
+        $crate::json::JsonValue::from($crate::__serde_json::json!($($json)+))
+    };
+}
+
 impl TryFrom<&str> for JsonValue {
-    type Error = std::io::Error;
+    type Error = AnymockError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let value = serde_json::from_str::<Value>(value)
-            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        let value = serde_json::from_str::<Value>(value)?;
         Ok(JsonValue::from(value))
     }
 }
@@ -44,14 +139,21 @@ impl From<Value> for JsonValue {
             Value::Null => JsonValue::Null,
             Value::Bool(val) => JsonValue::Bool(val),
             Value::Number(val) => {
-                if val.is_i64() {
-                    JsonValue::Int(val.as_i64().unwrap().into())
-                } else if val.is_u64() {
-                    JsonValue::Int(val.as_u64().unwrap().into())
-                } else if val.is_f64() {
-                    JsonValue::Float(val.as_f64().unwrap())
+                if let Some(int) = val.as_i64() {
+                    JsonValue::Int(int.into())
+                } else if let Some(int) = val.as_u64() {
+                    JsonValue::Int(int.into())
+                } else if let Some(float) = val.as_f64() {
+                    JsonValue::Float(float)
                 } else {
-                    unreachable!()
+                    // Only reachable with serde_json's `arbitrary_precision`, which keeps the
+                    // number as written.
+                    let number = val.to_string();
+                    number
+                        .parse::<i128>()
+                        .map(JsonValue::Int)
+                        .or_else(|_| number.parse::<f64>().map(JsonValue::Float))
+                        .unwrap_or(JsonValue::Null)
                 }
             }
             Value::String(val) => JsonValue::Str(val),
@@ -68,3 +170,72 @@ impl From<Value> for JsonValue {
         }
     }
 }
+
+#[cfg(feature = "msgpack")]
+impl JsonValue {
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, AnymockError> {
+        let value = rmp_serde::from_slice::<Value>(bytes)
+            .map_err(|err| AnymockError::InvalidEncoding(err.into()))?;
+        Ok(JsonValue::from(value))
+    }
+
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, AnymockError> {
+        rmp_serde::to_vec(&Value::from(self))
+            .map_err(|err| AnymockError::InvalidEncoding(err.into()))
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl JsonValue {
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, AnymockError> {
+        let value = ciborium::from_reader::<ciborium::Value, _>(bytes)
+            .map_err(|err| AnymockError::InvalidEncoding(err.into()))?;
+        JsonValue::try_from(value)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, AnymockError> {
+        let mut buff = Vec::new();
+        ciborium::into_writer(&Value::from(self), &mut buff)
+            .map_err(|err| AnymockError::InvalidEncoding(err.into()))?;
+        Ok(buff)
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl TryFrom<ciborium::Value> for JsonValue {
+    type Error = AnymockError;
+    fn try_from(value: ciborium::Value) -> Result<Self, Self::Error> {
+        use ciborium::Value as Cbor;
+        Ok(match value {
+            Cbor::Null => JsonValue::Null,
+            Cbor::Bool(val) => JsonValue::Bool(val),
+            Cbor::Integer(val) => JsonValue::Int(val.into()),
+            Cbor::Float(val) => JsonValue::Float(val),
+            Cbor::Text(val) => JsonValue::Str(val),
+            Cbor::Bytes(val) => {
+                JsonValue::List(val.into_iter().map(|b| JsonValue::Int(b.into())).collect())
+            }
+            Cbor::Tag(_, val) => JsonValue::try_from(*val)?,
+            Cbor::Array(list) => JsonValue::List(
+                list.into_iter()
+                    .map(JsonValue::try_from)
+                    .collect::<Result<Vec<JsonValue>, _>>()?,
+            ),
+            Cbor::Map(map) => JsonValue::Object(
+                map.into_iter()
+                    .map(|(k, v)| match k {
+                        Cbor::Text(k) => Ok((k, JsonValue::try_from(v)?)),
+                        _ => Err(AnymockError::InvalidEncoding(
+                            "CBOR map keys must be text".into(),
+                        )),
+                    })
+                    .collect::<Result<HashMap<String, JsonValue>, _>>()?,
+            ),
+            _ => {
+                return Err(AnymockError::InvalidEncoding(
+                    "unsupported CBOR value".into(),
+                ));
+            }
+        })
+    }
+}
@@ -1,5 +1,22 @@
+// Forwards to the `tracing` macro of the same level, and expands to nothing without the
+// `tracing` feature.
+#[allow(unused_macros)]
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
 #[cfg(feature = "ws")]
 pub mod ws;
 
+pub mod error;
 pub mod json;
 pub mod matchers;
+
+pub use error::AnymockError;
+pub use matchers::Body;
+
+#[doc(hidden)]
+pub use serde_json as __serde_json;
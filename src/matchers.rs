@@ -1,33 +1,183 @@
-use std::collections::HashMap;
-
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+use memchr::memmem;
 use regex::{Error, Regex};
 use serde_json::Value;
 
-use crate::json::JsonValue;
+use crate::{
+    AnymockError,
+    json::{Codec, JsonValue},
+};
 
+#[derive(Debug, Clone)]
 pub enum Body {
     Json(JsonValue),
     Binary(Vec<u8>),
     PlainText(String),
+    Encoded {
+        value: JsonValue,
+        codec: Codec,
+    },
+    /// Contents of a file, sent as text when they are UTF-8 and as binary otherwise. Only
+    /// used for responses.
+    File(PathBuf),
 }
 
+#[derive(Debug)]
 pub enum BodyMatcher {
     Json(JsonMatcher),
     Binary(BinaryMatcher),
     PlainText(TextMatcher),
+    Int(IntMatcher),
+    Float(FloatMatcher),
+    Bool(BoolMatcher),
+    #[cfg(feature = "cbor")]
+    Cbor(JsonMatcher),
+    #[cfg(feature = "msgpack")]
+    MsgPack(JsonMatcher),
+    /// Messages without data, such as an empty text or binary frame.
+    Empty,
+    /// Messages with any data.
+    NonEmpty,
+    /// Scores the whole message, for formats the other matchers do not decode.
+    Custom(Box<dyn MatcherFn<Body>>),
+    #[cfg(feature = "json-schema")]
+    JsonSchema(CompiledSchema),
 }
 
 impl BodyMatcher {
     pub fn score(&self, body: Option<&Body>) -> u16 {
+        self.score_raw(body, None)
+    }
+
+    // `raw` is the frame text as the client sent it, when the body was decoded from one.
+    pub(crate) fn score_raw(&self, body: Option<&Body>, raw: Option<&str>) -> u16 {
         match (body, self) {
             (Some(Body::Json(json)), BodyMatcher::Json(matcher)) => matcher.score(Some(json)),
             (Some(Body::PlainText(part)), BodyMatcher::PlainText(matcher)) => {
                 matcher.score(Some(part))
             }
             (Some(Body::Binary(part)), BodyMatcher::Binary(matcher)) => matcher.score(Some(part)),
+            // Text frames that parse as JSON are matched against their original text, or
+            // against their compact serialization when it is not known.
+            (Some(Body::Json(json)), BodyMatcher::PlainText(matcher)) => match raw {
+                Some(raw) => matcher.score(Some(&raw.to_owned())),
+                None => matcher.score(Some(&json.to_string())),
+            },
+
+            // Bare scalars are decoded as JSON by the server, but plain text is parsed as well.
+            (Some(Body::Json(JsonValue::Int(v))), BodyMatcher::Int(matcher)) => {
+                matcher.score(Some(v))
+            }
+            (Some(Body::PlainText(part)), BodyMatcher::Int(matcher)) => part
+                .trim()
+                .parse::<i128>()
+                .map_or(0, |v| matcher.score(Some(&v))),
+            (Some(Body::Json(JsonValue::Float(v))), BodyMatcher::Float(matcher)) => {
+                matcher.score(Some(v))
+            }
+            (Some(Body::Json(JsonValue::Int(v))), BodyMatcher::Float(matcher)) => {
+                matcher.score(Some(&(*v as f64)))
+            }
+            (Some(Body::PlainText(part)), BodyMatcher::Float(matcher)) => part
+                .trim()
+                .parse::<f64>()
+                .map_or(0, |v| matcher.score(Some(&v))),
+            (Some(Body::Json(JsonValue::Bool(v))), BodyMatcher::Bool(matcher)) => {
+                matcher.score(Some(v))
+            }
+            (Some(Body::PlainText(part)), BodyMatcher::Bool(matcher)) => part
+                .trim()
+                .parse::<bool>()
+                .map_or(0, |v| matcher.score(Some(&v))),
+            #[cfg(feature = "cbor")]
+            (Some(Body::Binary(part)), BodyMatcher::Cbor(matcher)) => {
+                JsonValue::from_cbor(part).map_or(0, |json| matcher.score(Some(&json)))
+            }
+            #[cfg(feature = "msgpack")]
+            (Some(Body::Binary(part)), BodyMatcher::MsgPack(matcher)) => {
+                JsonValue::from_msgpack(part).map_or(0, |json| matcher.score(Some(&json)))
+            }
+            (body, BodyMatcher::Empty) if body.is_none_or(Body::is_empty) => 2,
+            (Some(body), BodyMatcher::NonEmpty) if !body.is_empty() => 1,
+            (body, BodyMatcher::Custom(matcher)) => matcher.score(body),
+            #[cfg(feature = "json-schema")]
+            (Some(Body::Json(json)), BodyMatcher::JsonSchema(schema)) => schema.score(json),
             _ => 0,
         }
     }
+
+    // Highest score `score` can return, whatever the body.
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            BodyMatcher::Json(matcher) => matcher.max_score(),
+            BodyMatcher::Binary(matcher) => matcher.max_score(),
+            BodyMatcher::PlainText(matcher) => matcher.max_score(),
+            BodyMatcher::Int(matcher) => matcher.max_score(),
+            BodyMatcher::Float(matcher) => matcher.max_score(),
+            BodyMatcher::Bool(matcher) => matcher.max_score(),
+            #[cfg(feature = "cbor")]
+            BodyMatcher::Cbor(matcher) => matcher.max_score(),
+            #[cfg(feature = "msgpack")]
+            BodyMatcher::MsgPack(matcher) => matcher.max_score(),
+            BodyMatcher::Empty => 2,
+            BodyMatcher::NonEmpty => 1,
+            BodyMatcher::Custom(_) => u16::MAX,
+            #[cfg(feature = "json-schema")]
+            BodyMatcher::JsonSchema(_) => 8,
+        }
+    }
+}
+
+impl Body {
+    fn is_empty(&self) -> bool {
+        match self {
+            Body::PlainText(text) => text.is_empty(),
+            Body::Binary(buff) => buff.is_empty(),
+            Body::Json(_) | Body::Encoded { .. } | Body::File(_) => false,
+        }
+    }
+}
+
+impl From<JsonMatcher> for BodyMatcher {
+    fn from(matcher: JsonMatcher) -> Self {
+        BodyMatcher::Json(matcher)
+    }
+}
+
+impl From<BinaryMatcher> for BodyMatcher {
+    fn from(matcher: BinaryMatcher) -> Self {
+        BodyMatcher::Binary(matcher)
+    }
+}
+
+impl From<TextMatcher> for BodyMatcher {
+    fn from(matcher: TextMatcher) -> Self {
+        BodyMatcher::PlainText(matcher)
+    }
+}
+
+impl From<IntMatcher> for BodyMatcher {
+    fn from(matcher: IntMatcher) -> Self {
+        BodyMatcher::Int(matcher)
+    }
+}
+
+impl From<FloatMatcher> for BodyMatcher {
+    fn from(matcher: FloatMatcher) -> Self {
+        BodyMatcher::Float(matcher)
+    }
+}
+
+impl From<BoolMatcher> for BodyMatcher {
+    fn from(matcher: BoolMatcher) -> Self {
+        BodyMatcher::Bool(matcher)
+    }
 }
 
 pub trait MatcherFn<T>: Send + Sync {
@@ -43,10 +193,45 @@ where
     }
 }
 
+impl<T> fmt::Debug for dyn MatcherFn<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn>")
+    }
+}
+
+// Combinators
+
+// Sum of the scores, or 0 when any of them does not match.
+fn all_of_score(mut scores: impl Iterator<Item = u16>) -> u16 {
+    scores
+        .try_fold(0u16, |total, score| {
+            (score != 0).then(|| total.saturating_add(score))
+        })
+        .unwrap_or(0)
+}
+
+// Best score, or 0 when none of them matches.
+fn any_of_score(scores: impl Iterator<Item = u16>) -> u16 {
+    scores.max().unwrap_or(0)
+}
+
+fn not_score(score: u16) -> u16 {
+    u16::from(score == 0)
+}
+
+// Highest score `all_of_score` can return for children scoring at most `max_scores`.
+fn all_of_max_score(max_scores: impl Iterator<Item = u16>) -> u16 {
+    max_scores.fold(0, u16::saturating_add)
+}
+
 // Text
 
+#[derive(Debug)]
 pub enum TextMatcher {
     Fn(Box<dyn MatcherFn<String>>),
+    AllOf(Vec<TextMatcher>),
+    AnyOf(Vec<TextMatcher>),
+    Not(Box<TextMatcher>),
     Eq(String),
     Regex(Regex),
     Contains(String),
@@ -54,6 +239,10 @@ pub enum TextMatcher {
     LenEq(usize),
     LenGreaterThan(usize),
     LenLessThan(usize),
+    /// The empty string only. Whitespace counts as content.
+    Empty,
+    /// Any string but the empty one.
+    NonEmpty,
     Any,
     None,
 }
@@ -62,6 +251,8 @@ impl TextMatcher {
     pub fn score(&self, value: Option<&String>) -> u16 {
         match (self, value) {
             (TextMatcher::Eq(part), Some(v)) if v == part => 8,
+            // As exact as `Eq("")`, and above the length matchers.
+            (TextMatcher::Empty, Some(v)) if v.is_empty() => 8,
             (TextMatcher::Regex(regex), Some(v)) if regex.is_match(v) => 7,
             (TextMatcher::Contains(part), Some(v)) if v.contains(part) => 6,
             (TextMatcher::NotContains(part), Some(v)) if !v.contains(part) => 5,
@@ -69,11 +260,32 @@ impl TextMatcher {
             (TextMatcher::LenGreaterThan(len), Some(v)) if v.len() > *len => 3,
             (TextMatcher::LenLessThan(len), Some(v)) if v.len() < *len => 3,
             (TextMatcher::None, None) => 2,
+            // Below the length matchers, which say more about the text, and above `Any`.
+            (TextMatcher::NonEmpty, Some(v)) if !v.is_empty() => 2,
             (TextMatcher::Any, Some(_)) => 1,
+            (TextMatcher::AllOf(matchers), v) => all_of_score(matchers.iter().map(|m| m.score(v))),
+            (TextMatcher::AnyOf(matchers), v) => any_of_score(matchers.iter().map(|m| m.score(v))),
+            (TextMatcher::Not(matcher), v) => not_score(matcher.score(v)),
             (TextMatcher::Fn(matcher_fn), v) => matcher_fn.score(v),
             _ => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            TextMatcher::Eq(_) | TextMatcher::Empty => 8,
+            TextMatcher::Regex(_) => 7,
+            TextMatcher::Contains(_) => 6,
+            TextMatcher::NotContains(_) => 5,
+            TextMatcher::LenEq(_) => 4,
+            TextMatcher::LenGreaterThan(_) | TextMatcher::LenLessThan(_) => 3,
+            TextMatcher::None | TextMatcher::NonEmpty => 2,
+            TextMatcher::Any | TextMatcher::Not(_) => 1,
+            TextMatcher::AllOf(matchers) => all_of_max_score(matchers.iter().map(Self::max_score)),
+            TextMatcher::AnyOf(matchers) => matchers.iter().map(Self::max_score).max().unwrap_or(0),
+            TextMatcher::Fn(_) => u16::MAX,
+        }
+    }
 }
 
 pub fn text_eq(text: impl Into<String>) -> TextMatcher {
@@ -88,8 +300,28 @@ pub fn text_not_contains(text: impl Into<String>) -> TextMatcher {
     TextMatcher::NotContains(text.into())
 }
 
-pub fn text_regex<'a>(regex: impl Into<&'a str>) -> Result<TextMatcher, Error> {
-    Ok(TextMatcher::Regex(Regex::new(regex.into())?))
+pub fn text_regex<'a>(regex: impl Into<&'a str>) -> Result<TextMatcher, AnymockError> {
+    Ok(TextMatcher::Regex(cached_regex(regex.into())?))
+}
+
+// Compiled regexes by pattern, so stubs built from the same pattern compile it once. Cloning a
+// `Regex` shares the compiled program. Invalid patterns are not kept.
+pub(crate) fn cached_regex(pattern: &str) -> Result<Regex, Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Mutex::default);
+
+    if let Some(regex) = cache
+        .lock()
+        .ok()
+        .and_then(|cache| cache.get(pattern).cloned())
+    {
+        return Ok(regex);
+    }
+    let regex = Regex::new(pattern)?;
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(pattern.to_string(), regex.clone());
+    }
+    Ok(regex)
 }
 
 pub fn text_len_eq(len: usize) -> TextMatcher {
@@ -103,6 +335,15 @@ pub fn text_len_gt(len: usize) -> TextMatcher {
 pub fn text_len_lt(len: usize) -> TextMatcher {
     TextMatcher::LenLessThan(len)
 }
+
+pub fn text_empty() -> TextMatcher {
+    TextMatcher::Empty
+}
+
+pub fn text_non_empty() -> TextMatcher {
+    TextMatcher::NonEmpty
+}
+
 pub fn text_any() -> TextMatcher {
     TextMatcher::Any
 }
@@ -115,12 +356,33 @@ pub fn text_fn(matcher: impl MatcherFn<String> + 'static) -> TextMatcher {
     TextMatcher::Fn(Box::new(matcher))
 }
 
+pub fn text_all_of(matchers: impl IntoIterator<Item = TextMatcher>) -> TextMatcher {
+    TextMatcher::AllOf(matchers.into_iter().collect())
+}
+
+pub fn text_any_of(matchers: impl IntoIterator<Item = TextMatcher>) -> TextMatcher {
+    TextMatcher::AnyOf(matchers.into_iter().collect())
+}
+
+pub fn text_not(matcher: TextMatcher) -> TextMatcher {
+    TextMatcher::Not(Box::new(matcher))
+}
+
 // Binary
 
+#[derive(Debug)]
 pub enum BinaryMatcher {
     Fn(Box<dyn MatcherFn<Vec<u8>>>),
+    AllOf(Vec<BinaryMatcher>),
+    AnyOf(Vec<BinaryMatcher>),
+    Not(Box<BinaryMatcher>),
     Eq(Vec<u8>),
+    StartsWith(Vec<u8>),
+    EndsWith(Vec<u8>),
     Contains(Vec<u8>),
+    LenEq(usize),
+    LenGreaterThan(usize),
+    LenLessThan(usize),
     Any,
     None,
 }
@@ -128,28 +390,97 @@ pub enum BinaryMatcher {
 impl BinaryMatcher {
     pub fn score(&self, value: Option<&Vec<u8>>) -> u16 {
         match (self, value) {
-            (BinaryMatcher::Eq(part), Some(v)) if v == part => 4,
-            (BinaryMatcher::Contains(part), Some(v))
-                if part.len() <= v.len() && v.windows(part.len()).any(|sub| sub == part) =>
-            {
-                3
-            }
+            (BinaryMatcher::Eq(part), Some(v)) if v == part => 7,
+            (BinaryMatcher::StartsWith(part), Some(v)) if v.starts_with(part) => 6,
+            (BinaryMatcher::EndsWith(part), Some(v)) if v.ends_with(part) => 6,
+            (BinaryMatcher::Contains(part), Some(v)) if memmem::find(v, part).is_some() => 5,
+            (BinaryMatcher::LenEq(len), Some(v)) if v.len() == *len => 4,
+            (BinaryMatcher::LenGreaterThan(len), Some(v)) if v.len() > *len => 3,
+            (BinaryMatcher::LenLessThan(len), Some(v)) if v.len() < *len => 3,
             (BinaryMatcher::None, None) => 2,
             (BinaryMatcher::Any, Some(_)) => 1,
+            // Cheap matchers go first, so a failing one skips the scans of the others.
+            (BinaryMatcher::AllOf(matchers), v) => {
+                let cheap = matchers.iter().filter(|m| m.is_cheap());
+                let costly = matchers.iter().filter(|m| !m.is_cheap());
+                all_of_score(cheap.chain(costly).map(|m| m.score(v)))
+            }
+            (BinaryMatcher::AnyOf(matchers), v) => {
+                any_of_score(matchers.iter().map(|m| m.score(v)))
+            }
+            (BinaryMatcher::Not(matcher), v) => not_score(matcher.score(v)),
             (BinaryMatcher::Fn(matcher), v) => matcher.score(v),
             _ => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            BinaryMatcher::Eq(_) => 7,
+            BinaryMatcher::StartsWith(_) | BinaryMatcher::EndsWith(_) => 6,
+            BinaryMatcher::Contains(_) => 5,
+            BinaryMatcher::LenEq(_) => 4,
+            BinaryMatcher::LenGreaterThan(_) | BinaryMatcher::LenLessThan(_) => 3,
+            BinaryMatcher::None => 2,
+            BinaryMatcher::Any | BinaryMatcher::Not(_) => 1,
+            BinaryMatcher::AllOf(matchers) => {
+                all_of_max_score(matchers.iter().map(Self::max_score))
+            }
+            BinaryMatcher::AnyOf(matchers) => {
+                matchers.iter().map(Self::max_score).max().unwrap_or(0)
+            }
+            BinaryMatcher::Fn(_) => u16::MAX,
+        }
+    }
+
+    // Whether scoring takes constant time or stops at the length of its own bytes.
+    fn is_cheap(&self) -> bool {
+        matches!(
+            self,
+            BinaryMatcher::Eq(_)
+                | BinaryMatcher::StartsWith(_)
+                | BinaryMatcher::EndsWith(_)
+                | BinaryMatcher::LenEq(_)
+                | BinaryMatcher::LenGreaterThan(_)
+                | BinaryMatcher::LenLessThan(_)
+                | BinaryMatcher::Any
+                | BinaryMatcher::None
+        )
+    }
 }
 
 pub fn binary_eq(buff: impl Into<Vec<u8>>) -> BinaryMatcher {
     BinaryMatcher::Eq(buff.into())
 }
 
+pub fn binary_starts_with(buff: impl Into<Vec<u8>>) -> BinaryMatcher {
+    BinaryMatcher::StartsWith(buff.into())
+}
+
+pub fn binary_ends_with(buff: impl Into<Vec<u8>>) -> BinaryMatcher {
+    BinaryMatcher::EndsWith(buff.into())
+}
+
 pub fn binary_contains(buff: impl Into<Vec<u8>>) -> BinaryMatcher {
     BinaryMatcher::Contains(buff.into())
 }
 
+pub fn binary_len_eq(len: usize) -> BinaryMatcher {
+    BinaryMatcher::LenEq(len)
+}
+
+pub fn binary_len_gt(len: usize) -> BinaryMatcher {
+    BinaryMatcher::LenGreaterThan(len)
+}
+
+pub fn binary_len_lt(len: usize) -> BinaryMatcher {
+    BinaryMatcher::LenLessThan(len)
+}
+
+pub fn binary_eq_hex(hex: &str) -> Result<BinaryMatcher, HexError> {
+    Ok(BinaryMatcher::Eq(hex_to_bytes(hex)?))
+}
+
 pub fn binary_any() -> BinaryMatcher {
     BinaryMatcher::Any
 }
@@ -162,11 +493,64 @@ pub fn binary_fn(matcher: impl MatcherFn<Vec<u8>> + 'static) -> BinaryMatcher {
     BinaryMatcher::Fn(Box::new(matcher))
 }
 
+pub fn binary_all_of(matchers: impl IntoIterator<Item = BinaryMatcher>) -> BinaryMatcher {
+    BinaryMatcher::AllOf(matchers.into_iter().collect())
+}
+
+pub fn binary_any_of(matchers: impl IntoIterator<Item = BinaryMatcher>) -> BinaryMatcher {
+    BinaryMatcher::AnyOf(matchers.into_iter().collect())
+}
+
+pub fn binary_not(matcher: BinaryMatcher) -> BinaryMatcher {
+    BinaryMatcher::Not(Box::new(matcher))
+}
+
+/// Error returned when a hex string cannot be decoded into bytes.
+#[derive(Debug, PartialEq)]
+pub enum HexError {
+    OddLength,
+    InvalidChar(char),
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexError::OddLength => write!(f, "hex string has an odd number of digits"),
+            HexError::InvalidChar(c) => write!(f, "invalid hex digit '{c}'"),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+/// Decodes a hex string such as `"de ad:BE EF"` into bytes. Whitespace and colons are
+/// ignored.
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, HexError> {
+    let digits = hex
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .map(|c| c.to_digit(16).ok_or(HexError::InvalidChar(c)))
+        .collect::<Result<Vec<u32>, HexError>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(HexError::OddLength);
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+        .collect())
+}
+
 // Numbers
 
+#[derive(Debug)]
 pub enum IntMatcher {
     Fn(Box<dyn MatcherFn<i128>>),
+    AllOf(Vec<IntMatcher>),
+    AnyOf(Vec<IntMatcher>),
+    Not(Box<IntMatcher>),
     Eq(i128),
+    InRange(i128, i128),
     LessThan(i128),
     GreaterThan(i128),
     Any,
@@ -176,21 +560,47 @@ pub enum IntMatcher {
 impl IntMatcher {
     pub fn score(&self, value: Option<&i128>) -> u16 {
         match (self, value) {
-            (IntMatcher::Eq(m), Some(v)) if v == m => 4,
+            (IntMatcher::Eq(m), Some(v)) if v == m => 5,
+            (IntMatcher::InRange(lo, hi), Some(v)) if lo <= v && v <= hi => 4,
             (IntMatcher::LessThan(m), Some(v)) if v < m => 3,
             (IntMatcher::GreaterThan(m), Some(v)) if v > m => 3,
             (IntMatcher::None, None) => 2,
             (IntMatcher::Any, Some(_)) => 1,
+            (IntMatcher::AllOf(matchers), v) => all_of_score(matchers.iter().map(|m| m.score(v))),
+            (IntMatcher::AnyOf(matchers), v) => any_of_score(matchers.iter().map(|m| m.score(v))),
+            (IntMatcher::Not(matcher), v) => not_score(matcher.score(v)),
             (IntMatcher::Fn(matcher), v) => matcher.score(v),
             _ => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            IntMatcher::Eq(_) => 5,
+            IntMatcher::InRange(..) => 4,
+            IntMatcher::LessThan(_) | IntMatcher::GreaterThan(_) => 3,
+            IntMatcher::None => 2,
+            IntMatcher::Any | IntMatcher::Not(_) => 1,
+            IntMatcher::AllOf(matchers) => all_of_max_score(matchers.iter().map(Self::max_score)),
+            IntMatcher::AnyOf(matchers) => matchers.iter().map(Self::max_score).max().unwrap_or(0),
+            IntMatcher::Fn(_) => u16::MAX,
+        }
+    }
 }
 
 pub fn int_eq(num: impl Into<i128>) -> IntMatcher {
     IntMatcher::Eq(num.into())
 }
 
+pub fn int_in_range(lo: impl Into<i128>, hi: impl Into<i128>) -> IntMatcher {
+    let (lo, hi) = (lo.into(), hi.into());
+    if lo > hi {
+        IntMatcher::InRange(hi, lo)
+    } else {
+        IntMatcher::InRange(lo, hi)
+    }
+}
+
 pub fn int_lt(num: impl Into<i128>) -> IntMatcher {
     IntMatcher::LessThan(num.into())
 }
@@ -211,9 +621,27 @@ pub fn int_fn(matcher: impl MatcherFn<i128> + 'static) -> IntMatcher {
     IntMatcher::Fn(Box::new(matcher))
 }
 
+pub fn int_all_of(matchers: impl IntoIterator<Item = IntMatcher>) -> IntMatcher {
+    IntMatcher::AllOf(matchers.into_iter().collect())
+}
+
+pub fn int_any_of(matchers: impl IntoIterator<Item = IntMatcher>) -> IntMatcher {
+    IntMatcher::AnyOf(matchers.into_iter().collect())
+}
+
+pub fn int_not(matcher: IntMatcher) -> IntMatcher {
+    IntMatcher::Not(Box::new(matcher))
+}
+
+#[derive(Debug)]
 pub enum FloatMatcher {
     Fn(Box<dyn MatcherFn<f64>>),
+    AllOf(Vec<FloatMatcher>),
+    AnyOf(Vec<FloatMatcher>),
+    Not(Box<FloatMatcher>),
     Eq(f64),
+    ApproxEq(f64, f64),
+    InRange(f64, f64),
     LessThan(f64),
     GreaterThan(f64),
     Any,
@@ -223,21 +651,54 @@ pub enum FloatMatcher {
 impl FloatMatcher {
     pub fn score(&self, value: Option<&f64>) -> u16 {
         match (self, value) {
-            (FloatMatcher::Eq(m), Some(v)) if v == m => 4,
+            (FloatMatcher::Eq(m), Some(v)) if v == m => 5,
+            (FloatMatcher::ApproxEq(m, epsilon), Some(v)) if (v - m).abs() <= *epsilon => 5,
+            (FloatMatcher::InRange(lo, hi), Some(v)) if lo <= v && v <= hi => 4,
             (FloatMatcher::LessThan(m), Some(v)) if v < m => 3,
             (FloatMatcher::GreaterThan(m), Some(v)) if v > m => 3,
             (FloatMatcher::None, None) => 2,
             (FloatMatcher::Any, Some(_)) => 1,
+            (FloatMatcher::AllOf(matchers), v) => all_of_score(matchers.iter().map(|m| m.score(v))),
+            (FloatMatcher::AnyOf(matchers), v) => any_of_score(matchers.iter().map(|m| m.score(v))),
+            (FloatMatcher::Not(matcher), v) => not_score(matcher.score(v)),
             (FloatMatcher::Fn(matcher), v) => matcher.score(v),
             _ => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            FloatMatcher::Eq(_) | FloatMatcher::ApproxEq(..) => 5,
+            FloatMatcher::InRange(..) => 4,
+            FloatMatcher::LessThan(_) | FloatMatcher::GreaterThan(_) => 3,
+            FloatMatcher::None => 2,
+            FloatMatcher::Any | FloatMatcher::Not(_) => 1,
+            FloatMatcher::AllOf(matchers) => all_of_max_score(matchers.iter().map(Self::max_score)),
+            FloatMatcher::AnyOf(matchers) => {
+                matchers.iter().map(Self::max_score).max().unwrap_or(0)
+            }
+            FloatMatcher::Fn(_) => u16::MAX,
+        }
+    }
 }
 
 pub fn float_eq(num: impl Into<f64>) -> FloatMatcher {
     FloatMatcher::Eq(num.into())
 }
 
+pub fn float_approx_eq(num: impl Into<f64>, epsilon: impl Into<f64>) -> FloatMatcher {
+    FloatMatcher::ApproxEq(num.into(), epsilon.into().abs())
+}
+
+pub fn float_in_range(lo: impl Into<f64>, hi: impl Into<f64>) -> FloatMatcher {
+    let (lo, hi) = (lo.into(), hi.into());
+    if lo > hi {
+        FloatMatcher::InRange(hi, lo)
+    } else {
+        FloatMatcher::InRange(lo, hi)
+    }
+}
+
 pub fn float_lt(num: impl Into<f64>) -> FloatMatcher {
     FloatMatcher::LessThan(num.into())
 }
@@ -258,12 +719,28 @@ pub fn float_fn(matcher: impl MatcherFn<f64> + 'static) -> FloatMatcher {
     FloatMatcher::Fn(Box::new(matcher))
 }
 
+pub fn float_all_of(matchers: impl IntoIterator<Item = FloatMatcher>) -> FloatMatcher {
+    FloatMatcher::AllOf(matchers.into_iter().collect())
+}
+
+pub fn float_any_of(matchers: impl IntoIterator<Item = FloatMatcher>) -> FloatMatcher {
+    FloatMatcher::AnyOf(matchers.into_iter().collect())
+}
+
+pub fn float_not(matcher: FloatMatcher) -> FloatMatcher {
+    FloatMatcher::Not(Box::new(matcher))
+}
+
 // Bool
 
+#[derive(Debug)]
 pub enum BoolMatcher {
     Eq(bool),
     Any,
     None,
+    AllOf(Vec<BoolMatcher>),
+    AnyOf(Vec<BoolMatcher>),
+    Not(Box<BoolMatcher>),
 }
 
 impl BoolMatcher {
@@ -272,9 +749,22 @@ impl BoolMatcher {
             (BoolMatcher::Eq(b), Some(value)) if b.eq(value) => 3,
             (BoolMatcher::None, None) => 2,
             (BoolMatcher::Any, Some(_)) => 1,
+            (BoolMatcher::AllOf(matchers), v) => all_of_score(matchers.iter().map(|m| m.score(v))),
+            (BoolMatcher::AnyOf(matchers), v) => any_of_score(matchers.iter().map(|m| m.score(v))),
+            (BoolMatcher::Not(matcher), v) => not_score(matcher.score(v)),
             _ => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            BoolMatcher::Eq(_) => 3,
+            BoolMatcher::None => 2,
+            BoolMatcher::Any | BoolMatcher::Not(_) => 1,
+            BoolMatcher::AllOf(matchers) => all_of_max_score(matchers.iter().map(Self::max_score)),
+            BoolMatcher::AnyOf(matchers) => matchers.iter().map(Self::max_score).max().unwrap_or(0),
+        }
+    }
 }
 
 pub fn bool_eq(value: bool) -> BoolMatcher {
@@ -289,8 +779,21 @@ pub fn bool_none() -> BoolMatcher {
     BoolMatcher::None
 }
 
+pub fn bool_all_of(matchers: impl IntoIterator<Item = BoolMatcher>) -> BoolMatcher {
+    BoolMatcher::AllOf(matchers.into_iter().collect())
+}
+
+pub fn bool_any_of(matchers: impl IntoIterator<Item = BoolMatcher>) -> BoolMatcher {
+    BoolMatcher::AnyOf(matchers.into_iter().collect())
+}
+
+pub fn bool_not(matcher: BoolMatcher) -> BoolMatcher {
+    BoolMatcher::Not(Box::new(matcher))
+}
+
 // Json - Composition of Matchers
 
+#[derive(Debug)]
 pub enum JsonMatcher {
     Fn(Box<dyn MatcherFn<JsonValue>>),
     Null,
@@ -298,8 +801,23 @@ pub enum JsonMatcher {
     Str(TextMatcher),
     Int(IntMatcher),
     Float(FloatMatcher),
+    /// Matches both `Int` and `Float` values, compared as `f64`.
+    Number(FloatMatcher),
     List(Vec<JsonMatcher>),
+    ListAnyOrder(Vec<JsonMatcher>),
+    ArrayContains(Box<JsonMatcher>),
     Object(HashMap<String, JsonMatcher>),
+    /// Number of keys of an object.
+    ObjectLen(IntMatcher),
+    /// Number of items of a list.
+    ListLen(IntMatcher),
+    /// Object field that may be missing. A missing field scores 1, a present one is scored
+    /// by the inner matcher.
+    Optional(Box<JsonMatcher>),
+    AllOf(Vec<JsonMatcher>),
+    AnyOf(Vec<JsonMatcher>),
+    /// Scores 1 when the inner matcher does not match, a missing object field included.
+    Not(Box<JsonMatcher>),
 }
 
 impl JsonMatcher {
@@ -310,6 +828,10 @@ impl JsonMatcher {
             (Some(JsonValue::Str(v)), JsonMatcher::Str(matcher)) => matcher.score(Some(v)),
             (Some(JsonValue::Float(v)), JsonMatcher::Float(matcher)) => matcher.score(Some(v)),
             (Some(JsonValue::Int(v)), JsonMatcher::Int(matcher)) => matcher.score(Some(v)),
+            (Some(JsonValue::Int(v)), JsonMatcher::Number(matcher)) => {
+                matcher.score(Some(&(*v as f64)))
+            }
+            (Some(JsonValue::Float(v)), JsonMatcher::Number(matcher)) => matcher.score(Some(v)),
             (Some(JsonValue::List(list)), JsonMatcher::List(matchers)) => {
                 let mut total_score: u16 = 0;
                 for (m, item) in matchers.iter().zip(list.iter()) {
@@ -322,6 +844,14 @@ impl JsonMatcher {
 
                 total_score
             }
+            (Some(JsonValue::List(list)), JsonMatcher::ListAnyOrder(matchers)) => {
+                unordered_score(matchers, list)
+            }
+            (Some(JsonValue::List(list)), JsonMatcher::ArrayContains(matcher)) => list
+                .iter()
+                .map(|item| matcher.score(Some(item)))
+                .max()
+                .unwrap_or(0),
 
             (Some(JsonValue::Object(map)), JsonMatcher::Object(matchers)) => {
                 let mut total_score = 0;
@@ -336,10 +866,112 @@ impl JsonMatcher {
 
                 total_score
             }
+            (Some(JsonValue::Object(map)), JsonMatcher::ObjectLen(matcher)) => {
+                matcher.score(Some(&(map.len() as i128)))
+            }
+            (Some(JsonValue::List(list)), JsonMatcher::ListLen(matcher)) => {
+                matcher.score(Some(&(list.len() as i128)))
+            }
+            (None, JsonMatcher::Optional(_)) => 1,
+            (v, JsonMatcher::Optional(matcher)) => matcher.score(v),
+            (v, JsonMatcher::AllOf(matchers)) => all_of_score(matchers.iter().map(|m| m.score(v))),
+            (v, JsonMatcher::AnyOf(matchers)) => any_of_score(matchers.iter().map(|m| m.score(v))),
+            (v, JsonMatcher::Not(matcher)) => not_score(matcher.score(v)),
             (v, JsonMatcher::Fn(matcher)) => matcher.score(v),
             (_, _) => 0,
         }
     }
+
+    pub(crate) fn max_score(&self) -> u16 {
+        match self {
+            JsonMatcher::Null | JsonMatcher::Not(_) => 1,
+            JsonMatcher::Bool(matcher) => matcher.max_score(),
+            JsonMatcher::Str(matcher) => matcher.max_score(),
+            JsonMatcher::Int(matcher)
+            | JsonMatcher::ObjectLen(matcher)
+            | JsonMatcher::ListLen(matcher) => matcher.max_score(),
+            JsonMatcher::Float(matcher) | JsonMatcher::Number(matcher) => matcher.max_score(),
+            JsonMatcher::List(matchers)
+            | JsonMatcher::ListAnyOrder(matchers)
+            | JsonMatcher::AllOf(matchers) => {
+                all_of_max_score(matchers.iter().map(Self::max_score))
+            }
+            JsonMatcher::Object(matchers) => {
+                all_of_max_score(matchers.values().map(Self::max_score))
+            }
+            JsonMatcher::ArrayContains(matcher) => matcher.max_score(),
+            JsonMatcher::Optional(matcher) => matcher.max_score().max(1),
+            JsonMatcher::AnyOf(matchers) => matchers.iter().map(Self::max_score).max().unwrap_or(0),
+            JsonMatcher::Fn(_) => u16::MAX,
+        }
+    }
+}
+
+// Each matcher must be assigned to a distinct element. Candidates are tried from the
+// highest score down and earlier assignments are revisited (augmenting paths) when a
+// later matcher finds no free element, so duplicates and overlapping matchers resolve.
+fn unordered_score(matchers: &[JsonMatcher], list: &[JsonValue]) -> u16 {
+    if matchers.len() > list.len() {
+        return 0;
+    }
+
+    let candidates: Vec<Vec<(usize, u16)>> = matchers
+        .iter()
+        .map(|m| {
+            let mut scores: Vec<(usize, u16)> = list
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| (idx, m.score(Some(item))))
+                .filter(|(_, score)| *score > 0)
+                .collect();
+            scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+            scores
+        })
+        .collect();
+
+    fn assign(
+        matcher_idx: usize,
+        candidates: &[Vec<(usize, u16)>],
+        owners: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        for &(item_idx, _) in candidates[matcher_idx].iter() {
+            if visited[item_idx] {
+                continue;
+            }
+            visited[item_idx] = true;
+            let is_free = match owners[item_idx] {
+                None => true,
+                Some(owner) => assign(owner, candidates, owners, visited),
+            };
+            if is_free {
+                owners[item_idx] = Some(matcher_idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owners: Vec<Option<usize>> = vec![None; list.len()];
+    for matcher_idx in 0..matchers.len() {
+        let mut visited = vec![false; list.len()];
+        if !assign(matcher_idx, &candidates, &mut owners, &mut visited) {
+            return 0;
+        }
+    }
+
+    owners
+        .iter()
+        .enumerate()
+        .filter_map(|(item_idx, owner)| {
+            owner.and_then(|matcher_idx| {
+                candidates[matcher_idx]
+                    .iter()
+                    .find(|(idx, _)| *idx == item_idx)
+                    .map(|(_, score)| *score)
+            })
+        })
+        .sum()
 }
 
 #[macro_export]
@@ -366,6 +998,58 @@ macro_rules! json_list {
     }};
 }
 
+#[macro_export]
+macro_rules! json_list_unordered {
+    ( $( $value:expr ),* $(,)? ) => {{
+        use anymock::matchers::JsonMatcher;
+        let mut list: Vec<JsonMatcher> = Vec::new();
+        $(
+            list.push(($value).into());
+        )*
+        JsonMatcher::ListAnyOrder(list)
+    }};
+}
+
+pub fn json_array_contains(matcher: impl Into<JsonMatcher>) -> JsonMatcher {
+    JsonMatcher::ArrayContains(Box::new(matcher.into()))
+}
+
+pub fn json_object_len(matcher: IntMatcher) -> JsonMatcher {
+    JsonMatcher::ObjectLen(matcher)
+}
+
+pub fn json_list_len(matcher: IntMatcher) -> JsonMatcher {
+    JsonMatcher::ListLen(matcher)
+}
+
+pub fn json_number_eq(num: impl Into<f64>) -> JsonMatcher {
+    JsonMatcher::Number(float_eq(num))
+}
+
+pub fn json_number_gt(num: impl Into<f64>) -> JsonMatcher {
+    JsonMatcher::Number(float_gt(num))
+}
+
+pub fn json_number_lt(num: impl Into<f64>) -> JsonMatcher {
+    JsonMatcher::Number(float_lt(num))
+}
+
+pub fn json_optional(matcher: impl Into<JsonMatcher>) -> JsonMatcher {
+    JsonMatcher::Optional(Box::new(matcher.into()))
+}
+
+pub fn json_all_of(matchers: impl IntoIterator<Item = impl Into<JsonMatcher>>) -> JsonMatcher {
+    JsonMatcher::AllOf(matchers.into_iter().map(Into::into).collect())
+}
+
+pub fn json_any_of(matchers: impl IntoIterator<Item = impl Into<JsonMatcher>>) -> JsonMatcher {
+    JsonMatcher::AnyOf(matchers.into_iter().map(Into::into).collect())
+}
+
+pub fn json_not(matcher: impl Into<JsonMatcher>) -> JsonMatcher {
+    JsonMatcher::Not(Box::new(matcher.into()))
+}
+
 pub fn json_fn(matcher: impl MatcherFn<JsonValue> + 'static) -> JsonMatcher {
     JsonMatcher::Fn(Box::new(matcher))
 }
@@ -376,6 +1060,12 @@ impl From<TextMatcher> for JsonMatcher {
     }
 }
 
+impl From<BoolMatcher> for JsonMatcher {
+    fn from(value: BoolMatcher) -> Self {
+        JsonMatcher::Bool(value)
+    }
+}
+
 impl From<IntMatcher> for JsonMatcher {
     fn from(value: IntMatcher) -> Self {
         JsonMatcher::Int(value)
@@ -417,25 +1107,167 @@ impl From<Value> for JsonMatcher {
 }
 
 impl TryFrom<&str> for JsonMatcher {
-    type Error = std::io::Error;
+    type Error = AnymockError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let value = serde_json::from_str::<Value>(value)
-            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        let value = serde_json::from_str::<Value>(value)?;
 
         Ok(JsonMatcher::from(JsonValue::from(value)))
     }
 }
 
+// Json Schema
+
+/// JSON Schema compiled once, for [`BodyMatcher::JsonSchema`].
+#[cfg(feature = "json-schema")]
+pub struct CompiledSchema {
+    schema: Value,
+    validator: jsonschema::Validator,
+}
+
+#[cfg(feature = "json-schema")]
+impl CompiledSchema {
+    // A valid payload scores like an exact text match, so a schema wins over looser
+    // matchers on a single field.
+    fn score(&self, value: &JsonValue) -> u16 {
+        if self.validator.is_valid(&Value::from(value)) {
+            8
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(feature = "json-schema")]
+impl fmt::Debug for CompiledSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CompiledSchema").field(&self.schema).finish()
+    }
+}
+
+/// Error returned when a JSON Schema cannot be compiled.
+#[cfg(feature = "json-schema")]
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The schema is not JSON.
+    Json(serde_json::Error),
+    /// The schema is JSON but not a valid schema, with the reason.
+    Invalid(String),
+}
+
+#[cfg(feature = "json-schema")]
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Json(source) => write!(f, "schema is not valid JSON: {source}"),
+            SchemaError::Invalid(reason) => write!(f, "invalid schema: {reason}"),
+        }
+    }
+}
+
+#[cfg(feature = "json-schema")]
+impl std::error::Error for SchemaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SchemaError::Json(source) => Some(source),
+            SchemaError::Invalid(_) => None,
+        }
+    }
+}
+
+/// Compiles `schema`, written as JSON, into a matcher for JSON messages.
+#[cfg(feature = "json-schema")]
+pub fn json_schema(schema: &str) -> Result<BodyMatcher, SchemaError> {
+    let schema: Value = serde_json::from_str(schema).map_err(SchemaError::Json)?;
+    let validator =
+        jsonschema::validator_for(&schema).map_err(|err| SchemaError::Invalid(err.to_string()))?;
+    Ok(BodyMatcher::JsonSchema(CompiledSchema {
+        schema,
+        validator,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
 
     mod text {
 
-        use crate::matchers::{
-            text_any, text_contains, text_eq, text_fn, text_len_eq, text_len_gt, text_len_lt,
-            text_none, text_not_contains, text_regex,
+        use crate::{
+            AnymockError,
+            matchers::{
+                text_all_of, text_any, text_any_of, text_contains, text_empty, text_eq, text_fn,
+                text_len_eq, text_len_gt, text_len_lt, text_non_empty, text_none, text_not,
+                text_not_contains, text_regex,
+            },
         };
 
+        #[test]
+        fn should_text_all_of_returns_expected_scores() {
+            let matcher = text_all_of([text_contains("He"), text_len_eq(5)]);
+
+            assert_eq!(
+                text_contains("He").score(Some(&String::from("Hello")))
+                    + text_len_eq(5).score(Some(&String::from("Hello"))),
+                matcher.score(Some(&String::from("Hello")))
+            );
+            assert_eq!(0, matcher.score(Some(&String::from("Help"))));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_text_max_score_bound_scores() {
+            let value = String::from("Hello");
+            let matchers = [
+                text_eq("Hello"),
+                text_contains("ell"),
+                text_len_eq(5),
+                text_any(),
+                text_not(text_eq("Bye")),
+                text_all_of([text_contains("He"), text_len_eq(5)]),
+                text_any_of([text_eq("Hello"), text_any()]),
+            ];
+
+            for matcher in matchers {
+                assert_eq!(matcher.max_score(), matcher.score(Some(&value)));
+            }
+            assert!(text_eq("Bye").max_score() > text_eq("Bye").score(Some(&value)));
+            assert_eq!(u16::MAX, text_fn(|_: Option<&String>| 1).max_score());
+        }
+
+        #[test]
+        fn should_text_any_of_returns_expected_scores() {
+            let matcher = text_any_of([text_contains("Hello"), text_contains("Bye")]);
+
+            assert!(matcher.score(Some(&String::from("Hello John"))) > 0);
+            assert!(matcher.score(Some(&String::from("Bye John"))) > 0);
+            assert_eq!(0, matcher.score(Some(&String::from("John"))));
+            assert_eq!(
+                text_eq("Bye").score(Some(&String::from("Bye"))),
+                text_any_of([text_any(), text_eq("Bye")]).score(Some(&String::from("Bye")))
+            );
+        }
+
+        #[test]
+        fn should_text_not_returns_expected_scores() {
+            let matcher = text_not(text_eq("Hello"));
+
+            assert_eq!(1, matcher.score(Some(&String::from("Bye"))));
+            assert_eq!(1, matcher.score(None));
+            assert_eq!(0, matcher.score(Some(&String::from("Hello"))));
+        }
+
+        #[test]
+        fn should_text_nested_combinators_returns_expected_scores() {
+            let matcher = text_all_of([
+                text_any_of([text_contains("Hello"), text_contains("Hi")]),
+                text_not(text_contains("spam")),
+            ]);
+
+            assert!(matcher.score(Some(&String::from("Hello John"))) > 0);
+            assert!(matcher.score(Some(&String::from("Hi John"))) > 0);
+            assert_eq!(0, matcher.score(Some(&String::from("Hi spam"))));
+            assert_eq!(0, matcher.score(Some(&String::from("Bye John"))));
+        }
+
         #[test]
         fn should_text_eq_returns_expected_scores() {
             let matcher = text_eq("Hello");
@@ -475,6 +1307,22 @@ mod tests {
             assert_eq!(0, matcher.score(None));
         }
 
+        #[test]
+        fn should_text_regex_reuse_compiled_patterns() {
+            for _ in 0..3 {
+                let matcher = text_regex("^Cached [0-9]+$").unwrap();
+                assert!(matcher.score(Some(&String::from("Cached 42"))) > 0);
+                assert_eq!(0, matcher.score(Some(&String::from("Cached"))));
+            }
+
+            for _ in 0..2 {
+                assert!(matches!(
+                    text_regex("[unclosed"),
+                    Err(AnymockError::InvalidRegex(_))
+                ));
+            }
+        }
+
         #[test]
         fn should_text_len_eq_returns_expected_scores() {
             let matcher = text_len_eq(5);
@@ -502,6 +1350,36 @@ mod tests {
             assert_eq!(0, matcher.score(None));
         }
 
+        #[test]
+        fn should_text_empty_returns_expected_scores() {
+            let matcher = text_empty();
+
+            assert!(
+                matcher.score(Some(&String::new())) > text_len_eq(0).score(Some(&String::new()))
+            );
+            assert_eq!(0, matcher.score(Some(&String::from("  "))));
+            assert_eq!(0, matcher.score(Some(&String::from("Hello"))));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_text_non_empty_returns_expected_scores() {
+            let matcher = text_non_empty();
+
+            assert!(matcher.score(Some(&String::from("Hello"))) > 0);
+            assert!(matcher.score(Some(&String::from("  "))) > 0);
+            assert!(
+                matcher.score(Some(&String::from("Hello")))
+                    < text_len_gt(0).score(Some(&String::from("Hello")))
+            );
+            assert!(
+                matcher.score(Some(&String::from("Hello")))
+                    > text_any().score(Some(&String::from("Hello")))
+            );
+            assert_eq!(0, matcher.score(Some(&String::new())));
+            assert_eq!(0, matcher.score(None));
+        }
+
         #[test]
         fn should_text_any_returns_expected_scores() {
             let matcher = text_any();
@@ -554,8 +1432,156 @@ mod tests {
         }
     }
 
+    mod binary {
+
+        use crate::matchers::{
+            HexError, binary_all_of, binary_any_of, binary_contains, binary_ends_with, binary_eq,
+            binary_eq_hex, binary_len_eq, binary_len_gt, binary_len_lt, binary_not,
+            binary_starts_with, hex_to_bytes,
+        };
+
+        #[test]
+        fn should_binary_combinators_returns_expected_scores() {
+            let matcher = binary_any_of([
+                binary_starts_with([0xCA, 0xFE]),
+                binary_not(binary_len_gt(1)),
+            ]);
+
+            assert!(matcher.score(Some(&vec![0xCA, 0xFE, 0x01])) > 0);
+            assert!(matcher.score(Some(&vec![0x01])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x01, 0x02])));
+        }
+
+        #[test]
+        fn should_binary_contains_and_all_of_returns_expected_scores() {
+            let frame = vec![0x01, 0xCA, 0xFE, 0x02];
+
+            assert_eq!(5, binary_contains([0xCA, 0xFE]).score(Some(&frame)));
+            assert_eq!(5, binary_contains([]).score(Some(&frame)));
+            assert_eq!(0, binary_contains([0xFE, 0xCA]).score(Some(&frame)));
+            assert_eq!(0, binary_contains([0x01; 8]).score(Some(&frame)));
+
+            let matcher = binary_all_of([binary_contains([0xCA, 0xFE]), binary_len_lt(5)]);
+            assert_eq!(8, matcher.score(Some(&frame)));
+            assert_eq!(0, matcher.score(Some(&vec![0xCA, 0xFE, 0, 0, 0])));
+        }
+
+        #[test]
+        fn should_hex_to_bytes_ignore_whitespace_and_colons() {
+            assert_eq!(
+                Ok(vec![0xDE, 0xAD, 0xBE, 0xEF]),
+                hex_to_bytes("de ad:BE\nef")
+            );
+            assert_eq!(Ok(vec![]), hex_to_bytes(""));
+        }
+
+        #[test]
+        fn should_hex_to_bytes_fail_on_invalid_input() {
+            assert_eq!(Err(HexError::OddLength), hex_to_bytes("dea"));
+            assert_eq!(Err(HexError::InvalidChar('g')), hex_to_bytes("deag"));
+        }
+
+        #[test]
+        fn should_binary_eq_hex_returns_expected_scores() {
+            let matcher = match binary_eq_hex("deadbeef") {
+                Ok(m) => m,
+                Err(err) => panic!("Hex should decode: {err:?}"),
+            };
+
+            assert!(matcher.score(Some(&vec![0xDE, 0xAD, 0xBE, 0xEF])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0xDE, 0xAD])));
+            assert!(binary_eq_hex("xyz").is_err());
+        }
+
+        #[test]
+        fn should_binary_starts_with_returns_expected_scores() {
+            let matcher = binary_starts_with([0xCA, 0xFE]);
+
+            assert!(matcher.score(Some(&vec![0xCA, 0xFE, 0x01, 0x02])) > 0);
+            assert!(matcher.score(Some(&vec![0xCA, 0xFE])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x01, 0xCA, 0xFE])));
+            assert_eq!(0, matcher.score(Some(&vec![0xCA])));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_binary_ends_with_returns_expected_scores() {
+            let checksum = [0x5A, 0xA5];
+            let matcher = binary_ends_with(checksum);
+
+            assert!(matcher.score(Some(&vec![0x01, 0x02, 0x5A, 0xA5])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x5A, 0xA5, 0x01])));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_binary_len_eq_returns_expected_scores() {
+            let matcher = binary_len_eq(4);
+
+            assert!(matcher.score(Some(&vec![0x01, 0x02, 0x03, 0x04])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x01, 0x02, 0x03])));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_binary_len_gt_returns_expected_scores() {
+            let matcher = binary_len_gt(2);
+
+            assert!(matcher.score(Some(&vec![0x01, 0x02, 0x03])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x01, 0x02])));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_binary_len_lt_returns_expected_scores() {
+            let matcher = binary_len_lt(2);
+
+            assert!(matcher.score(Some(&vec![0x01])) > 0);
+            assert!(matcher.score(Some(&vec![])) > 0);
+            assert_eq!(0, matcher.score(Some(&vec![0x01, 0x02])));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_binary_prefix_and_suffix_rank_between_eq_and_contains() {
+            let frame = vec![0xCA, 0xFE, 0x01, 0x5A];
+
+            let eq = binary_eq(frame.clone()).score(Some(&frame));
+            let starts_with = binary_starts_with([0xCA, 0xFE]).score(Some(&frame));
+            let ends_with = binary_ends_with([0x5A]).score(Some(&frame));
+            let contains = binary_contains([0xFE, 0x01]).score(Some(&frame));
+
+            assert!(eq > starts_with && starts_with > contains);
+            assert!(eq > ends_with && ends_with > contains);
+        }
+    }
+
     mod bool {
-        use crate::matchers::{bool_any, bool_eq, bool_none};
+        use crate::matchers::{bool_all_of, bool_any, bool_any_of, bool_eq, bool_none, bool_not};
+
+        #[test]
+        fn should_bool_combinators_returns_expected_scores() {
+            assert_eq!(
+                bool_eq(true).score(Some(&true)) + bool_any().score(Some(&true)),
+                bool_all_of([bool_eq(true), bool_any()]).score(Some(&true))
+            );
+            assert_eq!(
+                0,
+                bool_all_of([bool_eq(true), bool_any()]).score(Some(&false))
+            );
+
+            let either = bool_any_of([bool_eq(true), bool_none()]);
+            assert_eq!(3, either.score(Some(&true)));
+            assert_eq!(2, either.score(None));
+            assert_eq!(0, either.score(Some(&false)));
+
+            assert_eq!(1, bool_not(bool_eq(true)).score(Some(&false)));
+            assert_eq!(0, bool_not(bool_eq(true)).score(Some(&true)));
+            assert_eq!(
+                1,
+                bool_not(bool_any_of([bool_eq(true), bool_none()])).score(Some(&false))
+            );
+        }
 
         #[test]
         fn should_bool_eq_returns_expected_scores() {
@@ -594,7 +1620,18 @@ mod tests {
 
     mod int {
 
-        use crate::matchers::{int_any, int_eq, int_fn, int_gt, int_lt, int_none};
+        use crate::matchers::{
+            int_all_of, int_any, int_eq, int_fn, int_gt, int_in_range, int_lt, int_none, int_not,
+        };
+
+        #[test]
+        fn should_int_combinators_returns_expected_scores() {
+            let matcher = int_all_of([int_in_range(0, 100), int_not(int_eq(50))]);
+
+            assert!(matcher.score(Some(&10)) > 0);
+            assert_eq!(0, matcher.score(Some(&50)));
+            assert_eq!(0, matcher.score(Some(&200)));
+        }
 
         #[test]
         fn should_int_eq_returns_expected_scores() {
@@ -602,6 +1639,27 @@ mod tests {
             assert_eq!(0, int_eq(10).score(Some(&9)));
         }
 
+        #[test]
+        fn should_int_in_range_returns_expected_scores() {
+            let matcher = int_in_range(18, 65);
+
+            assert!(matcher.score(Some(&18)) > 0);
+            assert!(matcher.score(Some(&40)) > 0);
+            assert!(matcher.score(Some(&65)) > 0);
+            assert_eq!(0, matcher.score(Some(&17)));
+            assert_eq!(0, matcher.score(Some(&66)));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_int_in_range_swap_inverted_bounds() {
+            let matcher = int_in_range(65, 18);
+
+            assert!(matcher.score(Some(&18)) > 0);
+            assert!(matcher.score(Some(&65)) > 0);
+            assert_eq!(0, matcher.score(Some(&66)));
+        }
+
         #[test]
         fn should_int_lt_returns_expected_scores() {
             let matcher = int_lt(10);
@@ -647,14 +1705,16 @@ mod tests {
             let value: i128 = 10;
 
             let eq_score = int_eq(10).score(Some(&value));
+            let in_range_score = int_in_range(5, 20).score(Some(&value));
             let lt_score = int_lt(20).score(Some(&value));
             let gt_score = int_gt(5).score(Some(&value));
             let any_score = int_any().score(Some(&value));
             let none_score = int_none().score(None);
 
             assert!(
-                eq_score > lt_score
-                    && eq_score > gt_score
+                eq_score > in_range_score
+                    && in_range_score > lt_score
+                    && in_range_score > gt_score
                     && lt_score >= gt_score
                     && gt_score > none_score
                     && none_score > any_score
@@ -664,7 +1724,19 @@ mod tests {
 
     mod float {
 
-        use crate::matchers::{float_any, float_eq, float_fn, float_gt, float_lt, float_none};
+        use crate::matchers::{
+            float_any, float_any_of, float_approx_eq, float_eq, float_fn, float_gt, float_in_range,
+            float_lt, float_none,
+        };
+
+        #[test]
+        fn should_float_combinators_returns_expected_scores() {
+            let matcher = float_any_of([float_lt(-10.0), float_gt(10.0)]);
+
+            assert!(matcher.score(Some(&-20.0)) > 0);
+            assert!(matcher.score(Some(&20.0)) > 0);
+            assert_eq!(0, matcher.score(Some(&0.0)));
+        }
 
         #[test]
         fn should_float_eq_returns_expected_scores() {
@@ -672,6 +1744,48 @@ mod tests {
             assert_eq!(0, float_eq(10.0).score(Some(&9.9)));
         }
 
+        #[test]
+        fn should_float_approx_eq_returns_expected_scores() {
+            let matcher = float_approx_eq(0.3, 1e-9);
+            let value = 0.1 + 0.2;
+
+            assert_eq!(0, float_eq(0.3).score(Some(&value)));
+            assert!(matcher.score(Some(&value)) > 0);
+            assert_eq!(0, matcher.score(Some(&0.31)));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_float_approx_eq_score_as_eq() {
+            let value = 0.1 + 0.2;
+
+            assert_eq!(
+                float_eq(0.3).score(Some(&0.3)),
+                float_approx_eq(0.3, 1e-9).score(Some(&value))
+            );
+        }
+
+        #[test]
+        fn should_float_in_range_returns_expected_scores() {
+            let matcher = float_in_range(1.5, 2.5);
+
+            assert!(matcher.score(Some(&1.5)) > 0);
+            assert!(matcher.score(Some(&2.0)) > 0);
+            assert!(matcher.score(Some(&2.5)) > 0);
+            assert_eq!(0, matcher.score(Some(&1.4)));
+            assert_eq!(0, matcher.score(Some(&2.6)));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_float_in_range_swap_inverted_bounds() {
+            let matcher = float_in_range(2.5, 1.5);
+
+            assert!(matcher.score(Some(&1.5)) > 0);
+            assert!(matcher.score(Some(&2.5)) > 0);
+            assert_eq!(0, matcher.score(Some(&2.6)));
+        }
+
         #[test]
         fn should_float_lt_returns_expected_scores() {
             let matcher = float_lt(10.0);
@@ -717,14 +1831,16 @@ mod tests {
             let value: f64 = 10.0;
 
             let eq_score = float_eq(10.0).score(Some(&value));
+            let in_range_score = float_in_range(5.0, 20.0).score(Some(&value));
             let lt_score = float_lt(20.0).score(Some(&value));
             let gt_score = float_gt(5.0).score(Some(&value));
             let any_score = float_any().score(Some(&value));
             let none_score = float_none().score(None);
 
             assert!(
-                eq_score > lt_score
-                    && eq_score > gt_score
+                eq_score > in_range_score
+                    && in_range_score > lt_score
+                    && in_range_score > gt_score
                     && lt_score >= gt_score
                     && gt_score > none_score
                     && none_score > any_score
@@ -732,20 +1848,170 @@ mod tests {
         }
     }
 
+    mod body {
+        use crate::{
+            json::JsonValue,
+            matchers::{Body, BodyMatcher, bool_eq, float_gt, int_eq, int_gt},
+        };
+
+        #[test]
+        fn should_int_body_parse_plain_text() {
+            let matcher = BodyMatcher::Int(int_gt(40));
+
+            assert!(matcher.score(Some(&Body::PlainText("42".into()))) > 0);
+            assert!(matcher.score(Some(&Body::Json(JsonValue::Int(42)))) > 0);
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("39".into()))));
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("forty".into()))));
+            assert_eq!(0, matcher.score(Some(&Body::Binary(vec![42]))));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_float_body_parse_plain_text() {
+            let matcher = BodyMatcher::Float(float_gt(4.0));
+
+            assert!(matcher.score(Some(&Body::PlainText("4.2".into()))) > 0);
+            assert!(matcher.score(Some(&Body::Json(JsonValue::Float(4.2)))) > 0);
+            assert!(matcher.score(Some(&Body::Json(JsonValue::Int(5)))) > 0);
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("4.2.1".into()))));
+        }
+
+        #[test]
+        fn should_bool_body_parse_plain_text() {
+            let matcher = BodyMatcher::Bool(bool_eq(true));
+
+            assert!(matcher.score(Some(&Body::PlainText("true".into()))) > 0);
+            assert!(matcher.score(Some(&Body::Json(JsonValue::Bool(true)))) > 0);
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("false".into()))));
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("yes".into()))));
+            assert_eq!(
+                0,
+                BodyMatcher::Int(int_eq(1)).score(Some(&Body::PlainText("true".into())))
+            );
+        }
+    }
+
+    #[cfg(feature = "json-schema")]
+    mod json_schema {
+        use crate::{
+            json_value,
+            matchers::{Body, SchemaError, json_schema},
+        };
+
+        const SCHEMA: &str = r#"{
+            "type": "object",
+            "required": ["kind", "qty"],
+            "properties": {
+                "kind": { "enum": ["buy", "sell"] },
+                "qty": { "type": "integer", "minimum": 1 }
+            }
+        }"#;
+
+        #[test]
+        fn should_json_schema_returns_expected_scores() {
+            let matcher = json_schema(SCHEMA).unwrap();
+
+            assert!(matcher.score(Some(&Body::Json(json_value!({"kind": "buy", "qty": 2})))) > 0);
+            assert_eq!(
+                0,
+                matcher.score(Some(&Body::Json(json_value!({"kind": "hold", "qty": 2}))))
+            );
+            assert_eq!(
+                0,
+                matcher.score(Some(&Body::Json(json_value!({"kind": "buy", "qty": 0}))))
+            );
+            assert_eq!(
+                0,
+                matcher.score(Some(&Body::Json(json_value!({"kind": "buy"}))))
+            );
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("buy".into()))));
+            assert_eq!(0, matcher.score(None));
+        }
+
+        #[test]
+        fn should_json_schema_fail_to_compile_invalid_schemas() {
+            assert!(matches!(json_schema("{"), Err(SchemaError::Json(_))));
+            assert!(matches!(
+                json_schema(r#"{"type": "whatever"}"#),
+                Err(SchemaError::Invalid(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    mod cbor {
+        use std::collections::HashMap;
+
+        use crate::matchers::{Body, BodyMatcher, JsonMatcher, int_gt, text_eq};
+
+        fn encode(value: &ciborium::Value) -> Vec<u8> {
+            let mut buff = Vec::new();
+            ciborium::into_writer(value, &mut buff).unwrap();
+            buff
+        }
+
+        #[test]
+        fn should_cbor_body_returns_expected_scores() {
+            let payload = encode(&ciborium::Value::Map(vec![
+                ("name".into(), "John".into()),
+                ("age".into(), 30.into()),
+            ]));
+            let matcher = BodyMatcher::Cbor(JsonMatcher::Object(HashMap::from([
+                ("name".to_string(), text_eq("John").into()),
+                ("age".to_string(), int_gt(20).into()),
+            ])));
+
+            assert!(matcher.score(Some(&Body::Binary(payload))) > 0);
+            assert_eq!(0, matcher.score(Some(&Body::Binary(vec![0xff, 0x00]))));
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("John".into()))));
+        }
+    }
+
+    #[cfg(feature = "msgpack")]
+    mod msgpack {
+        use std::collections::HashMap;
+
+        use serde_json::json;
+
+        use crate::matchers::{Body, BodyMatcher, JsonMatcher, int_gt, text_eq};
+
+        #[test]
+        fn should_msgpack_body_returns_expected_scores() {
+            let payload = rmp_serde::to_vec(&json!({"name": "John", "age": 30})).unwrap();
+            let matcher = BodyMatcher::MsgPack(JsonMatcher::Object(HashMap::from([
+                ("name".to_string(), text_eq("John").into()),
+                ("age".to_string(), int_gt(20).into()),
+            ])));
+
+            assert!(matcher.score(Some(&Body::Binary(payload))) > 0);
+            assert_eq!(0, matcher.score(Some(&Body::Binary(vec![0xc1]))));
+            assert_eq!(0, matcher.score(Some(&Body::PlainText("John".into()))));
+        }
+    }
+
     mod json {
-        use crate::{json::JsonValue, matchers::json_fn};
+        use std::collections::HashMap;
+
+        use crate::{
+            AnymockError,
+            json::JsonValue,
+            matchers::{
+                JsonMatcher, float_eq, int_eq, int_gt, int_lt, json_all_of, json_any_of,
+                json_array_contains, json_fn, json_list_len, json_not, json_number_eq,
+                json_number_gt, json_number_lt, json_object_len, json_optional, text_any,
+                text_contains, text_eq,
+            },
+        };
 
         fn json_none_or_list(value: Option<&JsonValue>) -> u16 {
-            let score = if let Some(value) = value {
+            if let Some(value) = value {
                 match value {
                     JsonValue::List(_) => 1,
                     _ => 0,
                 }
             } else {
                 1
-            };
-
-            score
+            }
         }
 
         #[test]
@@ -756,5 +2022,256 @@ mod tests {
             assert_eq!(1, matcher.score(Some(&JsonValue::List(vec![]))));
             assert_eq!(0, matcher.score(Some(&JsonValue::Str("text!".into()))));
         }
+
+        #[test]
+        fn should_json_value_display_as_json() {
+            let value = JsonValue::try_from(r#"{"b":1,"a":[true,null,"x"]}"#).unwrap();
+
+            assert_eq!(r#"{"a":[true,null,"x"],"b":1}"#, value.to_string());
+            assert_eq!("Int(1)", format!("{:?}", JsonValue::from(1)));
+        }
+
+        #[test]
+        fn should_json_value_display_out_of_range_int_as_float() {
+            let value = JsonValue::Int(u64::MAX as i128 + 1);
+
+            assert_eq!("1.8446744073709552e+19", value.to_string());
+            assert_eq!(
+                "-1.8446744073709552e+19",
+                JsonValue::Int(-(u64::MAX as i128) - 1).to_string()
+            );
+        }
+
+        #[test]
+        fn should_reject_invalid_json_with_typed_error() {
+            assert!(matches!(
+                JsonValue::try_from(r#"{"name":"#),
+                Err(AnymockError::InvalidJson(_))
+            ));
+            assert!(matches!(
+                JsonMatcher::try_from("[1, 2"),
+                Err(AnymockError::InvalidJson(_))
+            ));
+        }
+
+        #[test]
+        fn should_matchers_debug_describe_rule() {
+            assert_eq!(r#"Eq("John")"#, format!("{:?}", text_eq("John")));
+            assert_eq!("Fn(<fn>)", format!("{:?}", json_fn(json_none_or_list)));
+            assert_eq!(
+                "ArrayContains(Str(Contains(\"a\")))",
+                format!("{:?}", json_array_contains(text_contains("a")))
+            );
+        }
+
+        #[test]
+        fn should_json_value_convert_from_rust_types() {
+            assert!(matches!(JsonValue::from(true), JsonValue::Bool(true)));
+            assert!(matches!(JsonValue::from(42), JsonValue::Int(42)));
+            assert!(matches!(JsonValue::from(-7i64), JsonValue::Int(-7)));
+            assert!(
+                matches!(JsonValue::from(u64::MAX), JsonValue::Int(v) if v == i128::from(u64::MAX))
+            );
+            assert!(matches!(JsonValue::from(2.5), JsonValue::Float(v) if v == 2.5));
+            assert!(
+                matches!(JsonValue::from("John".to_string()), JsonValue::Str(v) if v == "John")
+            );
+            assert!(matches!(
+                JsonValue::from(vec![1, 2, 3]).get_path("2"),
+                Some(JsonValue::Int(3))
+            ));
+        }
+
+        #[test]
+        fn should_json_value_keep_large_integers() {
+            let max_u64 = JsonValue::try_from(u64::MAX.to_string().as_str()).unwrap();
+            assert!(matches!(max_u64, JsonValue::Int(v) if v == i128::from(u64::MAX)));
+
+            let huge = JsonValue::try_from("123456789012345678901234567890").unwrap();
+            assert!(json_number_gt(1e29).score(Some(&huge)) > 0);
+        }
+
+        #[test]
+        fn should_json_number_match_int_and_float_values() {
+            let int = JsonValue::try_from("30").unwrap();
+            let float = JsonValue::try_from("30.0").unwrap();
+
+            assert!(json_number_eq(30.0).score(Some(&int)) > 0);
+            assert!(json_number_eq(30).score(Some(&float)) > 0);
+            assert!(json_number_gt(29.5).score(Some(&int)) > 0);
+            assert!(json_number_lt(30.5).score(Some(&float)) > 0);
+            assert_eq!(0, json_number_gt(30).score(Some(&int)));
+            assert_eq!(
+                0,
+                json_number_eq(30).score(Some(&JsonValue::Str("30".into())))
+            );
+
+            // Float matchers stay strict on the value type.
+            assert_eq!(0, JsonMatcher::from(float_eq(30.0)).score(Some(&int)));
+        }
+
+        #[test]
+        fn should_json_optional_returns_expected_scores() {
+            let matcher = JsonMatcher::Object(HashMap::from([
+                ("name".to_string(), JsonMatcher::from(text_eq("John"))),
+                ("nickname".to_string(), json_optional(text_contains("Jo"))),
+            ]));
+
+            let without_nickname = JsonValue::try_from(r#"{"name":"John"}"#).unwrap();
+            let with_nickname =
+                JsonValue::try_from(r#"{"name":"John","nickname":"Johnny"}"#).unwrap();
+            let with_other_nickname =
+                JsonValue::try_from(r#"{"name":"John","nickname":"Bob"}"#).unwrap();
+
+            assert!(matcher.score(Some(&without_nickname)) > 0);
+            assert!(matcher.score(Some(&with_nickname)) > matcher.score(Some(&without_nickname)));
+            assert_eq!(0, matcher.score(Some(&with_other_nickname)));
+        }
+
+        #[test]
+        fn should_json_combinators_returns_expected_scores() {
+            let matcher = |inner: JsonMatcher| {
+                JsonMatcher::Object(HashMap::from([("status".to_string(), inner)]))
+            };
+            let active = JsonValue::try_from(r#"{"status":"active"}"#).unwrap();
+            let paused = JsonValue::try_from(r#"{"status":"paused"}"#).unwrap();
+            let closed = JsonValue::try_from(r#"{"status":"closed"}"#).unwrap();
+            let missing = JsonValue::try_from(r#"{"id":1}"#).unwrap();
+
+            let open = matcher(json_any_of([text_eq("active"), text_eq("paused")]));
+            assert!(open.score(Some(&active)) > 0);
+            assert!(open.score(Some(&paused)) > 0);
+            assert_eq!(0, open.score(Some(&closed)));
+
+            let not_closed = matcher(json_not(text_eq("closed")));
+            assert_eq!(1, not_closed.score(Some(&active)));
+            assert_eq!(1, not_closed.score(Some(&missing)));
+            assert_eq!(0, not_closed.score(Some(&closed)));
+
+            let nested = json_all_of([
+                json_object_len(int_eq(1)),
+                json_not(matcher(json_any_of([text_eq("closed"), text_eq("paused")]))),
+            ]);
+            assert_eq!(
+                json_object_len(int_eq(1)).score(Some(&active)) + 1,
+                nested.score(Some(&active))
+            );
+            assert_eq!(0, nested.score(Some(&paused)));
+            assert_eq!(0, nested.score(Some(&closed)));
+        }
+
+        #[test]
+        fn should_json_max_score_bound_scores() {
+            let matcher = JsonMatcher::Object(HashMap::from([
+                ("name".to_string(), JsonMatcher::from(text_eq("John"))),
+                ("age".to_string(), json_number_gt(18)),
+                ("nickname".to_string(), json_optional(text_contains("Jo"))),
+                ("tags".to_string(), json_array_contains(text_eq("a"))),
+                ("status".to_string(), json_not(text_eq("closed"))),
+            ]));
+            let value = JsonValue::try_from(
+                r#"{"name":"John","age":30,"nickname":"Johnny","tags":["a"],"status":"open"}"#,
+            )
+            .unwrap();
+            let without_nickname =
+                JsonValue::try_from(r#"{"name":"John","age":30,"tags":["a"]}"#).unwrap();
+
+            assert_eq!(matcher.max_score(), matcher.score(Some(&value)));
+            assert!(matcher.max_score() > matcher.score(Some(&without_nickname)));
+        }
+
+        #[test]
+        fn should_json_array_contains_returns_expected_scores() {
+            let value = JsonValue::try_from(r#"{"tags":["a","b","c"]}"#).unwrap();
+            let matcher = |inner: JsonMatcher| {
+                JsonMatcher::Object(HashMap::from([("tags".to_string(), inner)]))
+            };
+
+            assert!(matcher(json_array_contains(text_eq("b"))).score(Some(&value)) > 0);
+            assert_eq!(
+                0,
+                matcher(json_array_contains(text_eq("d"))).score(Some(&value))
+            );
+            assert_eq!(
+                0,
+                json_array_contains(text_eq("b")).score(Some(&JsonValue::List(vec![])))
+            );
+        }
+
+        #[test]
+        fn should_json_object_len_returns_expected_scores() {
+            let value = JsonValue::try_from(r#"{"a":1,"b":2,"c":3}"#).unwrap();
+
+            assert!(json_object_len(int_gt(2)).score(Some(&value)) > 0);
+            assert!(json_object_len(int_eq(3)).score(Some(&value)) > 0);
+            assert_eq!(0, json_object_len(int_gt(3)).score(Some(&value)));
+            assert!(json_object_len(int_eq(0)).score(Some(&JsonValue::Object(HashMap::new()))) > 0);
+            assert_eq!(
+                0,
+                json_object_len(int_eq(3)).score(Some(&strs(&["a", "b", "c"])))
+            );
+            assert_eq!(0, json_object_len(int_eq(0)).score(None));
+        }
+
+        #[test]
+        fn should_json_list_len_returns_expected_scores() {
+            let value = strs(&["a", "b"]);
+
+            assert!(json_list_len(int_eq(2)).score(Some(&value)) > 0);
+            assert!(json_list_len(int_lt(3)).score(Some(&value)) > 0);
+            assert_eq!(0, json_list_len(int_gt(2)).score(Some(&value)));
+            assert!(json_list_len(int_eq(0)).score(Some(&JsonValue::List(vec![]))) > 0);
+            assert_eq!(
+                0,
+                json_list_len(int_eq(1)).score(Some(&JsonValue::Str("a".into())))
+            );
+            assert_eq!(
+                0,
+                json_list_len(int_eq(1)).score(Some(&JsonValue::Object(HashMap::from([(
+                    "a".to_string(),
+                    JsonValue::Null
+                )]))))
+            );
+        }
+
+        fn strs(values: &[&str]) -> JsonValue {
+            JsonValue::List(
+                values
+                    .iter()
+                    .map(|v| JsonValue::Str(v.to_string()))
+                    .collect(),
+            )
+        }
+
+        #[test]
+        fn should_json_list_any_order_returns_expected_scores() {
+            let matcher =
+                JsonMatcher::ListAnyOrder(vec![text_eq("rust").into(), text_eq("dev").into()]);
+
+            assert!(matcher.score(Some(&strs(&["dev", "rust"]))) > 0);
+            assert!(matcher.score(Some(&strs(&["json", "rust", "dev"]))) > 0);
+            assert_eq!(0, matcher.score(Some(&strs(&["dev", "json"]))));
+            assert_eq!(0, matcher.score(Some(&strs(&["rust"]))));
+        }
+
+        #[test]
+        fn should_json_list_any_order_handles_duplicates() {
+            let matcher =
+                JsonMatcher::ListAnyOrder(vec![text_eq("rust").into(), text_eq("rust").into()]);
+
+            assert!(matcher.score(Some(&strs(&["rust", "dev", "rust"]))) > 0);
+            assert_eq!(0, matcher.score(Some(&strs(&["rust", "dev"]))));
+        }
+
+        #[test]
+        fn should_json_list_any_order_reassigns_overlapping_matchers() {
+            let matcher =
+                JsonMatcher::ListAnyOrder(vec![text_contains("r").into(), text_eq("dev").into()]);
+            assert!(matcher.score(Some(&strs(&["dev", "rust"]))) > 0);
+
+            let matcher = JsonMatcher::ListAnyOrder(vec![text_any().into(), text_eq("dev").into()]);
+            assert!(matcher.score(Some(&strs(&["dev", "rust"]))) > 0);
+            assert!(matcher.score(Some(&strs(&["rust", "dev"]))) > 0);
+        }
     }
 }
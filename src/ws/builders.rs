@@ -1,13 +1,227 @@
-use std::{collections::HashMap, marker::PhantomData, time::Duration};
+use std::{
+    collections::HashMap, fmt, fs, io, marker::PhantomData, path::PathBuf, sync::OnceLock,
+    time::Duration,
+};
 
-use rand::distr::{Alphanumeric, SampleString};
+use tungstenite::http::{HeaderMap, HeaderName, HeaderValue};
 
+#[cfg(feature = "json-schema")]
+use crate::matchers::{SchemaError, json_schema};
 use crate::{
-    json::JsonValue,
-    matchers::{Body, BodyMatcher, JsonMatcher, TextMatcher},
-    ws::stubs::{Delay, RequestMatcher, Stub},
+    json::{Codec, JsonValue},
+    matchers::{
+        BinaryMatcher, Body, BodyMatcher, BoolMatcher, FloatMatcher, HexError, IntMatcher,
+        JsonMatcher, MatcherFn, TextMatcher, cached_regex, hex_to_bytes, text_any, text_none,
+    },
+    ws::{
+        PATH_HEADER, SUBPROTOCOL_HEADER,
+        stubs::{Control, Delay, HeadersFn, RequestMatcher, Response, Stub},
+    },
 };
 
+/// Configuration error found while building a stub.
+///
+/// Builder methods never panic on invalid input. Errors are collected inside the
+/// resulting [`Stub`] and returned by `ServerHandle::register`, which refuses the stub.
+#[derive(Debug)]
+pub enum BuildError {
+    InvalidHeaderRegex {
+        key: String,
+        source: regex::Error,
+    },
+    InvalidTextRegex {
+        source: regex::Error,
+    },
+    LoopingWithoutDelay,
+    InvalidResponseHeader {
+        name: String,
+    },
+    InvalidHex {
+        source: HexError,
+    },
+    UnreadableFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    InvalidJson {
+        source: serde_json::Error,
+    },
+    #[cfg(feature = "json-schema")]
+    InvalidJsonSchema {
+        source: SchemaError,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InvalidHeaderRegex { key, source } => {
+                write!(f, "invalid regex for header '{key}': {source}")
+            }
+            BuildError::InvalidTextRegex { source } => {
+                write!(f, "invalid regex for text body: {source}")
+            }
+            BuildError::LoopingWithoutDelay => {
+                write!(f, "looping periodical stubs need a delay greater than zero")
+            }
+            BuildError::InvalidResponseHeader { name } => {
+                write!(f, "invalid name or value for response header '{name}'")
+            }
+            BuildError::InvalidHex { source } => write!(f, "invalid hex payload: {source}"),
+            BuildError::UnreadableFile { path, source } => {
+                write!(
+                    f,
+                    "cannot read response file '{}': {source}",
+                    path.display()
+                )
+            }
+            BuildError::InvalidJson { source } => write!(f, "invalid JSON payload: {source}"),
+            #[cfg(feature = "json-schema")]
+            BuildError::InvalidJsonSchema { source } => {
+                write!(f, "invalid JSON schema for body: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::InvalidHeaderRegex { source, .. }
+            | BuildError::InvalidTextRegex { source } => Some(source),
+            BuildError::InvalidHex { source } => Some(source),
+            BuildError::UnreadableFile { source, .. } => Some(source),
+            BuildError::InvalidJson { source } => Some(source),
+            #[cfg(feature = "json-schema")]
+            BuildError::InvalidJsonSchema { source } => Some(source),
+            BuildError::LoopingWithoutDelay | BuildError::InvalidResponseHeader { .. } => None,
+        }
+    }
+}
+
+/// Range rejected by `try_with_delay_interval_in`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DelayError {
+    /// The lower bound is above the upper one.
+    Inverted { lower: Duration, upper: Duration },
+    /// Both bounds are the same, which is a fixed delay.
+    ZeroWidth(Duration),
+}
+
+impl fmt::Display for DelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelayError::Inverted { lower, upper } => {
+                write!(
+                    f,
+                    "delay interval lower bound {lower:?} is above upper bound {upper:?}"
+                )
+            }
+            DelayError::ZeroWidth(delay) => {
+                write!(
+                    f,
+                    "delay interval bounds are both {delay:?}, use a fixed delay"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DelayError {}
+
+fn checked_interval(lower: Duration, upper: Duration) -> Result<Delay, DelayError> {
+    match lower.cmp(&upper) {
+        std::cmp::Ordering::Less => Ok(Delay::Interval(lower, upper)),
+        std::cmp::Ordering::Equal => Err(DelayError::ZeroWidth(lower)),
+        std::cmp::Ordering::Greater => Err(DelayError::Inverted { lower, upper }),
+    }
+}
+
+/// How a response template renders a placeholder that is not found in the request.
+#[derive(Default)]
+pub enum MissingPlaceholder {
+    /// Renders it as an empty string.
+    #[default]
+    Empty,
+    /// Closes the connection with an internal error frame instead of responding.
+    Error,
+}
+
+fn header_regex(
+    headers: &mut Option<HashMap<String, TextMatcher>>,
+    errors: &mut Vec<BuildError>,
+    key: String,
+    pattern: &str,
+) {
+    match cached_regex(pattern) {
+        Ok(regex) => {
+            headers
+                .get_or_insert_with(HashMap::new)
+                .insert(key, TextMatcher::Regex(regex));
+        }
+        Err(source) => errors.push(BuildError::InvalidHeaderRegex { key, source }),
+    }
+}
+
+// Path matchers report a broken pattern like text body matchers, not like header ones.
+fn path_regex(
+    headers: &mut Option<HashMap<String, TextMatcher>>,
+    errors: &mut Vec<BuildError>,
+    pattern: &str,
+) {
+    match cached_regex(pattern) {
+        Ok(regex) => {
+            headers
+                .get_or_insert_with(HashMap::new)
+                .insert(PATH_HEADER.to_string(), TextMatcher::Regex(regex));
+        }
+        Err(source) => errors.push(BuildError::InvalidTextRegex { source }),
+    }
+}
+
+// Regex for a path glob, matched segment by segment: `*` stands for any part of a single
+// segment and a `**` segment for any number of segments. Trailing slashes are optional.
+fn path_glob_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for segment in glob.split('/').filter(|segment| !segment.is_empty()) {
+        if segment == "**" {
+            pattern.push_str("(?:/[^/]+)*");
+        } else {
+            pattern.push('/');
+            let parts: Vec<String> = segment.split('*').map(regex::escape).collect();
+            pattern.push_str(&parts.join("[^/]*"));
+        }
+    }
+    pattern.push_str("/?$");
+    pattern
+}
+
+// Only checks the file can be opened, it is read when first sent.
+fn response_file(errors: &mut Vec<BuildError>, path: PathBuf) -> Body {
+    if let Err(source) = fs::File::open(&path) {
+        errors.push(BuildError::UnreadableFile {
+            path: path.clone(),
+            source,
+        });
+    }
+    Body::File(path)
+}
+
+// Checks `json` parses without building a value, the text is sent as written.
+fn json_text(errors: &mut Vec<BuildError>, json: &str) -> Body {
+    if let Err(source) = serde_json::from_str::<serde::de::IgnoredAny>(json) {
+        errors.push(BuildError::InvalidJson { source });
+    }
+    Body::PlainText(json.to_string())
+}
+
+fn hex_bytes(errors: &mut Vec<BuildError>, hex: &str) -> Vec<u8> {
+    hex_to_bytes(hex).unwrap_or_else(|source| {
+        errors.push(BuildError::InvalidHex { source });
+        Vec::new()
+    })
+}
+
 pub fn on_connect() -> OnConnectBuilder {
     OnConnectBuilder::default()
 }
@@ -15,6 +229,10 @@ pub fn on_connect() -> OnConnectBuilder {
 #[derive(Default)]
 pub struct OnConnectBuilder {
     headers: Option<HashMap<String, TextMatcher>>,
+    priority: i32,
+    headers_fn: Option<HeadersFn>,
+    response_headers: HeaderMap,
+    errors: Vec<BuildError>,
 }
 
 impl OnConnectBuilder {
@@ -30,6 +248,61 @@ impl OnConnectBuilder {
         self
     }
 
+    /// Matches when the header is sent, whatever its value.
+    pub fn with_header_present(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_any())
+    }
+
+    /// Matches when the header is not sent. A header sent with an empty value is present.
+    pub fn with_header_absent(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_none())
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Matches the subprotocol negotiated through `Server::with_subprotocols`.
+    pub fn with_subprotocol(self, matcher: TextMatcher) -> Self {
+        self.with_header(SUBPROTOCOL_HEADER, matcher)
+    }
+
+    /// Matches the `Origin` header browsers send with the handshake.
+    pub fn with_origin(self, matcher: TextMatcher) -> Self {
+        self.with_header("origin", matcher)
+    }
+
+    /// Matches the header against `pattern`. An invalid pattern is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn with_header_matching(mut self, key: impl Into<String>, pattern: &str) -> Self {
+        header_regex(&mut self.headers, &mut self.errors, key.into(), pattern);
+        self
+    }
+
+    /// Matches handshakes whose path fits `glob`, like `/ws/*/events`, where `*` matches
+    /// within a segment and `**` any number of segments. The server accepts such paths even
+    /// when they differ from `Server::path`.
+    pub fn with_path_glob(mut self, glob: &str) -> Self {
+        header_regex(
+            &mut self.headers,
+            &mut self.errors,
+            PATH_HEADER.to_string(),
+            &path_glob_regex(glob),
+        );
+        self
+    }
+
+    /// Matches handshakes whose path fits the regex `pattern`, like `^/tenants/\d+$`. The
+    /// server accepts such paths like with [`with_path_glob`](Self::with_path_glob). An
+    /// invalid pattern is reported as a [`BuildError`] when the stub is registered.
+    pub fn with_path_matching(mut self, pattern: &str) -> Self {
+        path_regex(&mut self.headers, &mut self.errors, pattern);
+        self
+    }
+
     pub fn returning_text(self, text: impl Into<String>) -> Stub {
         self.build(Body::PlainText(text.into()))
     }
@@ -42,10 +315,96 @@ impl OnConnectBuilder {
         self.build(Body::Binary(buff.into()))
     }
 
+    /// Sends `json` as a text message exactly as written, keeping its formatting. Invalid
+    /// JSON is reported as a [`BuildError`] when the stub is registered.
+    pub fn returning_json_str(mut self, json: &str) -> Stub {
+        let body = json_text(&mut self.errors, json);
+        self.build(body)
+    }
+
+    /// Sends the bytes of a hex string like `"de ad:be ef"`. Invalid input is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn returning_binary_hex(mut self, hex: &str) -> Stub {
+        let buff = hex_bytes(&mut self.errors, hex);
+        self.returning_binary(buff)
+    }
+
+    /// Sends the contents of the file at `path`, as text when they are UTF-8 and as binary
+    /// otherwise. The file is read when first sent and kept in memory afterwards. A file
+    /// that cannot be opened is reported as a [`BuildError`] when the stub is registered.
+    pub fn returning_file(mut self, path: impl Into<PathBuf>) -> Stub {
+        let body = response_file(&mut self.errors, path.into());
+        self.build(body)
+    }
+
+    /// Serializes `value` with `codec`. Binary codecs are sent as binary frames.
+    pub fn returning_encoded(self, value: impl Into<JsonValue>, codec: Codec) -> Stub {
+        self.build(Body::Encoded {
+            value: value.into(),
+            codec,
+        })
+    }
+
+    /// Scores all the connection headers at once, for logic spanning several of them. The
+    /// score is added to the header matchers one, and 0 discards the stub.
+    pub fn with_headers_fn(
+        mut self,
+        matcher: impl Fn(&HashMap<String, String>) -> u16 + Send + Sync + 'static,
+    ) -> Self {
+        self.headers_fn = Some(Box::new(matcher));
+        self
+    }
+
+    /// Adds a header to the handshake response sent when this stub wins. Repeating a name
+    /// sends the header once per value, e.g. for several `Set-Cookie`.
+    pub fn with_response_header(mut self, name: &str, value: &str) -> Self {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                self.response_headers.append(name, value);
+            }
+            _ => self.errors.push(BuildError::InvalidResponseHeader {
+                name: name.to_string(),
+            }),
+        }
+        self
+    }
+
+    /// Sends all of `responses` in order once connected, e.g. a banner followed by a snapshot.
+    pub fn returning_many(self, responses: impl Into<Vec<Body>>) -> Stub {
+        self.returning_many_delayed(
+            responses
+                .into()
+                .into_iter()
+                .map(|body| (Duration::ZERO, body))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Like [`returning_many`](Self::returning_many), sending each body at its offset from the
+    /// handshake. Bodies with the same offset keep their order.
+    pub fn returning_many_delayed(self, responses: impl Into<Vec<(Duration, Body)>>) -> Stub {
+        self.build_response(Response::Many(responses.into()))
+    }
+
+    pub fn rejecting(self, status: u16) -> Stub {
+        self.build_response(Response::Reject(status))
+    }
+
     fn build(self, body: Body) -> Stub {
+        self.build_response(Response::Body(body))
+    }
+
+    fn build_response(self, response: Response) -> Stub {
         Stub::Connect {
             headers: self.headers,
-            response: body,
+            priority: self.priority,
+            headers_fn: self.headers_fn,
+            response_headers: self.response_headers,
+            response,
+            errors: self.errors,
         }
     }
 }
@@ -59,8 +418,15 @@ pub fn on_message() -> OnMessageBuilder {
 #[derive(Default)]
 pub struct OnMessageBuilder {
     headers: Option<HashMap<String, TextMatcher>>,
+    priority: i32,
+    errors: Vec<BuildError>,
     delay: Option<Delay>,
+    throttle: Option<u32>,
     payload: Option<BodyMatcher>,
+    state: Option<HashMap<String, JsonMatcher>>,
+    state_updates: HashMap<String, JsonValue>,
+    once: bool,
+    monotonic: Option<String>,
 }
 
 impl OnMessageBuilder {
@@ -76,6 +442,46 @@ impl OnMessageBuilder {
         self
     }
 
+    /// Matches when the header is sent, whatever its value.
+    pub fn with_header_present(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_any())
+    }
+
+    /// Matches when the header is not sent. A header sent with an empty value is present.
+    pub fn with_header_absent(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_none())
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Matches the subprotocol negotiated through `Server::with_subprotocols`.
+    pub fn with_subprotocol(self, matcher: TextMatcher) -> Self {
+        self.with_header(SUBPROTOCOL_HEADER, matcher)
+    }
+
+    /// Matches the header against `pattern`. An invalid pattern is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn with_header_matching(mut self, key: impl Into<String>, pattern: &str) -> Self {
+        header_regex(&mut self.headers, &mut self.errors, key.into(), pattern);
+        self
+    }
+
+    /// Like [`with_delay_interval_in`](Self::with_delay_interval_in), but inverted or
+    /// zero-width ranges are an error instead of being fixed up.
+    pub fn try_with_delay_interval_in(
+        mut self,
+        lower: Duration,
+        upper: Duration,
+    ) -> Result<Self, DelayError> {
+        self.delay = Some(checked_interval(lower, upper)?);
+        Ok(self)
+    }
+
     pub fn with_delay_interval_in(mut self, lower: Duration, upper: Duration) -> Self {
         match (lower, upper) {
             (lower, upper) if lower == upper => self.delay = Some(Delay::Fixed(lower)),
@@ -91,11 +497,103 @@ impl OnMessageBuilder {
         self
     }
 
+    /// Delays each response by `base` plus or minus a random amount up to `plus_minus`,
+    /// never below zero.
+    pub fn with_jitter(mut self, base: Duration, plus_minus: Duration) -> Self {
+        self.delay = Some(Delay::Jitter(base, plus_minus));
+        self
+    }
+
+    /// Sends the response in fragments paced to `bytes_per_sec`. Pacing starts once the
+    /// configured delay has elapsed, so both add up.
+    pub fn with_throttle(mut self, bytes_per_sec: u32) -> Self {
+        self.throttle = Some(bytes_per_sec);
+        self
+    }
+
+    /// Only matches while the value stored under `key` in the connection session satisfies
+    /// `matcher`.
+    pub fn requiring_state(
+        mut self,
+        key: impl Into<String>,
+        matcher: impl Into<JsonMatcher>,
+    ) -> Self {
+        self.state
+            .get_or_insert_with(HashMap::new)
+            .insert(key.into(), matcher.into());
+        self
+    }
+
+    /// Stores `value` under `key` in the connection session each time the stub answers.
+    pub fn setting_state(mut self, key: impl Into<String>, value: impl Into<JsonValue>) -> Self {
+        self.state_updates.insert(key.into(), value.into());
+        self
+    }
+
+    /// Answers the first matching message of each connection only, leaving the following
+    /// ones to other stubs.
+    pub fn once(mut self) -> Self {
+        self.once = true;
+        self
+    }
+
+    /// Matches JSON messages whose number at `path`, like `$.seq` or `meta.seq`, is above the
+    /// one of the last message the connection got answered by a stub watching that path.
+    /// Repeated or lower numbers, which mean a replay, are left to other stubs.
+    pub fn with_monotonic_json_field(mut self, path: &str) -> Self {
+        self.monotonic = Some(path.strip_prefix("$.").unwrap_or(path).to_string());
+        self
+    }
+
     pub fn with_text_like(mut self, body: impl Into<TextMatcher>) -> Self {
         self.payload = Some(BodyMatcher::PlainText(body.into()));
         self
     }
 
+    /// Matches text messages against `pattern`. An invalid pattern is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn with_text_matching(mut self, pattern: &str) -> Self {
+        match cached_regex(pattern) {
+            Ok(regex) => self.payload = Some(BodyMatcher::PlainText(TextMatcher::Regex(regex))),
+            Err(source) => self.errors.push(BuildError::InvalidTextRegex { source }),
+        }
+        self
+    }
+
+    /// Matches empty text or binary messages only.
+    pub fn with_empty_body(mut self) -> Self {
+        self.payload = Some(BodyMatcher::Empty);
+        self
+    }
+
+    /// Matches messages carrying any data, but not empty ones.
+    pub fn with_any_body(mut self) -> Self {
+        self.payload = Some(BodyMatcher::NonEmpty);
+        self
+    }
+
+    /// Scores messages with `matcher`, which gets the decoded body of any text or binary
+    /// message and returns 0 when it does not match.
+    pub fn with_body_fn(mut self, matcher: impl MatcherFn<Body> + 'static) -> Self {
+        self.payload = Some(BodyMatcher::Custom(Box::new(matcher)));
+        self
+    }
+
+    pub fn with_int_like(mut self, matcher: impl Into<IntMatcher>) -> Self {
+        self.payload = Some(BodyMatcher::Int(matcher.into()));
+        self
+    }
+
+    pub fn with_float_like(mut self, matcher: impl Into<FloatMatcher>) -> Self {
+        self.payload = Some(BodyMatcher::Float(matcher.into()));
+        self
+    }
+
+    pub fn with_bool_like(mut self, matcher: impl Into<BoolMatcher>) -> Self {
+        self.payload = Some(BodyMatcher::Bool(matcher.into()));
+        self
+    }
+
     pub fn with_json_body_eq(mut self, body: impl Into<JsonValue>) -> Self {
         self.payload = Some(BodyMatcher::Json(JsonMatcher::from(body.into())));
         self
@@ -106,6 +604,29 @@ impl OnMessageBuilder {
         self
     }
 
+    /// Matches JSON messages valid against `schema`, a JSON Schema written as JSON. A schema
+    /// that does not compile is reported as a [`BuildError`] when the stub is registered.
+    #[cfg(feature = "json-schema")]
+    pub fn with_json_schema(mut self, schema: &str) -> Self {
+        match json_schema(schema) {
+            Ok(matcher) => self.payload = Some(matcher),
+            Err(source) => self.errors.push(BuildError::InvalidJsonSchema { source }),
+        }
+        self
+    }
+
+    #[cfg(feature = "cbor")]
+    pub fn with_cbor_body_like(mut self, matcher: impl Into<JsonMatcher>) -> Self {
+        self.payload = Some(BodyMatcher::Cbor(matcher.into()));
+        self
+    }
+
+    #[cfg(feature = "msgpack")]
+    pub fn with_msgpack_body_like(mut self, matcher: impl Into<JsonMatcher>) -> Self {
+        self.payload = Some(BodyMatcher::MsgPack(matcher.into()));
+        self
+    }
+
     pub fn returning_text(self, text: impl Into<String>) -> Stub {
         self.build(Body::PlainText(text.into()))
     }
@@ -118,16 +639,450 @@ impl OnMessageBuilder {
         self.build(Body::Binary(buff.into()))
     }
 
+    /// Sends `json` as a text message exactly as written, keeping its formatting. Invalid
+    /// JSON is reported as a [`BuildError`] when the stub is registered.
+    pub fn returning_json_str(mut self, json: &str) -> Stub {
+        let body = json_text(&mut self.errors, json);
+        self.build(body)
+    }
+
+    /// Sends the bytes of a hex string like `"de ad:be ef"`. Invalid input is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn returning_binary_hex(mut self, hex: &str) -> Stub {
+        let buff = hex_bytes(&mut self.errors, hex);
+        self.returning_binary(buff)
+    }
+
+    /// Sends the contents of the file at `path`, as text when they are UTF-8 and as binary
+    /// otherwise. The file is read when first sent and kept in memory afterwards. A file
+    /// that cannot be opened is reported as a [`BuildError`] when the stub is registered.
+    pub fn returning_file(mut self, path: impl Into<PathBuf>) -> Stub {
+        let body = response_file(&mut self.errors, path.into());
+        self.build(body)
+    }
+
+    /// Serializes `value` with `codec`. Binary codecs are sent as binary frames.
+    pub fn returning_encoded(self, value: impl Into<JsonValue>, codec: Codec) -> Stub {
+        self.build(Body::Encoded {
+            value: value.into(),
+            codec,
+        })
+    }
+
+    /// Responds to the Nth matching message with the Nth body. Once all of them are sent the
+    /// stub starts over when `cycle` is set, otherwise it stops matching.
+    ///
+    /// The position is tracked per connection, so each client receives the whole sequence.
+    pub fn returning_sequence(self, responses: impl Into<Vec<Body>>, cycle: bool) -> Stub {
+        self.build_response(Response::Sequence {
+            id: String::new(),
+            responses: responses.into(),
+            cycle,
+        })
+    }
+
+    /// Responds with the matched message itself, byte for byte and as text or binary like it
+    /// was received.
+    pub fn echoing(self) -> Stub {
+        self.build_response(Response::Echo)
+    }
+
+    /// Responds to each match with all of `responses`, sent in order.
+    pub fn returning_many(self, responses: impl Into<Vec<Body>>) -> Stub {
+        self.returning_many_delayed(
+            responses
+                .into()
+                .into_iter()
+                .map(|body| (Duration::ZERO, body))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Like [`returning_many`](Self::returning_many), sending each body at its offset from the
+    /// configured delay. Bodies with the same offset keep their order.
+    pub fn returning_many_delayed(self, responses: impl Into<Vec<(Duration, Body)>>) -> Stub {
+        self.build_response(Response::Many(responses.into()))
+    }
+
+    /// Responds with a text built from `template`, where each `{{path}}` is replaced by the
+    /// value at that dot path of the incoming JSON message. Missing values render as empty.
+    pub fn returning_template(self, template: impl Into<String>) -> Stub {
+        self.returning_template_with(template, MissingPlaceholder::default())
+    }
+
+    pub fn returning_template_with(
+        self,
+        template: impl Into<String>,
+        missing: MissingPlaceholder,
+    ) -> Stub {
+        self.build_response(Response::Template(template.into(), missing))
+    }
+
+    #[cfg(feature = "msgpack")]
+    pub fn returning_msgpack(self, json: impl Into<JsonValue>) -> Stub {
+        self.returning_encoded(json, Codec::MessagePack)
+    }
+
+    pub fn returning_close(self, code: u16, reason: impl Into<String>) -> Stub {
+        self.build_response(Response::Close(code, reason.into()))
+    }
+
+    pub fn returning_disconnect(self) -> Stub {
+        self.build_response(Response::Disconnect)
+    }
+
     fn build(self, body: Body) -> Stub {
+        self.build_response(Response::Body(body))
+    }
+
+    fn build_response(self, response: Response) -> Stub {
         Stub::Message {
+            control: None,
             request: RequestMatcher {
                 headers: self.headers,
                 payload: self.payload,
+                state: self.state,
+                monotonic: self.monotonic,
             },
             delay: self
                 .delay
                 .unwrap_or_else(|| Delay::Fixed(Duration::from_millis(0))),
-            response: body,
+            throttle: self.throttle,
+            response,
+            state_updates: self.state_updates,
+            once: self.once.then(String::new),
+            priority: self.priority,
+            errors: self.errors,
+        }
+    }
+}
+
+// Conversation
+
+/// Starts a lock-step exchange where each expected message is answered with its reply, in
+/// order, on every connection.
+pub fn conversation() -> ConversationBuilder {
+    ConversationBuilder::default()
+}
+
+#[derive(Default)]
+pub struct ConversationBuilder {
+    steps: Vec<(BodyMatcher, Body)>,
+    strict: bool,
+    priority: i32,
+}
+
+/// Step waiting for the reply to its expected message.
+pub struct ConversationStep {
+    builder: ConversationBuilder,
+    expected: BodyMatcher,
+}
+
+impl ConversationBuilder {
+    /// Adds a step matching the next message of the conversation.
+    pub fn expect(self, matcher: impl Into<BodyMatcher>) -> ConversationStep {
+        ConversationStep {
+            builder: self,
+            expected: matcher.into(),
+        }
+    }
+
+    /// Closes the connection with code 1008 when a message does not match the current step
+    /// and no other stub answers it. By default such messages are left to the other stubs.
+    pub fn failing_on_unexpected(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn build(self) -> Stub {
+        Stub::Conversation {
+            id: String::new(),
+            steps: self.steps,
+            strict: self.strict,
+            priority: self.priority,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl ConversationStep {
+    pub fn reply_text(self, text: impl Into<String>) -> ConversationBuilder {
+        self.reply(Body::PlainText(text.into()))
+    }
+
+    pub fn reply_json(self, json: impl Into<JsonValue>) -> ConversationBuilder {
+        self.reply(Body::Json(json.into()))
+    }
+
+    pub fn reply_binary(self, binary: impl Into<Vec<u8>>) -> ConversationBuilder {
+        self.reply(Body::Binary(binary.into()))
+    }
+
+    pub fn reply(self, body: Body) -> ConversationBuilder {
+        let mut builder = self.builder;
+        builder.steps.push((self.expected, body));
+        builder
+    }
+}
+
+// Ping / Pong
+
+pub fn on_ping() -> OnControlBuilder {
+    OnControlBuilder::new(Control::Ping)
+}
+
+pub fn on_pong() -> OnControlBuilder {
+    OnControlBuilder::new(Control::Pong)
+}
+
+/// Builds stubs answering the ping or pong control frames sent by the client. The frame
+/// payload is matched as bytes.
+pub struct OnControlBuilder {
+    control: Control,
+    headers: Option<HashMap<String, TextMatcher>>,
+    priority: i32,
+    errors: Vec<BuildError>,
+    delay: Option<Delay>,
+    payload: Option<BinaryMatcher>,
+}
+
+impl OnControlBuilder {
+    fn new(control: Control) -> Self {
+        OnControlBuilder {
+            control,
+            headers: None,
+            priority: 0,
+            errors: Vec::new(),
+            delay: None,
+            payload: None,
+        }
+    }
+
+    pub fn with_header(mut self, key: impl Into<String>, matcher: TextMatcher) -> Self {
+        if let Some(headers) = self.headers.as_mut() {
+            headers.insert(key.into(), matcher);
+        } else {
+            let mut headers = HashMap::new();
+            headers.insert(key.into(), matcher);
+            self.headers = Some(headers);
+        }
+
+        self
+    }
+
+    /// Matches when the header is sent, whatever its value.
+    pub fn with_header_present(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_any())
+    }
+
+    /// Matches when the header is not sent. A header sent with an empty value is present.
+    pub fn with_header_absent(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_none())
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Matches the header against `pattern`. An invalid pattern is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn with_header_matching(mut self, key: impl Into<String>, pattern: &str) -> Self {
+        header_regex(&mut self.headers, &mut self.errors, key.into(), pattern);
+        self
+    }
+
+    /// Like [`with_delay_interval_in`](Self::with_delay_interval_in), but inverted or
+    /// zero-width ranges are an error instead of being fixed up.
+    pub fn try_with_delay_interval_in(
+        mut self,
+        lower: Duration,
+        upper: Duration,
+    ) -> Result<Self, DelayError> {
+        self.delay = Some(checked_interval(lower, upper)?);
+        Ok(self)
+    }
+
+    pub fn with_delay_interval_in(mut self, lower: Duration, upper: Duration) -> Self {
+        match (lower, upper) {
+            (lower, upper) if lower == upper => self.delay = Some(Delay::Fixed(lower)),
+            (lower, upper) if lower > upper => self.delay = Some(Delay::Interval(upper, lower)),
+            (lower, upper) => self.delay = Some(Delay::Interval(lower, upper)),
+        }
+
+        self
+    }
+
+    pub fn with_fixed_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(Delay::Fixed(delay));
+        self
+    }
+
+    /// Delays each response by `base` plus or minus a random amount up to `plus_minus`,
+    /// never below zero.
+    pub fn with_jitter(mut self, base: Duration, plus_minus: Duration) -> Self {
+        self.delay = Some(Delay::Jitter(base, plus_minus));
+        self
+    }
+
+    pub fn with_payload_like(mut self, matcher: impl Into<BinaryMatcher>) -> Self {
+        self.payload = Some(matcher.into());
+        self
+    }
+
+    pub fn returning_text(self, text: impl Into<String>) -> Stub {
+        self.build(Body::PlainText(text.into()))
+    }
+
+    pub fn returning_json(self, json: impl Into<JsonValue>) -> Stub {
+        self.build(Body::Json(json.into()))
+    }
+
+    pub fn returning_binary(self, buff: impl Into<Vec<u8>>) -> Stub {
+        self.build(Body::Binary(buff.into()))
+    }
+
+    /// Sends `json` as a text message exactly as written, keeping its formatting. Invalid
+    /// JSON is reported as a [`BuildError`] when the stub is registered.
+    pub fn returning_json_str(mut self, json: &str) -> Stub {
+        let body = json_text(&mut self.errors, json);
+        self.build(body)
+    }
+
+    /// Sends the bytes of a hex string like `"de ad:be ef"`. Invalid input is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn returning_binary_hex(mut self, hex: &str) -> Stub {
+        let buff = hex_bytes(&mut self.errors, hex);
+        self.returning_binary(buff)
+    }
+
+    /// Serializes `value` with `codec`. Binary codecs are sent as binary frames.
+    pub fn returning_encoded(self, value: impl Into<JsonValue>, codec: Codec) -> Stub {
+        self.build(Body::Encoded {
+            value: value.into(),
+            codec,
+        })
+    }
+
+    pub fn returning_close(self, code: u16, reason: impl Into<String>) -> Stub {
+        self.build_response(Response::Close(code, reason.into()))
+    }
+
+    pub fn returning_disconnect(self) -> Stub {
+        self.build_response(Response::Disconnect)
+    }
+
+    fn build(self, body: Body) -> Stub {
+        self.build_response(Response::Body(body))
+    }
+
+    fn build_response(self, response: Response) -> Stub {
+        Stub::Message {
+            control: Some(self.control),
+            request: RequestMatcher {
+                headers: self.headers,
+                payload: self.payload.map(BodyMatcher::Binary),
+                state: None,
+                monotonic: None,
+            },
+            delay: self
+                .delay
+                .unwrap_or_else(|| Delay::Fixed(Duration::from_millis(0))),
+            throttle: None,
+            response,
+            state_updates: HashMap::new(),
+            once: None,
+            priority: self.priority,
+            errors: self.errors,
+        }
+    }
+}
+
+// Close
+
+/// Matches the close frame sent by the client. The close is acknowledged automatically, so
+/// these stubs send nothing and are meant to be checked with `ServerHandle::stub_hits`.
+pub fn on_close() -> OnCloseBuilder {
+    OnCloseBuilder::default()
+}
+
+#[derive(Default)]
+pub struct OnCloseBuilder {
+    headers: Option<HashMap<String, TextMatcher>>,
+    priority: i32,
+    errors: Vec<BuildError>,
+    fields: HashMap<String, JsonMatcher>,
+}
+
+impl OnCloseBuilder {
+    pub fn with_header(mut self, key: impl Into<String>, matcher: TextMatcher) -> Self {
+        if let Some(headers) = self.headers.as_mut() {
+            headers.insert(key.into(), matcher);
+        } else {
+            let mut headers = HashMap::new();
+            headers.insert(key.into(), matcher);
+            self.headers = Some(headers);
+        }
+
+        self
+    }
+
+    /// Matches when the header is sent, whatever its value.
+    pub fn with_header_present(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_any())
+    }
+
+    /// Matches when the header is not sent. A header sent with an empty value is present.
+    pub fn with_header_absent(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_none())
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Matches the close code, 1005 when the client sent none.
+    pub fn with_code_like(mut self, matcher: impl Into<IntMatcher>) -> Self {
+        self.fields
+            .insert("code".to_string(), JsonMatcher::Int(matcher.into()));
+        self
+    }
+
+    pub fn with_reason_like(mut self, matcher: impl Into<TextMatcher>) -> Self {
+        self.fields
+            .insert("reason".to_string(), JsonMatcher::Str(matcher.into()));
+        self
+    }
+
+    pub fn build(self) -> Stub {
+        Stub::Message {
+            control: Some(Control::Close),
+            request: RequestMatcher {
+                headers: self.headers,
+                payload: (!self.fields.is_empty())
+                    .then(|| BodyMatcher::Json(JsonMatcher::Object(self.fields))),
+                state: None,
+                monotonic: None,
+            },
+            delay: Delay::Fixed(Duration::ZERO),
+            throttle: None,
+            response: Response::Many(Vec::new()),
+            state_updates: HashMap::new(),
+            once: None,
+            priority: self.priority,
+            errors: self.errors,
         }
     }
 }
@@ -139,8 +1094,14 @@ pub fn on_periodical() -> OnPeriodicalBuilder<NeedsBody> {
         _phantom_data: PhantomData::<NeedsBody>,
         id: None,
         headers: None,
+        priority: 0,
         delay: None,
         responses: Vec::new(),
+        shared: false,
+        looping: false,
+        take: None,
+        duration: None,
+        errors: Vec::new(),
     }
 }
 
@@ -150,8 +1111,14 @@ pub struct Ready;
 pub struct OnPeriodicalBuilder<T> {
     id: Option<String>,
     headers: Option<HashMap<String, TextMatcher>>,
+    priority: i32,
     delay: Option<Delay>,
     responses: Vec<Body>,
+    shared: bool,
+    looping: bool,
+    take: Option<usize>,
+    duration: Option<Duration>,
+    errors: Vec<BuildError>,
     _phantom_data: PhantomData<T>,
 }
 
@@ -173,6 +1140,53 @@ impl<T> OnPeriodicalBuilder<T> {
         self
     }
 
+    /// Matches when the header is sent, whatever its value.
+    pub fn with_header_present(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_any())
+    }
+
+    /// Matches when the header is not sent. A header sent with an empty value is present.
+    pub fn with_header_absent(self, key: impl Into<String>) -> Self {
+        self.with_header(key, text_none())
+    }
+
+    /// Breaks ties with stubs of the same score, the highest priority wins. Stubs with the
+    /// same priority fall back to registration order.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Matches the subprotocol negotiated through `Server::with_subprotocols`.
+    pub fn with_subprotocol(self, matcher: TextMatcher) -> Self {
+        self.with_header(SUBPROTOCOL_HEADER, matcher)
+    }
+
+    /// Matches the header against `pattern`. An invalid pattern is reported as a
+    /// [`BuildError`] when the stub is registered.
+    pub fn with_header_matching(mut self, key: impl Into<String>, pattern: &str) -> Self {
+        header_regex(&mut self.headers, &mut self.errors, key.into(), pattern);
+        self
+    }
+
+    /// Only sends to connections whose handshake path fits the regex `pattern`. An invalid
+    /// pattern is reported as a [`BuildError`] when the stub is registered.
+    pub fn with_path_matching(mut self, pattern: &str) -> Self {
+        path_regex(&mut self.headers, &mut self.errors, pattern);
+        self
+    }
+
+    /// Like [`with_delay_interval_in`](Self::with_delay_interval_in), but inverted or
+    /// zero-width ranges are an error instead of being fixed up.
+    pub fn try_with_delay_interval_in(
+        mut self,
+        lower: Duration,
+        upper: Duration,
+    ) -> Result<Self, DelayError> {
+        self.delay = Some(checked_interval(lower, upper)?);
+        Ok(self)
+    }
+
     pub fn with_delay_interval_in(mut self, lower: Duration, upper: Duration) -> Self {
         match (lower, upper) {
             (lower, upper) if lower == upper => self.delay = Some(Delay::Fixed(lower)),
@@ -188,6 +1202,40 @@ impl<T> OnPeriodicalBuilder<T> {
         self
     }
 
+    /// Delays each response by `base` plus or minus a random amount up to `plus_minus`,
+    /// never below zero.
+    pub fn with_jitter(mut self, base: Duration, plus_minus: Duration) -> Self {
+        self.delay = Some(Delay::Jitter(base, plus_minus));
+        self
+    }
+
+    /// Consumes the responses once across all connections instead of once per connection,
+    /// so a client connecting later continues where the previous ones left off.
+    pub fn shared_sequence(mut self) -> Self {
+        self.shared = true;
+        self
+    }
+
+    /// Repeats the responses forever, scheduling each one once the previous is due. Needs a
+    /// delay greater than zero, otherwise a [`BuildError`] is reported on register.
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    /// Stops after `n` messages, even if there are more responses left.
+    pub fn take(mut self, n: usize) -> Self {
+        self.take = Some(n);
+        self
+    }
+
+    /// Stops scheduling messages once `duration` has elapsed since the stub was first
+    /// evaluated for a connection.
+    pub fn for_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
     pub fn returning_text(mut self, text: impl Into<String>) -> OnPeriodicalBuilder<Ready> {
         self.responses.push(Body::PlainText(text.into()));
         self.into_ready()
@@ -203,12 +1251,31 @@ impl<T> OnPeriodicalBuilder<T> {
         self.into_ready()
     }
 
+    /// Serializes `value` with `codec`. Binary codecs are sent as binary frames.
+    pub fn returning_encoded(
+        mut self,
+        value: impl Into<JsonValue>,
+        codec: Codec,
+    ) -> OnPeriodicalBuilder<Ready> {
+        self.responses.push(Body::Encoded {
+            value: value.into(),
+            codec,
+        });
+        self.into_ready()
+    }
+
     fn into_ready(self) -> OnPeriodicalBuilder<Ready> {
         OnPeriodicalBuilder {
             id: self.id,
             headers: self.headers,
+            priority: self.priority,
             delay: self.delay,
             responses: self.responses,
+            shared: self.shared,
+            looping: self.looping,
+            take: self.take,
+            duration: self.duration,
+            errors: self.errors,
             _phantom_data: PhantomData::<Ready>,
         }
     }
@@ -216,15 +1283,30 @@ impl<T> OnPeriodicalBuilder<T> {
 
 impl OnPeriodicalBuilder<Ready> {
     pub fn build(self) -> Stub {
+        let delay = self
+            .delay
+            .unwrap_or_else(|| Delay::Fixed(Duration::from_millis(0)));
+        let mut errors = self.errors;
+        if self.looping
+            && let Delay::Fixed(lower) | Delay::Interval(lower, _) | Delay::Jitter(lower, _) =
+                &delay
+            && lower.is_zero()
+        {
+            errors.push(BuildError::LoopingWithoutDelay);
+        }
+
         Stub::Periodical {
-            id: self
-                .id
-                .unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::rng(), 16)),
+            id: self.id.unwrap_or_default(),
             headers: self.headers,
-            delay: self
-                .delay
-                .unwrap_or_else(|| Delay::Fixed(Duration::from_millis(0))),
+            priority: self.priority,
+            delay,
             responses: self.responses,
+            shared: self.shared,
+            looping: self.looping,
+            take: self.take,
+            duration: self.duration,
+            started_at: OnceLock::new(),
+            errors,
         }
     }
 }
@@ -1,25 +1,82 @@
 use std::{
     collections::{BinaryHeap, HashMap},
-    net::{IpAddr, Ipv4Addr, TcpListener},
+    fmt,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener},
+    sync::{
+        Arc, Condvar, Mutex, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
     thread,
     time::{Duration, Instant},
 };
+#[cfg(unix)]
+use std::{
+    os::unix::net::UnixListener,
+    path::{Path, PathBuf},
+};
 
-use tungstenite::accept_hdr;
+use tungstenite::{
+    Bytes, Message, Utf8Bytes, WebSocket, accept_hdr_with_config,
+    handshake::server::ErrorResponse,
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{ORIGIN, SEC_WEBSOCKET_PROTOCOL},
+    },
+    protocol::{
+        CloseFrame, WebSocketConfig,
+        frame::{
+            Frame,
+            coding::{CloseCode, Data, OpCode},
+        },
+    },
+};
 
 use crate::{
+    AnymockError,
     json::JsonValue,
-    matchers::Body,
-    ws::stubs::{Msg, StubsHandle},
+    matchers::{Body, BodyMatcher},
+    ws::{
+        builders::BuildError,
+        pool::WorkerPool,
+        stream::{ClientStream, Listener, Socket},
+        stubs::{Action, Control, Msg, StubsHandle, offset_from},
+    },
 };
 
 pub mod builders;
+mod pool;
+#[cfg(feature = "spec")]
+pub mod spec;
+mod sse;
+mod stream;
 mod stubs;
 
+pub use stubs::Stub;
+
+#[derive(Clone)]
 pub struct Server {
     addr: IpAddr,
     port: u16,
     path: String,
+    ping_interval: Option<Duration>,
+    poll_interval: Duration,
+    subprotocols: Vec<String>,
+    require_subprotocol: bool,
+    on_unmatched: UnmatchedBehavior,
+    max_message_size: Option<usize>,
+    max_frame_size: Option<usize>,
+    idle_timeout: Option<Duration>,
+    sse_fallback: bool,
+    seed: Option<u64>,
+    replay: Vec<(Duration, Body)>,
+    max_connections: Option<usize>,
+    auto_pong: bool,
+    allowed_origins: Option<Vec<String>>,
+    max_worker_threads: Option<usize>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
 }
 
 impl Default for Server {
@@ -28,6 +85,23 @@ impl Default for Server {
             addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 8080,
             path: "/".to_string(),
+            ping_interval: None,
+            poll_interval: Duration::from_millis(1000),
+            subprotocols: Vec::new(),
+            require_subprotocol: false,
+            on_unmatched: UnmatchedBehavior::default(),
+            max_message_size: None,
+            max_frame_size: None,
+            idle_timeout: None,
+            sse_fallback: false,
+            seed: None,
+            replay: Vec::new(),
+            max_connections: None,
+            auto_pong: true,
+            allowed_origins: None,
+            max_worker_threads: None,
+            #[cfg(unix)]
+            unix_socket: None,
         }
     }
 }
@@ -43,122 +117,1061 @@ impl Server {
         self
     }
 
+    /// Path clients must connect to, `/` by default. Handshakes on any other path are
+    /// rejected with `404 Not Found`.
     pub fn path(mut self, value: String) -> Self {
         self.path = value;
         self
     }
 
-    pub fn start(self) -> Result<ServerHandle, std::io::Error> {
-        let listener = TcpListener::bind(format!("{}:{}", self.addr, self.port))?;
-        let stubs_handle = StubsHandle::default();
+    /// Address clients connect to once started, including the path.
+    pub fn url(&self) -> String {
+        url(self.addr, self.port, &self.path)
+    }
+
+    /// Sends an empty ping frame to every connection each `value`, starting one interval after
+    /// it is accepted. Off by default. Pongs from clients can be matched with `on_pong()`, and
+    /// clients that never answer are not disconnected.
+    pub fn ping_interval(mut self, value: Duration) -> Self {
+        self.ping_interval = Some(value);
+        self
+    }
+
+    /// Longest time a connection waits for client frames before checking for periodical and
+    /// pushed messages, 1s by default. Shorter intervals deliver them sooner at the cost of
+    /// waking up idle connections more often.
+    pub fn poll_interval(mut self, value: Duration) -> Self {
+        self.poll_interval = value.max(MIN_READ_TIMEOUT);
+        self
+    }
+
+    /// Subprotocols the server speaks, in no particular order. The first one offered by the
+    /// client that is in this list is echoed back in the handshake, and stubs see it as the
+    /// value of the `sec-websocket-protocol` header.
+    pub fn with_subprotocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.subprotocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Rejects the handshake with `400 Bad Request` when none of the offered subprotocols is
+    /// supported.
+    pub fn require_subprotocol(mut self) -> Self {
+        self.require_subprotocol = true;
+        self
+    }
+
+    /// What to do with text or binary messages no stub matches. They are ignored by default.
+    pub fn on_unmatched(mut self, value: UnmatchedBehavior) -> Self {
+        self.on_unmatched = value;
+        self
+    }
+
+    /// Largest message accepted from clients. Bigger ones close the connection with code 1009
+    /// (message too big). Defaults to tungstenite's 64 MiB.
+    pub fn max_message_size(mut self, value: usize) -> Self {
+        self.max_message_size = Some(value);
+        self
+    }
+
+    /// Largest frame accepted from clients, closing the connection like
+    /// [`Server::max_message_size`]. Defaults to tungstenite's 16 MiB.
+    pub fn max_frame_size(mut self, value: usize) -> Self {
+        self.max_frame_size = Some(value);
+        self
+    }
+
+    /// Closes connections with code 1000 once the client sent nothing for `value`. Messages
+    /// sent by the server do not count as activity.
+    pub fn idle_timeout(mut self, value: Duration) -> Self {
+        self.idle_timeout = Some(value);
+        self
+    }
+
+    /// Whether client pings are answered with a pong, which is the default. Disabling it
+    /// simulates a server that stopped answering, to test client timeouts. Ping stubs still
+    /// see the pings.
+    pub fn auto_pong(mut self, value: bool) -> Self {
+        self.auto_pong = value;
+        self
+    }
+
+    /// Rejects handshakes with `503 Service Unavailable` while `value` connections are open,
+    /// like an overloaded server. New ones are accepted again once a connection closes.
+    pub fn max_connections(mut self, value: usize) -> Self {
+        self.max_connections = Some(value);
+        self
+    }
+
+    /// Serves connections on a pool of `value` threads instead of a thread each. Connections
+    /// past that wait, with the handshake done, until a thread is free, and their messages
+    /// are answered from then on.
+    pub fn max_worker_threads(mut self, value: usize) -> Self {
+        self.max_worker_threads = Some(value.max(1));
+        self
+    }
+
+    /// Rejects handshakes with `403 Forbidden` unless their `Origin` header is one of
+    /// `origins`, like a server guarding against cross-site browser connections. Origins are
+    /// compared ignoring ASCII case, and handshakes without an `Origin` header are rejected too.
+    pub fn allowed_origins(mut self, origins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.allowed_origins = Some(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Listens on a Unix domain socket created at `path` instead of the TCP address, for
+    /// clients talking to a local daemon. The path must not exist yet and is left behind
+    /// once the server stops. Requests are still routed by [`Server::path`], and the SSE
+    /// fallback is only served over TCP.
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix_socket = Some(path.into());
+        self
+    }
+
+    /// Answers plain HTTP `GET` requests on the server path with a Server-Sent Events stream
+    /// of the periodical stubs, for clients falling back from WebSocket. Each text message is
+    /// sent as a `data:` event, binary messages are skipped.
+    pub fn sse_fallback(mut self) -> Self {
+        self.sse_fallback = true;
+        self
+    }
+
+    /// Seeds the random interval and jitter delays and the ids generated for stubs, so they
+    /// are the same on every run. With several connections drawing delays at once, their
+    /// order still decides who gets which value.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sends the timeline to every client once connected, each body at its offset from the
+    /// handshake, after the messages of the connect stub.
+    pub fn replay(mut self, timeline: Vec<(Duration, Body)>) -> Self {
+        self.replay = timeline;
+        self
+    }
+
+    /// Listens on the address, or the Unix socket if set, and serves connections on a
+    /// background thread. Fails with [`AnymockError::Bind`] when it cannot be listened on.
+    pub fn start(self) -> Result<ServerHandle, AnymockError> {
+        self.start_with_stubs(Vec::new())
+    }
+
+    /// Starts the server with `stubs` already registered, so no client connects before they
+    /// are. Fails with [`AnymockError::Build`] without listening when a stub is invalid.
+    pub fn start_with_stubs(self, stubs: Vec<Stub>) -> Result<ServerHandle, AnymockError> {
+        let stubs_handle = StubsHandle::new(self.seed);
+        stubs_handle.replace(stubs)?;
+        let listener = self.bind().map_err(AnymockError::Bind)?;
+        let send_errors = SendErrors::default();
+        let connections = Arc::new(Connections::default());
         let handle = ServerHandle {
             addr: self.addr,
             port: self.port,
+            path: self.path.clone(),
+            #[cfg(unix)]
+            unix_socket: self.unix_socket.clone(),
             stubs_handle: StubsHandle::clone(&stubs_handle),
+            send_errors: Arc::clone(&send_errors),
+            connections: Arc::clone(&connections),
         };
-        thread::spawn(|| Server::run(self, stubs_handle, listener));
+        thread::spawn(|| Server::run(self, stubs_handle, send_errors, connections, listener));
         Ok(handle)
     }
 
-    fn run(self, stubs_handle: StubsHandle, listener: TcpListener) {
-        for stream in listener.incoming() {
-            let stream = match stream {
+    fn bind(&self) -> std::io::Result<Listener> {
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            return UnixListener::bind(path).map(Listener::Unix);
+        }
+        TcpListener::bind(SocketAddr::new(self.addr, self.port)).map(Listener::Tcp)
+    }
+
+    fn run(
+        self,
+        stubs_handle: StubsHandle,
+        send_errors: SendErrors,
+        connections: Arc<Connections>,
+        listener: Listener,
+    ) {
+        let server = Arc::new(self);
+        let workers = server
+            .max_worker_threads
+            .map(|size| Arc::new(WorkerPool::new(size)));
+        loop {
+            let stream = match listener.accept() {
                 Ok(stream) => stream,
-                Err(_) => {
+                Err(err) => {
+                    Server::report_error(err, "failed to accept connection");
                     continue;
                 }
             };
 
-            let mut headers: HashMap<String, String> = HashMap::new();
-            let headers_ref = &mut headers;
-            let callback =
-                move |req: &tungstenite::handshake::server::Request,
-                      response: tungstenite::handshake::server::Response| {
-                    for (ref header, value) in req.headers() {
-                        if let Ok(value) = value.to_str() {
-                            headers_ref.insert(header.to_string(), value.to_string());
+            // Each client sends its request on a thread of its own, so one that is slow to send
+            // it holds back no other.
+            let server = Arc::clone(&server);
+            let stubs_handle = StubsHandle::clone(&stubs_handle);
+            let send_errors = Arc::clone(&send_errors);
+            let connections = Arc::clone(&connections);
+            let workers = workers.clone();
+            thread::spawn(move || {
+                server.serve(
+                    stream,
+                    stubs_handle,
+                    send_errors,
+                    connections,
+                    workers.as_deref(),
+                )
+            });
+        }
+    }
+
+    // Reads the request within `HANDSHAKE_TIMEOUT` and answers it with an event stream or a
+    // WebSocket session, which runs on the pool when there is one.
+    fn serve(
+        &self,
+        stream: Socket,
+        stubs_handle: StubsHandle,
+        send_errors: SendErrors,
+        connections: Arc<Connections>,
+        workers: Option<&WorkerPool>,
+    ) {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        let stream = match stream {
+            Socket::Tcp(stream) if self.sse_fallback => {
+                match sse::peek_request(&stream, deadline) {
+                    Ok(Some(request)) => {
+                        // Clients going away are the normal end of a stream.
+                        if let Err(err) = sse::serve(
+                            stream,
+                            request,
+                            &self.path,
+                            &stubs_handle,
+                            self.poll_interval,
+                        ) && !matches!(
+                            err.kind(),
+                            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+                        ) {
+                            Server::report_error(err, "event stream failed");
                         }
+                        return;
+                    }
+                    Ok(None) => Socket::Tcp(stream),
+                    Err(err) => {
+                        Server::report_error(err, "failed to read request");
+                        return;
                     }
+                }
+            }
+            stream => stream,
+        };
 
-                    Ok(response)
-                };
+        let mut headers: HashMap<String, String> = HashMap::new();
+        let mut on_connect: Vec<Msg> = Vec::new();
+        let headers_ref = &mut headers;
+        let on_connect_ref = &mut on_connect;
+        let stubs_handle_ref = &stubs_handle;
+        let subprotocols = &self.subprotocols;
+        let require_subprotocol = self.require_subprotocol;
+        let max_connections = self.max_connections;
+        let allowed_origins = self.allowed_origins.as_deref();
+        let connections_ref = &connections;
+        let path = self.path.trim_start_matches('/');
+        let mut handshake: Option<HandshakeRecord> = None;
+        let handshake_ref = &mut handshake;
+        let mut slot = false;
+        let slot_ref = &mut slot;
+        #[allow(clippy::result_large_err)]
+        let mut respond =
+            move |req: &tungstenite::handshake::server::Request,
+                  mut response: tungstenite::handshake::server::Response| {
+                let request_path = req.uri().path();
+                if request_path.trim_start_matches('/') != path
+                    && !stubs_handle_ref.accepts_path(request_path)
+                {
+                    let mut error = ErrorResponse::new(None);
+                    *error.status_mut() = StatusCode::NOT_FOUND;
+                    return Err(error);
+                }
+                headers_ref.insert(PATH_HEADER.to_string(), request_path.to_string());
 
-            let mut websocket = if let Ok(websocket) = accept_hdr(stream, callback) {
-                websocket
-            } else {
-                continue;
-            };
+                if let Some(allowed_origins) = allowed_origins {
+                    let origin = req.headers().get(ORIGIN).and_then(|v| v.to_str().ok());
+                    if !origin.is_some_and(|origin| {
+                        allowed_origins
+                            .iter()
+                            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                    }) {
+                        let mut error = ErrorResponse::new(None);
+                        *error.status_mut() = StatusCode::FORBIDDEN;
+                        return Err(error);
+                    }
+                }
 
-            thread::spawn({
-                let stubs_handle = StubsHandle::clone(&stubs_handle);
-                let mut messages: BinaryHeap<Msg> = BinaryHeap::new();
+                // Taken in one step, since other handshakes may be past this check meanwhile.
+                if connections_ref
+                    .slots
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |slots| {
+                        max_connections
+                            .is_none_or(|max| slots < max)
+                            .then_some(slots + 1)
+                    })
+                    .is_err()
+                {
+                    let mut error = ErrorResponse::new(None);
+                    *error.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    return Err(error);
+                }
+                *slot_ref = true;
 
-                if let Some(msg) = stubs_handle.on_connect(&headers) {
-                    messages.push(msg);
+                // Values outside visible ASCII are kept, decoded as UTF-8 with invalid bytes
+                // replaced by U+FFFD, so stubs can still match the rest of them.
+                for (ref header, value) in req.headers() {
+                    let value = match value.to_str() {
+                        Ok(value) => value.to_string(),
+                        Err(_) => {
+                            let lossy = String::from_utf8_lossy(value.as_bytes());
+                            trace_event!(
+                                warn,
+                                %header,
+                                value = %lossy,
+                                "non-ASCII header value"
+                            );
+                            lossy.into_owned()
+                        }
+                    };
+                    headers_ref.insert(header.to_string(), value);
                 }
 
-                move || {
-                    loop {
-                        if let Some(msgs) = stubs_handle.on_periodical(&headers) {
-                            messages.extend(msgs);
+                if !subprotocols.is_empty() {
+                    let negotiated = headers_ref
+                        .remove(SUBPROTOCOL_HEADER)
+                        .and_then(|offered| {
+                            offered
+                                .split(',')
+                                .map(str::trim)
+                                .find(|protocol| subprotocols.iter().any(|p| p == protocol))
+                                .map(str::to_string)
+                        })
+                        .and_then(|protocol| {
+                            HeaderValue::from_str(&protocol)
+                                .ok()
+                                .map(|value| (protocol, value))
+                        });
+                    match negotiated {
+                        Some((protocol, value)) => {
+                            response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+                            headers_ref.insert(SUBPROTOCOL_HEADER.to_string(), protocol);
+                        }
+                        None if require_subprotocol => {
+                            let mut error = ErrorResponse::new(None);
+                            *error.status_mut() = StatusCode::BAD_REQUEST;
+                            return Err(error);
                         }
+                        None => {}
+                    }
+                }
 
-                        let now = Instant::now();
-                        if messages.is_empty() {
-                            websocket
-                                .get_mut()
-                                .set_read_timeout(Some(Duration::from_millis(1000)))
-                                .expect("failed to set read timeout");
+                match stubs_handle_ref.on_connect(headers_ref) {
+                    Ok((msg, response_headers)) => {
+                        *on_connect_ref = msg;
+                        for (name, value) in &response_headers {
+                            response.headers_mut().append(name, value.clone());
                         }
+                        Ok(response)
+                    }
+                    Err(status) => {
+                        let mut error = ErrorResponse::new(None);
+                        *error.status_mut() =
+                            StatusCode::from_u16(status).unwrap_or(StatusCode::FORBIDDEN);
+                        Err(error)
+                    }
+                }
+            };
+        #[allow(clippy::result_large_err)]
+        let callback =
+            move |req: &tungstenite::handshake::server::Request,
+                  response: tungstenite::handshake::server::Response| {
+                let result = respond(req, response);
+                *handshake_ref = Some(HandshakeRecord::new(req, &result));
+                result
+            };
 
-                        while let Some(Msg(_, when)) = messages.peek() {
-                            if *when <= now {
-                                let Msg(msg, _) = messages
-                                    .pop()
-                                    .expect("peek returned Some, so pop must succeed");
+        let mut config = WebSocketConfig::default();
+        if let Some(max_message_size) = self.max_message_size {
+            config = config.max_message_size(Some(max_message_size));
+        }
+        if let Some(max_frame_size) = self.max_frame_size {
+            config = config.max_frame_size(Some(max_frame_size));
+        }
+        let mut stream = ClientStream::new(stream);
+        stream.set_deadline(Some(deadline));
+        let mut websocket = match accept_hdr_with_config(stream, callback, Some(config)) {
+            Ok(websocket) => websocket,
+            Err(err) => {
+                // The error holds on to the callback, so it goes first.
+                Server::report_error(err, "websocket handshake failed");
+                if slot {
+                    connections.slots.fetch_sub(1, Ordering::SeqCst);
+                }
+                if let Some(handshake) = handshake {
+                    connections.record_handshake(handshake);
+                }
+                return;
+            }
+        };
+        websocket.get_mut().set_deadline(None);
+        websocket.get_mut().set_auto_pong(self.auto_pong);
 
-                                let _ = websocket.send(msg);
-                                continue;
-                            }
-                            websocket
-                                .get_mut()
-                                .set_read_timeout(Some(when.duration_since(now)))
-                                .expect("failed to set read timeout");
+        let session = {
+            let stubs_handle = StubsHandle::clone(&stubs_handle);
+            let send_errors = Arc::clone(&send_errors);
+            let (connection_guard, pushed) =
+                ConnectionGuard::new(Arc::clone(&connections), &headers, handshake);
+            let mut messages: BinaryHeap<Msg> = BinaryHeap::new();
 
-                            break;
-                        }
+            messages.extend(on_connect);
+            let connected_at = Instant::now();
+            messages.extend(self.replay.iter().map(|(offset, body)| {
+                Msg::new(
+                    Action::Send(Stub::body_message(body)),
+                    offset_from(connected_at, *offset),
+                )
+            }));
+
+            let ping_interval = self.ping_interval;
+            let poll_interval = self.poll_interval;
+            let on_unmatched = self.on_unmatched.clone();
+            let idle_timeout = self.idle_timeout;
+            let mut last_activity = Instant::now();
+            let mut next_ping = ping_interval.map(|interval| Instant::now() + interval);
+            if let Some(when) = next_ping {
+                messages.push(Msg::new(Action::Send(Message::Ping(Bytes::new())), when));
+            }
+
+            move || {
+                let connection = connection_guard;
+                let mut closing = false;
+                let mut close_at = None;
+                'session: loop {
+                    if !closing && let Some(msgs) = stubs_handle.on_periodical(&headers) {
+                        messages.extend(msgs);
+                    }
+
+                    if !closing {
+                        messages.extend(pushed.try_iter().map(|body| {
+                            Msg::new(Action::Send(Stub::body_message(&body)), Instant::now())
+                        }));
+                    }
+
+                    let now = Instant::now();
+                    if let (Some(interval), Some(when)) = (ping_interval, next_ping.as_mut())
+                        && !closing
+                        && *when <= now
+                    {
+                        *when += interval;
+                        messages.push(Msg::new(Action::Send(Message::Ping(Bytes::new())), *when));
+                    }
+
+                    if let Some(idle_timeout) = idle_timeout
+                        && !closing
+                        && now.saturating_duration_since(last_activity) >= idle_timeout
+                    {
+                        messages.push(Msg::new(
+                            Action::Send(Message::Close(Some(CloseFrame {
+                                code: CloseCode::Normal,
+                                reason: Utf8Bytes::from_static("idle timeout"),
+                            }))),
+                            now,
+                        ));
+                    }
 
-                        let payload = match websocket.read() {
-                            Ok(msg) if msg.is_binary() => Body::Binary(msg.into_data().into()),
-                            Ok(msg) if msg.is_text() => {
-                                let msg_buf = msg
-                                    .into_text()
-                                    .expect("Checked previously that's text message");
-                                match JsonValue::try_from(msg_buf.as_str()) {
-                                    Ok(json) => Body::Json(json),
-                                    Err(_) => Body::PlainText(msg_buf.as_str().to_string()),
+                    // Reached after every read, so inbound traffic cannot hold back due
+                    // messages.
+                    while let Some(Msg(_, when, _)) = messages.peek() {
+                        if *when <= now {
+                            let Msg(action, ..) = messages
+                                .pop()
+                                .expect("peek returned Some, so pop must succeed");
+
+                            match action {
+                                Action::Send(msg) => {
+                                    // Nothing else is sent after a close frame, the loop ends
+                                    // once the client acknowledges it.
+                                    if msg.is_close() {
+                                        closing = true;
+                                        close_at = Some(Instant::now() + CLOSE_TIMEOUT);
+                                        messages.clear();
+                                    }
+                                    trace_event!(
+                                        debug,
+                                        id = %connection.id,
+                                        frame = ?msg,
+                                        "message sent"
+                                    );
+                                    let result = websocket.send(msg);
+                                    if !Server::handle_send(result, &send_errors) {
+                                        break 'session;
+                                    }
                                 }
-                            }
-                            Ok(_) => {
-                                continue;
-                            }
-                            Err(err) => match err {
-                                tungstenite::Error::Io(_) => {
-                                    continue;
+                                Action::Throttle(msg, bytes_per_sec) => {
+                                    let result =
+                                        Server::send_throttled(&mut websocket, msg, bytes_per_sec);
+                                    if !Server::handle_send(result, &send_errors) {
+                                        break 'session;
+                                    }
                                 }
-                                _ => {
-                                    break;
+                                // The next read fails without a closing handshake and ends
+                                // the loop.
+                                Action::Disconnect => {
+                                    if let Err(err) =
+                                        websocket.get_ref().socket().shutdown(Shutdown::Both)
+                                    {
+                                        Server::report_error(err, "failed to shut down socket");
+                                    }
+                                    closing = true;
+                                    messages.clear();
                                 }
-                            },
-                        };
+                            }
+                            continue;
+                        }
+                        break;
+                    }
+
+                    // A client that never acknowledges the close frame would keep the
+                    // connection open for good.
+                    if close_at.is_some_and(|close_at| close_at <= Instant::now()) {
+                        trace_event!(debug, id = %connection.id, "close not acknowledged");
+                        break 'session;
+                    }
 
-                        if let Some(msg) = stubs_handle.on_message(&headers, payload) {
-                            messages.push(msg);
+                    // Blocks until the next scheduled message is due, the connection goes
+                    // idle or the close acknowledgement is overdue. The floor keeps messages
+                    // due within the same millisecond from spinning the loop.
+                    let idle_at = idle_timeout
+                        .filter(|_| !closing)
+                        .and_then(|idle_timeout| last_activity.checked_add(idle_timeout));
+                    let read_timeout = messages
+                        .peek()
+                        .map(|Msg(_, when, _)| *when)
+                        .into_iter()
+                        .chain(idle_at)
+                        .chain(close_at)
+                        .min()
+                        .map_or(poll_interval, |when| {
+                            when.saturating_duration_since(Instant::now())
+                                .clamp(MIN_READ_TIMEOUT, poll_interval)
+                        });
+                    websocket
+                        .get_ref()
+                        .socket()
+                        .set_read_timeout(Some(read_timeout))
+                        .expect("failed to set read timeout");
+
+                    let read = websocket.read();
+                    if read.is_ok() {
+                        last_activity = Instant::now();
+                    }
+                    let incoming = match read {
+                        Ok(msg) if msg.is_binary() || msg.is_text() => {
+                            Incoming::Data(IncomingMessage::decode(msg))
+                        }
+                        Ok(Message::Ping(data)) => {
+                            Incoming::Control(Control::Ping, Body::Binary(data.into()))
+                        }
+                        Ok(Message::Pong(data)) => {
+                            Incoming::Control(Control::Pong, Body::Binary(data.into()))
+                        }
+                        // Tungstenite queues the acknowledgement, which is flushed by the
+                        // next read before it reports the connection as closed.
+                        Ok(Message::Close(frame)) => {
+                            let (code, reason) = frame.map_or_else(
+                                || (u16::from(CloseCode::Status), String::new()),
+                                |frame| (u16::from(frame.code), frame.reason.to_string()),
+                            );
+                            connection.emit(ServerEvent::CloseReceived {
+                                id: connection.id,
+                                code,
+                                reason: reason.clone(),
+                            });
+                            Incoming::Control(
+                                Control::Close,
+                                Body::Json(JsonValue::Object(HashMap::from([
+                                    ("code".to_string(), JsonValue::Int(code.into())),
+                                    ("reason".to_string(), JsonValue::Str(reason)),
+                                ]))),
+                            )
+                        }
+                        Ok(_) => {
+                            continue;
+                        }
+                        // Only timeouts are retried, any other I/O error would fail again
+                        // right away.
+                        Err(tungstenite::Error::Io(err))
+                            if matches!(
+                                err.kind(),
+                                ErrorKind::WouldBlock
+                                    | ErrorKind::TimedOut
+                                    | ErrorKind::Interrupted
+                            ) =>
+                        {
+                            continue;
+                        }
+                        // The rest of the oversized frame is left unread, so nothing else can
+                        // be read from this connection.
+                        Err(tungstenite::Error::Capacity(_)) => {
+                            if let Err(err) = websocket.close(Some(CloseFrame {
+                                code: CloseCode::Size,
+                                reason: Utf8Bytes::from_static("message too big"),
+                            })) {
+                                Server::report_error(err, "failed to close oversized connection");
+                            }
+                            if let Err(err) = websocket.flush() {
+                                Server::report_error(err, "failed to flush close frame");
+                            }
+                            break;
+                        }
+                        Err(tungstenite::Error::ConnectionClosed) => {
+                            break;
+                        }
+                        Err(err) => {
+                            Server::report_error(err, "failed to read message");
+                            break;
                         }
+                    };
+
+                    trace_event!(
+                        debug,
+                        id = %connection.id,
+                        frame = ?incoming,
+                        "message received"
+                    );
+                    if let Incoming::Data(message) = &incoming {
+                        connection.record(message);
                     }
+
+                    if closing {
+                        continue;
+                    }
+
+                    let msgs = match incoming {
+                        Incoming::Control(control, payload) => {
+                            stubs_handle.on_control(control, &headers, payload)
+                        }
+                        Incoming::Data(message) => {
+                            let msgs = stubs_handle.on_message(&headers, message);
+                            if msgs.is_empty() {
+                                on_unmatched.message().into_iter().collect()
+                            } else {
+                                msgs
+                            }
+                        }
+                    };
+                    messages.extend(msgs);
                 }
+            }
+        };
+        match workers {
+            Some(workers) => workers.execute(session),
+            None => session(),
+        }
+    }
+
+    // Returns whether the session can go on. Errors caused by the client going away end it,
+    // any other one is kept for `ServerHandle::take_send_errors`.
+    fn handle_send(result: tungstenite::Result<()>, send_errors: &SendErrors) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(
+                tungstenite::Error::ConnectionClosed
+                | tungstenite::Error::AlreadyClosed
+                | tungstenite::Error::Io(_),
+            ) => false,
+            Err(err) => {
+                send_errors
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(err);
+                true
+            }
+        }
+    }
+
+    // Errors nothing can be done about, only reported through `tracing` when enabled.
+    fn report_error(err: impl fmt::Display, context: &'static str) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(error = %err, "{context}");
+        #[cfg(not(feature = "tracing"))]
+        let _ = (err, context);
+    }
+
+    // Splits the payload in fragments sized for a tenth of a second, waiting the time each
+    // one takes at the given rate before writing it.
+    fn send_throttled(
+        websocket: &mut WebSocket<ClientStream>,
+        msg: Message,
+        bytes_per_sec: u32,
+    ) -> tungstenite::Result<()> {
+        let bytes_per_sec = bytes_per_sec.max(1) as usize;
+        let (data, opcode): (Bytes, Data) = match msg {
+            Message::Text(text) => (text.into(), Data::Text),
+            Message::Binary(binary) => (binary, Data::Binary),
+            msg => return websocket.send(msg),
+        };
+
+        let chunk_size = (bytes_per_sec / 10).max(1);
+        let total_chunks = data.len().div_ceil(chunk_size).max(1);
+        for idx in 0..total_chunks {
+            let start = (idx * chunk_size).min(data.len());
+            let end = (start + chunk_size).min(data.len());
+            let chunk = data.slice(start..end);
+            thread::sleep(Duration::from_secs_f64(
+                chunk.len() as f64 / bytes_per_sec as f64,
+            ));
+
+            let opcode = if idx == 0 { opcode } else { Data::Continue };
+            let frame = Frame::message(chunk, OpCode::Data(opcode), idx + 1 == total_chunks);
+            websocket.send(Message::Frame(frame))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) const SUBPROTOCOL_HEADER: &str = "sec-websocket-protocol";
+
+// Pseudo header holding the handshake path, the `:` keeps it apart from real header names.
+pub(crate) const PATH_HEADER: &str = ":path";
+
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+// Longest time a client has to send its request once connected.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Longest time a client has to acknowledge a close frame before the socket is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type SendErrors = Arc<Mutex<Vec<tungstenite::Error>>>;
+
+/// Identifies a client connection for as long as the server runs. Ids are handed out in
+/// connection order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Identifies a registered stub, as returned by `ServerHandle::register`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StubId(u64);
+
+/// Error returned when pushing a message to a connection.
+#[derive(Debug)]
+pub enum SendToError {
+    NotConnected(ConnectionId),
+}
+
+impl fmt::Display for SendToError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendToError::NotConnected(id) => write!(f, "no open connection with id {id}"),
+        }
+    }
+}
+
+impl std::error::Error for SendToError {}
+
+/// Something that happened in the server, delivered in order through [`ServerHandle::events`].
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    Connected {
+        id: ConnectionId,
+        headers: HashMap<String, String>,
+    },
+    Disconnected {
+        id: ConnectionId,
+    },
+    /// A text or binary message sent by the client. Control frames are not reported.
+    MessageReceived {
+        id: ConnectionId,
+        body: Body,
+    },
+    /// A close frame sent by the client. `code` is 1005 when the frame had none.
+    CloseReceived {
+        id: ConnectionId,
+        code: u16,
+        reason: String,
+    },
+}
+
+/// Answer to a text or binary message that no stub matches, set with
+/// [`Server::on_unmatched`].
+#[derive(Debug, Clone, Default)]
+pub enum UnmatchedBehavior {
+    #[default]
+    Ignore,
+    Reply(Body),
+    Close(u16, String),
+}
+
+impl UnmatchedBehavior {
+    fn message(&self) -> Option<Msg> {
+        let message = match self {
+            UnmatchedBehavior::Ignore => return None,
+            UnmatchedBehavior::Reply(body) => Stub::body_message(body),
+            UnmatchedBehavior::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code: (*code).into(),
+                reason: Utf8Bytes::from(reason.as_str()),
+            })),
+        };
+        Some(Msg::new(Action::Send(message), Instant::now()))
+    }
+}
+
+/// Scores the message stubs gave to the last text or binary message, returned by
+/// [`ServerHandle::last_match_report`].
+#[derive(Debug, Clone)]
+pub struct MatchReport {
+    pub body: Body,
+    /// Index of the stub that answered, if any.
+    pub winner: Option<usize>,
+    /// One entry per message stub, in registration order.
+    pub stubs: Vec<StubScore>,
+}
+
+/// Score of a message stub, indexed by registration order among message stubs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StubScore {
+    pub index: usize,
+    /// `None` when the stub was not scored, as its matchers could not outscore the winner.
+    pub score: Option<u16>,
+}
+
+impl StubScore {
+    /// Whether some matcher of the stub did not match.
+    pub fn is_disqualified(&self) -> bool {
+        self.score == Some(0)
+    }
+}
+
+/// A text or binary message received from a client.
+#[derive(Debug, Clone)]
+pub struct RecordedMessage {
+    pub id: ConnectionId,
+    pub body: Body,
+    /// The frame exactly as the client sent it.
+    pub raw: Message,
+    pub received_at: Instant,
+}
+
+/// A handshake as received from a client, with the response the server answered. Rejected
+/// handshakes are recorded too.
+#[derive(Debug, Clone)]
+pub struct HandshakeRecord {
+    /// Connection opened by the handshake, None when it was rejected.
+    pub connection: Option<ConnectionId>,
+    /// Like `GET /chat HTTP/1.1`.
+    pub request_line: String,
+    /// Request headers in the order sent, with lowercase names. Repeated headers appear once
+    /// per value.
+    pub headers: Vec<(String, String)>,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+}
+
+impl HandshakeRecord {
+    #[allow(clippy::result_large_err)]
+    fn new(
+        req: &tungstenite::handshake::server::Request,
+        result: &Result<tungstenite::handshake::server::Response, ErrorResponse>,
+    ) -> Self {
+        let (status, response_headers) = match result {
+            Ok(response) => (response.status(), response.headers()),
+            Err(error) => (error.status(), error.headers()),
+        };
+        HandshakeRecord {
+            connection: None,
+            request_line: format!("{} {} {:?}", req.method(), req.uri(), req.version()),
+            headers: header_pairs(req.headers()),
+            status: status.as_u16(),
+            response_headers: header_pairs(response_headers),
+        }
+    }
+
+    /// First value of the request header `name`, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// A text or binary message as sent by the client and as seen by matchers.
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    /// The frame as received, keeping the original text or bytes.
+    pub raw: Message,
+    /// JSON when the text parses as JSON, plain text or binary otherwise.
+    pub decoded: Body,
+}
+
+impl IncomingMessage {
+    fn decode(raw: Message) -> Self {
+        let decoded = match &raw {
+            Message::Text(text) => match JsonValue::try_from(text.as_str()) {
+                Ok(json) => Body::Json(json),
+                Err(_) => Body::PlainText(text.as_str().to_string()),
+            },
+            raw => Body::Binary(raw.clone().into_data().into()),
+        };
+        IncomingMessage { raw, decoded }
+    }
+}
+
+// Frame read from a client, data messages go to message stubs and the rest to control stubs.
+#[derive(Debug)]
+enum Incoming {
+    Data(IncomingMessage),
+    Control(Control, Body),
+}
+
+/// Error returned when nothing matching arrived before the timeout.
+#[derive(Debug)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out waiting for a matching message")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+// Open connections, with the channel each one drains to send pushed messages. Every received
+// message and handshake is recorded for as long as the server runs.
+#[derive(Default)]
+struct Connections {
+    count: AtomicUsize,
+    // Open connections plus the ones past the `max_connections` check still handshaking.
+    slots: AtomicUsize,
+    next_id: AtomicU64,
+    senders: Mutex<HashMap<ConnectionId, Sender<Body>>>,
+    subscribers: Mutex<Vec<Sender<ServerEvent>>>,
+    recorded: Mutex<Vec<RecordedMessage>>,
+    recorded_changed: Condvar,
+    handshakes: Mutex<Vec<HandshakeRecord>>,
+}
+
+impl Connections {
+    fn record_handshake(&self, handshake: HandshakeRecord) {
+        self.handshakes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(handshake);
+    }
+
+    // Receivers that were dropped are forgotten on the next event.
+    fn emit(&self, event: ServerEvent) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+fn url(addr: IpAddr, port: u16, path: &str) -> String {
+    let separator = if path.starts_with('/') { "" } else { "/" };
+    format!("ws://{}{separator}{path}", SocketAddr::new(addr, port))
+}
+
+// Counts a connection as open for as long as its thread holds it, whatever way it exits.
+struct ConnectionGuard {
+    id: ConnectionId,
+    connections: Arc<Connections>,
+}
+
+impl ConnectionGuard {
+    fn new(
+        connections: Arc<Connections>,
+        headers: &HashMap<String, String>,
+        handshake: Option<HandshakeRecord>,
+    ) -> (Self, Receiver<Body>) {
+        let id = ConnectionId(connections.next_id.fetch_add(1, Ordering::SeqCst));
+        let (sender, receiver) = mpsc::channel();
+        connections
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, sender);
+        if let Some(handshake) = handshake {
+            connections.record_handshake(HandshakeRecord {
+                connection: Some(id),
+                ..handshake
             });
         }
+        connections.count.fetch_add(1, Ordering::SeqCst);
+        trace_event!(debug, %id, ?headers, "connection accepted");
+        connections.emit(ServerEvent::Connected {
+            id,
+            headers: headers.clone(),
+        });
+
+        (ConnectionGuard { id, connections }, receiver)
+    }
+
+    fn emit(&self, event: ServerEvent) {
+        self.connections.emit(event);
+    }
+
+    fn record(&self, message: &IncomingMessage) {
+        self.connections
+            .recorded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(RecordedMessage {
+                id: self.id,
+                body: message.decoded.clone(),
+                raw: message.raw.clone(),
+                received_at: Instant::now(),
+            });
+        self.connections.recorded_changed.notify_all();
+        self.emit(ServerEvent::MessageReceived {
+            id: self.id,
+            body: message.decoded.clone(),
+        });
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+        self.connections.count.fetch_sub(1, Ordering::SeqCst);
+        self.connections.slots.fetch_sub(1, Ordering::SeqCst);
+        trace_event!(debug, id = %self.id, "connection closed");
+        self.emit(ServerEvent::Disconnected { id: self.id });
     }
 }
 
@@ -166,12 +1179,35 @@ impl Server {
 pub struct ServerHandle {
     addr: IpAddr,
     port: u16,
+    path: String,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
     stubs_handle: StubsHandle,
+    send_errors: SendErrors,
+    connections: Arc<Connections>,
 }
 
 impl ServerHandle {
-    pub fn register(&self, stub: stubs::Stub) {
-        self.stubs_handle.register(stub);
+    pub fn register(&self, stub: stubs::Stub) -> Result<StubId, Vec<BuildError>> {
+        self.stubs_handle.register(stub)
+    }
+
+    /// Registers `stubs` in place of every current one, like a reload. Each kind of stub is
+    /// swapped in a single write, so messages are matched against either the previous stubs or
+    /// the new ones, never against none. Nothing changes when any of them is invalid.
+    pub fn replace_stubs(&self, stubs: Vec<Stub>) -> Result<Vec<StubId>, Vec<BuildError>> {
+        self.stubs_handle.replace(stubs)
+    }
+
+    /// Removes every registered stub. Connections stay open, and only messages that were
+    /// already scheduled are still sent.
+    pub fn clear_stubs(&self) {
+        self.stubs_handle.clear();
+    }
+
+    /// Times the stub was selected to answer a connection or message so far.
+    pub fn stub_hits(&self, id: StubId) -> usize {
+        self.stubs_handle.hits(id)
     }
 
     pub fn port(&self) -> u16 {
@@ -181,4 +1217,159 @@ impl ServerHandle {
     pub fn addr(&self) -> String {
         self.addr.to_string()
     }
+
+    /// Address clients connect to, like `ws://127.0.0.1:8080/`. IPv6 hosts are bracketed, as
+    /// in `ws://[::1]:8080/`.
+    pub fn url(&self) -> String {
+        url(self.addr, self.port, &self.path)
+    }
+
+    /// Path of the Unix domain socket the server listens on, if started with
+    /// [`Server::unix_socket`]. Clients connect to it and send a handshake for [`Self::url`],
+    /// whose host is then ignored.
+    #[cfg(unix)]
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    /// Number of clients whose handshake completed and whose connection is still open.
+    pub fn connection_count(&self) -> usize {
+        self.connections.count.load(Ordering::SeqCst)
+    }
+
+    /// Ids of the open connections, in connection order.
+    pub fn connection_ids(&self) -> Vec<ConnectionId> {
+        let mut ids: Vec<ConnectionId> = self
+            .connections
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .copied()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Subscribes to the events happening from now on. Each call returns an independent
+    /// receiver. The channel is unbounded, so events pile up in memory until they are read.
+    pub fn events(&self) -> Receiver<ServerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.connections
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Handshakes received so far, accepted or not, in the order they arrived.
+    pub fn handshakes(&self) -> Vec<HandshakeRecord> {
+        self.connections
+            .handshakes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// How each message stub scored the last text or binary message received by any
+    /// connection. None until a message arrives.
+    pub fn last_match_report(&self) -> Option<MatchReport> {
+        self.stubs_handle.last_match_report()
+    }
+
+    /// Blocks until a message received by any connection matches `matcher`, checking the
+    /// ones received before the call first.
+    pub fn wait_for_message(
+        &self,
+        matcher: BodyMatcher,
+        timeout: Duration,
+    ) -> Result<RecordedMessage, Timeout> {
+        let deadline = Instant::now() + timeout;
+        let mut recorded = self
+            .connections
+            .recorded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut checked = 0;
+        loop {
+            if let Some(msg) = recorded[checked..]
+                .iter()
+                .find(|msg| matcher.score(Some(&msg.body)) > 0)
+            {
+                return Ok(msg.clone());
+            }
+            checked = recorded.len();
+
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or(Timeout)?;
+            recorded = self
+                .connections
+                .recorded_changed
+                .wait_timeout(recorded, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Panics when a connection sent more than `max_per_sec` messages matching `matcher`
+    /// within any one second window, naming the connection and the window.
+    #[track_caller]
+    pub fn verify_rate_below(&self, matcher: BodyMatcher, max_per_sec: usize) {
+        let mut received: HashMap<ConnectionId, Vec<Instant>> = HashMap::new();
+        for msg in self
+            .connections
+            .recorded
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|msg| matcher.score(Some(&msg.body)) > 0)
+        {
+            received.entry(msg.id).or_default().push(msg.received_at);
+        }
+
+        for (id, instants) in received {
+            let mut start = 0;
+            for (end, received_at) in instants.iter().enumerate() {
+                while received_at.duration_since(instants[start]) >= Duration::from_secs(1) {
+                    start += 1;
+                }
+                let count = end - start + 1;
+                assert!(
+                    count <= max_per_sec,
+                    "connection {id} sent {count} matching messages within {:?}, above the limit \
+                     of {max_per_sec} per second (matching messages {} to {}, starting {:?} \
+                     after its first one)",
+                    received_at.duration_since(instants[start]),
+                    start + 1,
+                    end + 1,
+                    instants[start].duration_since(instants[0]),
+                );
+            }
+        }
+    }
+
+    /// Pushes `body` to a single connection, which sends it on its next loop iteration.
+    pub fn send_to(&self, id: ConnectionId, body: Body) -> Result<(), SendToError> {
+        self.connections
+            .senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .and_then(|sender| sender.send(body).ok())
+            .ok_or(SendToError::NotConnected(id))
+    }
+
+    /// Drains the errors found while sending responses, other than the client going away,
+    /// which just ends its connection.
+    pub fn take_send_errors(&self) -> Vec<tungstenite::Error> {
+        std::mem::take(
+            &mut *self
+                .send_errors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
 }
@@ -0,0 +1,50 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex, PoisonError,
+        mpsc::{self, Sender},
+    },
+    thread,
+};
+
+use crate::ws::stubs::reset_connection_state;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Fixed set of threads running connection sessions one after another. Sessions queue until a
+// thread is free, and the threads exit once the pool is dropped.
+pub(crate) struct WorkerPool {
+    jobs: Sender<Job>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(size: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || {
+                loop {
+                    let job = match receiver
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .recv()
+                    {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    // A panicking session ends its connection, not the thread serving it.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    reset_connection_state();
+                }
+            });
+        }
+
+        WorkerPool { jobs }
+    }
+
+    pub(crate) fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        // The threads only stop once `jobs` is dropped, so sending cannot fail.
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
@@ -0,0 +1,418 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    matchers::{Body, HexError, TextMatcher, cached_regex, hex_to_bytes},
+    ws::{
+        builders::{OnPeriodicalBuilder, Ready, on_connect, on_message, on_periodical},
+        stubs::Stub,
+    },
+};
+
+/// Declarative form of a stub, loaded with [`Stub::from_spec`] from YAML or JSON such as:
+///
+/// ```yaml
+/// on: message
+/// body: { text: { eq: ping } }
+/// delay: { fixed_ms: 10 }
+/// response: { text: pong }
+/// ```
+///
+/// Only data can be described, closure matchers like `text_fn` have no spec form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "on", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StubSpec {
+    Connect {
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, TextSpec>,
+        #[serde(default)]
+        priority: i32,
+        response: ResponseSpec,
+    },
+    Message {
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, TextSpec>,
+        #[serde(default)]
+        priority: i32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<BodyMatcherSpec>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delay: Option<DelaySpec>,
+        #[serde(default)]
+        once: bool,
+        response: ResponseSpec,
+    },
+    Periodical {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, TextSpec>,
+        #[serde(default)]
+        priority: i32,
+        delay: DelaySpec,
+        responses: Vec<BodySpec>,
+        #[serde(default)]
+        looping: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        take: Option<usize>,
+    },
+}
+
+/// Spec form of a [`TextMatcher`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSpec {
+    Eq(String),
+    Contains(String),
+    NotContains(String),
+    Regex(String),
+    LenEq(usize),
+    LenGt(usize),
+    LenLt(usize),
+    Empty,
+    NonEmpty,
+    Any,
+    None,
+    AllOf(Vec<TextSpec>),
+    AnyOf(Vec<TextSpec>),
+    Not(Box<TextSpec>),
+}
+
+/// Matcher for the incoming message of a `message` stub.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyMatcherSpec {
+    Text(TextSpec),
+    JsonEq(Value),
+}
+
+/// Message sent by a stub. Binary bodies are written as hex strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodySpec {
+    Text(String),
+    Json(Value),
+    BinaryHex(String),
+}
+
+/// Response of a `connect` or `message` stub. `reject` is only valid for connect stubs, and
+/// `sequence`, `many`, `echo`, `close` and `disconnect` only for message stubs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseSpec {
+    Text(String),
+    Json(Value),
+    BinaryHex(String),
+    Sequence {
+        responses: Vec<BodySpec>,
+        #[serde(default)]
+        cycle: bool,
+    },
+    Many(Vec<BodySpec>),
+    Echo,
+    Close {
+        code: u16,
+        #[serde(default)]
+        reason: String,
+    },
+    Disconnect,
+    Reject(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelaySpec {
+    FixedMs(u64),
+    IntervalMs(u64, u64),
+    /// Base delay and the maximum deviation in either direction.
+    JitterMs(u64, u64),
+}
+
+/// Error found while loading a stub spec.
+#[derive(Debug)]
+pub enum SpecError {
+    Parse(serde_norway::Error),
+    InvalidRegex {
+        source: regex::Error,
+    },
+    InvalidHex {
+        source: HexError,
+    },
+    /// The spec or matcher uses something that has no declarative form or is not valid for
+    /// that kind of stub.
+    Unsupported(String),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::Parse(source) => write!(f, "invalid stub spec: {source}"),
+            SpecError::InvalidRegex { source } => write!(f, "invalid regex in stub spec: {source}"),
+            SpecError::InvalidHex { source } => write!(f, "invalid hex in stub spec: {source}"),
+            SpecError::Unsupported(what) => write!(f, "unsupported in stub specs: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpecError::Parse(source) => Some(source),
+            SpecError::InvalidRegex { source } => Some(source),
+            SpecError::InvalidHex { source } => Some(source),
+            SpecError::Unsupported(_) => None,
+        }
+    }
+}
+
+impl Stub {
+    /// Builds the stub described by a YAML or JSON [`StubSpec`].
+    pub fn from_spec(spec: &str) -> Result<Stub, SpecError> {
+        serde_norway::from_str::<StubSpec>(spec)
+            .map_err(SpecError::Parse)?
+            .try_into()
+    }
+
+    /// Builds the stubs of a YAML or JSON list of [`StubSpec`]s, in order.
+    pub fn from_specs(specs: &str) -> Result<Vec<Stub>, SpecError> {
+        serde_norway::from_str::<Vec<StubSpec>>(specs)
+            .map_err(SpecError::Parse)?
+            .into_iter()
+            .map(Stub::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<StubSpec> for Stub {
+    type Error = SpecError;
+
+    fn try_from(spec: StubSpec) -> Result<Self, Self::Error> {
+        match spec {
+            StubSpec::Connect {
+                headers,
+                priority,
+                response,
+            } => {
+                let mut builder = on_connect().with_priority(priority);
+                for (key, matcher) in headers {
+                    builder = builder.with_header(key, matcher.try_into()?);
+                }
+
+                match response {
+                    ResponseSpec::Text(text) => Ok(builder.returning_text(text)),
+                    ResponseSpec::Json(json) => Ok(builder.returning_json(json)),
+                    ResponseSpec::BinaryHex(hex) => Ok(builder.returning_binary(bytes(&hex)?)),
+                    ResponseSpec::Many(responses) => Ok(builder.returning_many(bodies(responses)?)),
+                    ResponseSpec::Reject(status) => Ok(builder.rejecting(status)),
+                    _ => Err(SpecError::Unsupported(
+                        "connect stubs only respond with text, json, binary_hex, many or reject"
+                            .into(),
+                    )),
+                }
+            }
+            StubSpec::Message {
+                headers,
+                priority,
+                body,
+                delay,
+                once,
+                response,
+            } => {
+                let mut builder = on_message().with_priority(priority);
+                for (key, matcher) in headers {
+                    builder = builder.with_header(key, matcher.try_into()?);
+                }
+                builder = match body {
+                    Some(BodyMatcherSpec::Text(matcher)) => {
+                        builder.with_text_like(TextMatcher::try_from(matcher)?)
+                    }
+                    Some(BodyMatcherSpec::JsonEq(json)) => builder.with_json_body_eq(json),
+                    None => builder,
+                };
+                builder = match delay {
+                    Some(DelaySpec::FixedMs(ms)) => {
+                        builder.with_fixed_delay(Duration::from_millis(ms))
+                    }
+                    Some(DelaySpec::IntervalMs(lower, upper)) => builder.with_delay_interval_in(
+                        Duration::from_millis(lower),
+                        Duration::from_millis(upper),
+                    ),
+                    Some(DelaySpec::JitterMs(base, plus_minus)) => builder.with_jitter(
+                        Duration::from_millis(base),
+                        Duration::from_millis(plus_minus),
+                    ),
+                    None => builder,
+                };
+                if once {
+                    builder = builder.once();
+                }
+
+                match response {
+                    ResponseSpec::Text(text) => Ok(builder.returning_text(text)),
+                    ResponseSpec::Json(json) => Ok(builder.returning_json(json)),
+                    ResponseSpec::BinaryHex(hex) => Ok(builder.returning_binary(bytes(&hex)?)),
+                    ResponseSpec::Sequence { responses, cycle } => {
+                        Ok(builder.returning_sequence(bodies(responses)?, cycle))
+                    }
+                    ResponseSpec::Many(responses) => Ok(builder.returning_many(bodies(responses)?)),
+                    ResponseSpec::Echo => Ok(builder.echoing()),
+                    ResponseSpec::Close { code, reason } => {
+                        Ok(builder.returning_close(code, reason))
+                    }
+                    ResponseSpec::Disconnect => Ok(builder.returning_disconnect()),
+                    ResponseSpec::Reject(_) => Err(SpecError::Unsupported(
+                        "reject responses are only valid for connect stubs".into(),
+                    )),
+                }
+            }
+            StubSpec::Periodical {
+                id,
+                headers,
+                priority,
+                delay,
+                responses,
+                looping,
+                take,
+            } => {
+                let mut builder = on_periodical().with_priority(priority);
+                if let Some(id) = id {
+                    builder = builder.with_id(id);
+                }
+                for (key, matcher) in headers {
+                    builder = builder.with_header(key, matcher.try_into()?);
+                }
+                builder = match delay {
+                    DelaySpec::FixedMs(ms) => builder.with_fixed_delay(Duration::from_millis(ms)),
+                    DelaySpec::IntervalMs(lower, upper) => builder.with_delay_interval_in(
+                        Duration::from_millis(lower),
+                        Duration::from_millis(upper),
+                    ),
+                    DelaySpec::JitterMs(base, plus_minus) => builder.with_jitter(
+                        Duration::from_millis(base),
+                        Duration::from_millis(plus_minus),
+                    ),
+                };
+                if looping {
+                    builder = builder.looping();
+                }
+                if let Some(n) = take {
+                    builder = builder.take(n);
+                }
+
+                let mut responses = responses.into_iter();
+                let Some(first) = responses.next() else {
+                    return Err(SpecError::Unsupported(
+                        "periodical stubs without responses".into(),
+                    ));
+                };
+                let mut builder = returning(builder, first)?;
+                for body in responses {
+                    builder = returning(builder, body)?;
+                }
+
+                Ok(builder.build())
+            }
+        }
+    }
+}
+
+fn returning<T>(
+    builder: OnPeriodicalBuilder<T>,
+    body: BodySpec,
+) -> Result<OnPeriodicalBuilder<Ready>, SpecError> {
+    Ok(match body {
+        BodySpec::Text(text) => builder.returning_text(text),
+        BodySpec::Json(json) => builder.returning_json(json),
+        BodySpec::BinaryHex(hex) => builder.returning_binary(bytes(&hex)?),
+    })
+}
+
+fn bodies(specs: Vec<BodySpec>) -> Result<Vec<Body>, SpecError> {
+    specs.into_iter().map(Body::try_from).collect()
+}
+
+fn bytes(hex: &str) -> Result<Vec<u8>, SpecError> {
+    hex_to_bytes(hex).map_err(|source| SpecError::InvalidHex { source })
+}
+
+impl TryFrom<BodySpec> for Body {
+    type Error = SpecError;
+
+    fn try_from(spec: BodySpec) -> Result<Self, Self::Error> {
+        Ok(match spec {
+            BodySpec::Text(text) => Body::PlainText(text),
+            BodySpec::Json(json) => Body::Json(json.into()),
+            BodySpec::BinaryHex(hex) => Body::Binary(bytes(&hex)?),
+        })
+    }
+}
+
+impl TryFrom<TextSpec> for TextMatcher {
+    type Error = SpecError;
+
+    fn try_from(spec: TextSpec) -> Result<Self, Self::Error> {
+        let all = |specs: Vec<TextSpec>| {
+            specs
+                .into_iter()
+                .map(TextMatcher::try_from)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match spec {
+            TextSpec::Eq(text) => TextMatcher::Eq(text),
+            TextSpec::Contains(text) => TextMatcher::Contains(text),
+            TextSpec::NotContains(text) => TextMatcher::NotContains(text),
+            TextSpec::Regex(pattern) => TextMatcher::Regex(
+                cached_regex(&pattern).map_err(|source| SpecError::InvalidRegex { source })?,
+            ),
+            TextSpec::LenEq(len) => TextMatcher::LenEq(len),
+            TextSpec::LenGt(len) => TextMatcher::LenGreaterThan(len),
+            TextSpec::LenLt(len) => TextMatcher::LenLessThan(len),
+            TextSpec::Empty => TextMatcher::Empty,
+            TextSpec::NonEmpty => TextMatcher::NonEmpty,
+            TextSpec::Any => TextMatcher::Any,
+            TextSpec::None => TextMatcher::None,
+            TextSpec::AllOf(specs) => TextMatcher::AllOf(all(specs)?),
+            TextSpec::AnyOf(specs) => TextMatcher::AnyOf(all(specs)?),
+            TextSpec::Not(spec) => TextMatcher::Not(Box::new((*spec).try_into()?)),
+        })
+    }
+}
+
+/// Fails for `TextMatcher::Fn`, closures cannot be written to a spec.
+impl TryFrom<&TextMatcher> for TextSpec {
+    type Error = SpecError;
+
+    fn try_from(matcher: &TextMatcher) -> Result<Self, Self::Error> {
+        let all = |matchers: &[TextMatcher]| {
+            matchers
+                .iter()
+                .map(TextSpec::try_from)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match matcher {
+            TextMatcher::Fn(_) => {
+                return Err(SpecError::Unsupported(
+                    "closure matchers have no spec form".into(),
+                ));
+            }
+            TextMatcher::Eq(text) => TextSpec::Eq(text.clone()),
+            TextMatcher::Contains(text) => TextSpec::Contains(text.clone()),
+            TextMatcher::NotContains(text) => TextSpec::NotContains(text.clone()),
+            TextMatcher::Regex(regex) => TextSpec::Regex(regex.as_str().to_string()),
+            TextMatcher::LenEq(len) => TextSpec::LenEq(*len),
+            TextMatcher::LenGreaterThan(len) => TextSpec::LenGt(*len),
+            TextMatcher::LenLessThan(len) => TextSpec::LenLt(*len),
+            TextMatcher::Empty => TextSpec::Empty,
+            TextMatcher::NonEmpty => TextSpec::NonEmpty,
+            TextMatcher::Any => TextSpec::Any,
+            TextMatcher::None => TextSpec::None,
+            TextMatcher::AllOf(matchers) => TextSpec::AllOf(all(matchers)?),
+            TextMatcher::AnyOf(matchers) => TextSpec::AnyOf(all(matchers)?),
+            TextMatcher::Not(matcher) => TextSpec::Not(Box::new(matcher.as_ref().try_into()?)),
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BinaryHeap, HashMap},
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tungstenite::Message;
+
+use crate::ws::{
+    MIN_READ_TIMEOUT,
+    stubs::{Action, Msg, StubsHandle},
+};
+
+// Longest request head read before giving up on finding its end.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+
+// Wait between peeks while a request head arrives in pieces.
+const HEAD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Head of a plain HTTP request, peeked so it stays unread for the WebSocket handshake.
+pub(crate) struct HttpRequest {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    len: usize,
+}
+
+// `None` for WebSocket upgrades and anything that does not look like an HTTP request, which
+// are left to the handshake. Fails with `TimedOut` when the head is not complete by `deadline`.
+pub(crate) fn peek_request(
+    stream: &TcpStream,
+    deadline: Instant,
+) -> io::Result<Option<HttpRequest>> {
+    let mut buf = vec![0; MAX_HEAD_LEN];
+    let head_len = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(left))?;
+        let peeked = match stream.peek(&mut buf) {
+            Ok(peeked) => peeked,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        };
+        if let Some(end) = buf[..peeked].windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if peeked == 0 || peeked == buf.len() {
+            return Ok(None);
+        }
+        // The rest of the head is still in flight.
+        thread::sleep(HEAD_POLL_INTERVAL);
+    };
+
+    let Ok(head) = std::str::from_utf8(&buf[..head_len]) else {
+        return Ok(None);
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let upgrade = headers
+        .get("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if upgrade {
+        return Ok(None);
+    }
+
+    Ok(Some(HttpRequest {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        headers,
+        len: head_len,
+    }))
+}
+
+// Streams the text messages of the periodical stubs as `data:` events until the client goes
+// away. Binary messages have no SSE form and are skipped.
+pub(crate) fn serve(
+    mut stream: TcpStream,
+    request: HttpRequest,
+    path: &str,
+    stubs_handle: &StubsHandle,
+    poll_interval: Duration,
+) -> io::Result<()> {
+    stream.read_exact(&mut vec![0; request.len])?;
+
+    if request.path.trim_start_matches('/') != path.trim_start_matches('/') {
+        return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    }
+    if request.method != "GET" {
+        return stream.write_all(
+            b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\n\r\n",
+        );
+    }
+
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+          Connection: keep-alive\r\n\r\n",
+    )?;
+
+    let mut messages: BinaryHeap<Msg> = BinaryHeap::new();
+    loop {
+        if let Some(msgs) = stubs_handle.on_periodical(&request.headers) {
+            messages.extend(msgs);
+        }
+
+        let now = Instant::now();
+        while messages.peek().is_some_and(|Msg(_, when, _)| *when <= now) {
+            let Msg(action, ..) = messages
+                .pop()
+                .expect("peek returned Some, so pop must succeed");
+            match action {
+                Action::Send(Message::Text(text)) | Action::Throttle(Message::Text(text), _) => {
+                    let event: String = text
+                        .as_str()
+                        .split('\n')
+                        .map(|line| format!("data: {line}\n"))
+                        .collect();
+                    stream.write_all(format!("{event}\n").as_bytes())?;
+                    stream.flush()?;
+                }
+                Action::Send(Message::Close(_)) | Action::Disconnect => return Ok(()),
+                _ => {}
+            }
+        }
+
+        let wait = messages.peek().map_or(poll_interval, |Msg(_, when, _)| {
+            when.saturating_duration_since(Instant::now())
+                .clamp(MIN_READ_TIMEOUT, poll_interval)
+        });
+        thread::sleep(wait);
+    }
+}
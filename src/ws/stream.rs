@@ -0,0 +1,326 @@
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+// Where connections are accepted from.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub(crate) fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Socket::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Socket::Unix(stream)),
+        }
+    }
+}
+
+// Accepted connection, of either kind of listener.
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// Frame opcode of pongs, in the low bits of the first header byte.
+const PONG_OPCODE: u8 = 0xA;
+
+// Connection socket, which drops the pongs tungstenite queues on its own when auto pong is
+// disabled. Nothing else in the server sends pongs, so every outgoing one is an automatic
+// reply to a client ping.
+pub(crate) struct ClientStream {
+    inner: Socket,
+    drop_pongs: bool,
+    // Header of the frame being written, kept until it is complete.
+    header: Vec<u8>,
+    // Complete header accepted from the caller but not yet written to the socket.
+    pending: Vec<u8>,
+    // Payload bytes left of the frame being written, and whether they are dropped.
+    remaining: u64,
+    dropping: bool,
+    // Reads fail once it passes, however the client spreads out its bytes.
+    deadline: Option<Instant>,
+}
+
+impl ClientStream {
+    pub(crate) fn new(inner: Socket) -> Self {
+        ClientStream {
+            inner,
+            drop_pongs: false,
+            header: Vec::new(),
+            pending: Vec::new(),
+            remaining: 0,
+            dropping: false,
+            deadline: None,
+        }
+    }
+
+    // Only set once the handshake is done, the HTTP exchange before it has no frames.
+    pub(crate) fn set_auto_pong(&mut self, auto_pong: bool) {
+        self.drop_pongs = !auto_pong;
+    }
+
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub(crate) fn socket(&self) -> &Socket {
+        &self.inner
+    }
+
+    // Length of the header once its first two bytes are known, with the extended length and
+    // the mask.
+    fn header_len(&self) -> Option<usize> {
+        let second = *self.header.get(1)?;
+        let extended = match second & 0x7f {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        };
+        let mask = if second & 0x80 == 0 { 0 } else { 4 };
+        Some(2 + extended + mask)
+    }
+
+    fn payload_len(&self) -> u64 {
+        match self.header[1] & 0x7f {
+            126 => u64::from(u16::from_be_bytes([self.header[2], self.header[3]])),
+            127 => u64::from_be_bytes(
+                self.header[2..10]
+                    .try_into()
+                    .expect("extended length has 8 bytes"),
+            ),
+            len => u64::from(len),
+        }
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.inner.write(&self.pending)? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                written => {
+                    self.pending.drain(..written);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.inner.set_read_timeout(Some(left))?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+// The parser state only moves past the bytes reported as written, so a write that fails,
+// such as on a write timeout, can be retried with the same buffer.
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_pending()?;
+        if !self.drop_pongs {
+            return self.inner.write(buf);
+        }
+
+        let mut written = 0;
+        while written < buf.len() {
+            let rest = &buf[written..];
+            if self.remaining > 0 {
+                let len = rest
+                    .len()
+                    .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+                if self.dropping {
+                    self.remaining -= len as u64;
+                    written += len;
+                    continue;
+                }
+                return match self.inner.write(&rest[..len]) {
+                    Ok(len) => {
+                        self.remaining -= len as u64;
+                        Ok(written + len)
+                    }
+                    Err(_) if written > 0 => Ok(written),
+                    Err(err) => Err(err),
+                };
+            }
+
+            self.header.push(rest[0]);
+            written += 1;
+            if self.header_len() != Some(self.header.len()) {
+                continue;
+            }
+            self.remaining = self.payload_len();
+            self.dropping = self.header[0] & 0x0f == PONG_OPCODE;
+            if self.dropping {
+                self.header.clear();
+            } else {
+                self.pending.append(&mut self.header);
+                // The header is accepted either way, and the error comes back on the next
+                // write or flush.
+                if self.write_pending().is_err() {
+                    return Ok(written);
+                }
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use crate::ws::stream::{ClientStream, Socket};
+
+    fn frame(opcode: u8, len: usize) -> Vec<u8> {
+        let mut frame = vec![0x80 | opcode];
+        match len {
+            0..=125 => frame.push(len as u8),
+            126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            _ => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend((0..len).map(|idx| (idx % 251) as u8));
+        frame
+    }
+
+    #[test]
+    fn should_drop_pongs_across_split_and_retried_writes() {
+        let text = frame(0x1, 5);
+        let binary = frame(0x2, 300);
+        let large = frame(0x2, 4 << 20);
+        let frames = [
+            frame(0xA, 3),
+            text.clone(),
+            frame(0xA, 200),
+            binary.clone(),
+            frame(0xA, 70_000),
+            large.clone(),
+            frame(0xA, 0),
+            text.clone(),
+        ];
+        let sent = frames.concat();
+        let expected = [text.clone(), binary, large, text].concat();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut reader, _) = listener.accept().unwrap();
+        // Nothing is read for a while, so the socket buffers fill and writes fail with
+        // `WouldBlock` partway through the large frame.
+        writer.set_nonblocking(true).unwrap();
+        let received = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let mut received = Vec::new();
+            reader.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut stream = ClientStream::new(Socket::Tcp(writer));
+        stream.set_auto_pong(false);
+        let chunks = [1, 2, 3, 5, 8, 13, 4096, 65_536];
+        let (mut offset, mut failures) = (0, 0);
+        for chunk in chunks.iter().cycle() {
+            if offset == sent.len() {
+                break;
+            }
+            let end = sent.len().min(offset + chunk);
+            loop {
+                match stream.write(&sent[offset..end]) {
+                    Ok(written) => {
+                        offset += written;
+                        break;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        failures += 1;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    Err(err) => panic!("{err}"),
+                }
+            }
+        }
+        while let Err(err) = stream.flush() {
+            assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(stream);
+
+        assert!(failures > 0);
+        assert!(received.join().unwrap() == expected);
+    }
+}
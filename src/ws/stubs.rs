@@ -1,53 +1,260 @@
 use std::{
     cell::RefCell,
-    cmp::Ordering,
+    cmp::{Ordering, Reverse},
     collections::HashMap,
+    fs,
     iter::from_fn,
-    sync::{Arc, RwLock},
+    panic::RefUnwindSafe,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering},
+    },
     time::{Duration, Instant},
 };
 
-use rand::Rng;
+use arc_swap::ArcSwap;
+use rand::{
+    Rng, SeedableRng,
+    distr::{Alphanumeric, SampleString},
+    rngs::StdRng,
+};
 use serde_json::Value;
-use tungstenite::{Bytes, Message, Utf8Bytes};
+use tungstenite::{
+    Bytes, Message, Utf8Bytes,
+    http::HeaderMap,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
 
-use crate::matchers::{Body, BodyMatcher, TextMatcher};
+use crate::{
+    json::{Codec, JsonValue},
+    matchers::{Body, BodyMatcher, JsonMatcher, TextMatcher},
+    ws::{
+        IncomingMessage, MatchReport, PATH_HEADER, ServerHandle, StubId, StubScore,
+        builders::{BuildError, MissingPlaceholder},
+    },
+};
 
 #[derive(Default, Clone)]
 pub struct StubsHandle {
-    on_connect: Arc<RwLock<Vec<Stub>>>,
-    on_message: Arc<RwLock<Vec<Stub>>>,
-    on_periodical: Arc<RwLock<Vec<Stub>>>,
+    on_connect: Arc<StubList>,
+    on_message: Arc<StubList>,
+    on_ping: Arc<StubList>,
+    on_pong: Arc<StubList>,
+    on_close: Arc<StubList>,
+    on_periodical: Arc<StubList>,
+    invocations: Arc<SharedInvocations>,
+    rng: Arc<SharedRng>,
+    last_match_report: Arc<Mutex<Option<MatchReport>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+// A stub along with its handle and the number of times it was selected.
+struct RegisteredStub {
+    id: StubId,
+    hits: AtomicUsize,
+    // Highest score the stub can get, computed once as the matchers never change.
+    max_score: u16,
+    stub: Stub,
+}
+
+impl RegisteredStub {
+    fn new(id: StubId, stub: Stub) -> Self {
+        RegisteredStub {
+            id,
+            hits: AtomicUsize::new(0),
+            max_score: stub.max_score(),
+            stub,
+        }
+    }
+}
+
+// Registration swaps in a copy of the list, so readers load the current snapshot without
+// locking and match against it while writers build the next one.
+#[derive(Default)]
+struct StubList(ArcSwap<Vec<Arc<RegisteredStub>>>);
+
+impl StubList {
+    fn snapshot(&self) -> Arc<Vec<Arc<RegisteredStub>>> {
+        self.0.load_full()
+    }
+
+    fn replace(&self, stubs: Vec<Arc<RegisteredStub>>) {
+        self.0.store(Arc::new(stubs));
+    }
+
+    // Retried when another registration swapped the list in the meantime.
+    fn push(&self, stub: RegisteredStub) {
+        let stub = Arc::new(stub);
+        self.0.rcu(|current| {
+            let mut updated = Vec::with_capacity(current.len() + 1);
+            updated.extend(current.iter().cloned());
+            updated.push(Arc::clone(&stub));
+            updated
+        });
+    }
 }
 
+// A panicking thread never leaves the list half updated, so handles stay usable across
+// `catch_unwind` like they were with a lock.
+impl RefUnwindSafe for StubList {}
+
 impl StubsHandle {
-    pub fn register(&self, stub: Stub) {
+    // Random delays and generated ids are drawn from `seed` when given.
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        StubsHandle {
+            rng: Arc::new(seed.map_or_else(SharedRng::default, |seed| {
+                SharedRng(Mutex::new(StdRng::seed_from_u64(seed)))
+            })),
+            ..StubsHandle::default()
+        }
+    }
+
+    pub fn register(&self, mut stub: Stub) -> Result<StubId, Vec<BuildError>> {
+        let errors = stub.take_errors();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        stub.assign_ids(&mut self.rng.lock());
+
+        let id = StubId(self.next_id.fetch_add(1, AtomicOrdering::SeqCst));
+        self.list(&stub).push(RegisteredStub::new(id, stub));
+
+        Ok(id)
+    }
+
+    // Registers `stubs` in place of every current one once all of them are valid. Each list is
+    // swapped in a single write, so matching sees either the previous stubs or the new ones.
+    pub(crate) fn replace(&self, mut stubs: Vec<Stub>) -> Result<Vec<StubId>, Vec<BuildError>> {
+        let errors: Vec<BuildError> = stubs.iter_mut().flat_map(Stub::take_errors).collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let registered: Vec<Arc<RegisteredStub>> = stubs
+            .into_iter()
+            .map(|mut stub| {
+                stub.assign_ids(&mut self.rng.lock());
+                let id = StubId(self.next_id.fetch_add(1, AtomicOrdering::SeqCst));
+                Arc::new(RegisteredStub::new(id, stub))
+            })
+            .collect();
+        for list in self.lists() {
+            list.replace(
+                registered
+                    .iter()
+                    .filter(|registered| ptr::eq(self.list(&registered.stub), list))
+                    .cloned()
+                    .collect(),
+            );
+        }
+
+        Ok(registered.iter().map(|registered| registered.id).collect())
+    }
+
+    fn list(&self, stub: &Stub) -> &StubList {
         match stub {
-            Stub::Connect { .. } => {
-                if let Ok(mut on_connect) = self.on_connect.write() {
-                    on_connect.push(stub);
-                }
-            }
-            Stub::Message { .. } => {
-                if let Ok(mut on_message) = self.on_message.write() {
-                    on_message.push(stub);
-                }
-            }
-            Stub::Periodical { .. } => {
-                if let Ok(mut on_periodical) = self.on_periodical.write() {
-                    on_periodical.push(stub);
-                }
-            }
+            Stub::Connect { .. } => &self.on_connect,
+            Stub::Message { control: None, .. } => &self.on_message,
+            Stub::Message {
+                control: Some(Control::Ping),
+                ..
+            } => &self.on_ping,
+            Stub::Message {
+                control: Some(Control::Pong),
+                ..
+            } => &self.on_pong,
+            Stub::Message {
+                control: Some(Control::Close),
+                ..
+            } => &self.on_close,
+            Stub::Periodical { .. } => &self.on_periodical,
+            Stub::Conversation { .. } => &self.on_message,
         }
     }
 
-    pub(crate) fn on_connect(&self, headers: &HashMap<String, String>) -> Option<Msg> {
-        Self::get_message(&self.on_connect, headers, None)
+    fn lists(&self) -> [&StubList; 6] {
+        [
+            &self.on_connect,
+            &self.on_message,
+            &self.on_ping,
+            &self.on_pong,
+            &self.on_close,
+            &self.on_periodical,
+        ]
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lists()
+            .into_iter()
+            .for_each(|stubs| stubs.replace(Vec::new()));
+    }
+
+    // Times the stub was selected, 0 for unknown ids.
+    pub(crate) fn hits(&self, id: StubId) -> usize {
+        [
+            &self.on_connect,
+            &self.on_message,
+            &self.on_ping,
+            &self.on_pong,
+            &self.on_close,
+            &self.on_periodical,
+        ]
+        .into_iter()
+        .filter_map(|stubs| {
+            stubs
+                .snapshot()
+                .iter()
+                .find(|registered| registered.id == id)
+                .map(|registered| registered.hits.load(AtomicOrdering::SeqCst))
+        })
+        .sum()
+    }
+
+    // Whether a connect stub matches handshakes on `path` through `with_path_glob`.
+    pub(crate) fn accepts_path(&self, path: &str) -> bool {
+        let path = path.to_string();
+        self.on_connect.snapshot().iter().any(|registered| {
+            matches!(&registered.stub, Stub::Connect { headers: Some(headers), .. }
+                if headers
+                    .get(PATH_HEADER)
+                    .is_some_and(|matcher| matcher.score(Some(&path)) > 0))
+        })
+    }
+
+    // Ok holds the message and the extra handshake response headers of the winning stub, Err
+    // the handshake status of a winning rejecting stub.
+    pub(crate) fn on_connect(
+        &self,
+        headers: &HashMap<String, String>,
+    ) -> Result<(Vec<Msg>, HeaderMap), u16> {
+        self.find_stub(&self.on_connect, headers, None, |stub| match stub {
+            Stub::Connect {
+                response: Response::Reject(status),
+                ..
+            } => Err(*status),
+            Stub::Connect {
+                response_headers, ..
+            } => Ok((
+                stub.messages(None, None, &self.invocations, &self.rng),
+                response_headers.clone(),
+            )),
+            stub => Ok((
+                stub.messages(None, None, &self.invocations, &self.rng),
+                HeaderMap::new(),
+            )),
+        })
+        .unwrap_or_else(|| Ok((Vec::new(), HeaderMap::new())))
     }
 
     pub(crate) fn on_periodical(&self, headers: &HashMap<String, String>) -> Option<Vec<Msg>> {
-        let messages: Vec<Msg> =
-            from_fn(|| Self::get_message(&self.on_periodical, headers, None)).collect();
+        let messages: Vec<Msg> = from_fn(|| {
+            let messages = self.get_messages(&self.on_periodical, headers, None);
+            (!messages.is_empty()).then_some(messages)
+        })
+        .flatten()
+        .collect();
 
         (!messages.is_empty()).then_some(messages)
     }
@@ -55,63 +262,406 @@ impl StubsHandle {
     pub(crate) fn on_message(
         &self,
         headers: &HashMap<String, String>,
+        message: IncomingMessage,
+    ) -> Vec<Msg> {
+        let stubs = self.on_message.snapshot();
+        let IncomingMessage { raw, decoded } = message;
+        let mut scores = vec![None; stubs.len()];
+        let raw_text = raw.to_text().ok();
+        let winner = self.best_stub(&stubs, headers, Some(&decoded), raw_text, Some(&mut scores));
+        #[cfg(feature = "tracing")]
+        match winner {
+            Some(index) => tracing::debug!(index, score = ?scores[index], "stub matched"),
+            None => tracing::debug!("no stub matched"),
+        }
+        let msgs = winner.map_or_else(Vec::new, |idx| {
+            stubs[idx]
+                .stub
+                .messages(Some(&decoded), Some(&raw), &self.invocations, &self.rng)
+        });
+
+        *self
+            .last_match_report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(MatchReport {
+            body: decoded,
+            winner,
+            stubs: scores
+                .into_iter()
+                .enumerate()
+                .map(|(index, score)| StubScore { index, score })
+                .collect(),
+        });
+        msgs
+    }
+
+    pub(crate) fn last_match_report(&self) -> Option<MatchReport> {
+        self.last_match_report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn on_control(
+        &self,
+        control: Control,
+        headers: &HashMap<String, String>,
         payload: Body,
-    ) -> Option<Msg> {
-        Self::get_message(&self.on_message, headers, Some(&payload))
+    ) -> Vec<Msg> {
+        let stubs = match control {
+            Control::Ping => &self.on_ping,
+            Control::Pong => &self.on_pong,
+            Control::Close => &self.on_close,
+        };
+        self.get_messages(stubs, headers, Some(&payload))
+    }
+
+    fn get_messages(
+        &self,
+        stubs: &StubList,
+        headers: &HashMap<String, String>,
+        payload: Option<&Body>,
+    ) -> Vec<Msg> {
+        self.find_stub(stubs, headers, payload, |stub| {
+            stub.messages(payload, None, &self.invocations, &self.rng)
+        })
+        .unwrap_or_default()
     }
 
-    fn get_message(
-        stubs: &RwLock<Vec<Stub>>,
+    fn find_stub<R>(
+        &self,
+        stubs: &StubList,
         headers: &HashMap<String, String>,
         payload: Option<&Body>,
-    ) -> Option<Msg> {
-        let mut current_stub: (Option<&Stub>, u16) = (None, 0);
-
-        if let Ok(on_message) = stubs.read() {
-            for stub in on_message.iter() {
-                let score = stub.score(payload, headers);
-                if score > current_stub.1 {
-                    current_stub = (Some(stub), score);
+        f: impl FnOnce(&Stub) -> R,
+    ) -> Option<R> {
+        let stubs = stubs.snapshot();
+        self.best_stub(&stubs, headers, payload, None, None)
+            .map(|idx| f(&stubs[idx].stub))
+    }
+
+    // Index of the winning stub. The score of every stub that was scored is set in `scores`
+    // when given, which must hold one entry per stub.
+    fn best_stub(
+        &self,
+        stubs: &[Arc<RegisteredStub>],
+        headers: &HashMap<String, String>,
+        payload: Option<&Body>,
+        raw_text: Option<&str>,
+        mut scores: Option<&mut Vec<Option<u16>>>,
+    ) -> Option<usize> {
+        // Ties on score go to the highest priority, then to the first registered stub.
+        let rank = |idx: usize, score: u16| (score, stubs[idx].stub.priority(), Reverse(idx));
+
+        // Stubs are scored from the highest they can get down, so scoring stops at the first
+        // one that cannot beat the current winner and the rest are skipped.
+        let mut order: Vec<usize> = (0..stubs.len()).collect();
+        order.sort_by_key(|&idx| Reverse(rank(idx, stubs[idx].max_score)));
+
+        let mut current_stub: Option<(usize, u16)> = None;
+        for idx in order {
+            let registered = &stubs[idx];
+            if let Some((best, best_score)) = current_stub
+                && rank(idx, registered.max_score) < rank(best, best_score)
+            {
+                if registered.max_score < best_score {
+                    break;
                 }
+                continue;
             }
 
-            current_stub.0.map(|stub| stub.message())
-        } else {
-            None
+            let score = registered
+                .stub
+                .score_raw(payload, raw_text, headers, &self.invocations);
+            if let Some(scores) = scores.as_deref_mut() {
+                scores[idx] = Some(score);
+            }
+            let is_better = current_stub
+                .is_none_or(|(best, best_score)| rank(idx, score) > rank(best, best_score));
+            if score > 0 && is_better {
+                current_stub = Some((idx, score));
+            }
         }
+
+        let (idx, _) = current_stub?;
+        stubs[idx].hits.fetch_add(1, AtomicOrdering::SeqCst);
+        Some(idx)
     }
 }
 
 // Stubs
 
+// Counters live per connection thread, so every connection walks periodical and sequence
+// responses from the start. Shared sequences use the counters kept in `StubsHandle` instead.
 thread_local! {
-    static PERIODICALLY_STUBS_INVOCATION_COUNT: RefCell<HashMap<String, usize>> =
+    static STUBS_INVOCATION_COUNT: RefCell<HashMap<String, usize>> =
+        RefCell::new(HashMap::new());
+    // Values set by the stubs answering this connection, read by the ones requiring them.
+    static SESSION_STATE: RefCell<HashMap<String, JsonValue>> = RefCell::new(HashMap::new());
+    // When the last message of each looping periodical stub is due. The next one is only
+    // scheduled after that, so they keep the configured pace.
+    static LOOPING_STUBS_NEXT_AT: RefCell<HashMap<String, Instant>> =
         RefCell::new(HashMap::new());
+    // Number at each monotonic field path in the last message answered by a stub watching it.
+    static LAST_SEQUENCE_NUMBERS: RefCell<HashMap<String, f64>> = RefCell::new(HashMap::new());
+}
+
+// Forgets the state of the connection this thread served, before it serves another one.
+pub(crate) fn reset_connection_state() {
+    STUBS_INVOCATION_COUNT.with(|invocations| invocations.borrow_mut().clear());
+    SESSION_STATE.with(|state| state.borrow_mut().clear());
+    LOOPING_STUBS_NEXT_AT.with(|next_at| next_at.borrow_mut().clear());
+    LAST_SEQUENCE_NUMBERS.with(|numbers| numbers.borrow_mut().clear());
+}
+
+// Number at `path` of a JSON payload, for monotonic field matchers.
+fn sequence_number(path: &str, payload: Option<&Body>) -> Option<f64> {
+    match payload {
+        Some(Body::Json(json)) => match json.get_path(path)? {
+            JsonValue::Int(number) => Some(*number as f64),
+            JsonValue::Float(number) => Some(*number),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+type SharedInvocations = RwLock<HashMap<String, usize>>;
+
+// Longest delay scheduled, delays past the range of `Instant` are capped to it rather than
+// sending right away.
+const MAX_DELAY: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+fn after(delay: Duration) -> Instant {
+    offset_from(Instant::now(), delay)
+}
+
+pub(crate) fn offset_from(base: Instant, delay: Duration) -> Instant {
+    base.checked_add(delay.min(MAX_DELAY)).unwrap_or(base)
+}
+
+// Shared by every connection, so a seeded sequence is only reproducible for a given order of
+// draws across connections.
+pub(crate) struct SharedRng(Mutex<StdRng>);
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        SharedRng(Mutex::new(StdRng::from_os_rng()))
+    }
+}
+
+impl SharedRng {
+    fn lock(&self) -> MutexGuard<'_, StdRng> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn invocations(id: &str, shared: Option<&SharedInvocations>) -> usize {
+    match shared {
+        Some(shared) => shared
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .copied()
+            .unwrap_or(0),
+        None => STUBS_INVOCATION_COUNT
+            .with(|invocations| invocations.borrow().get(id).copied().unwrap_or(0)),
+    }
 }
 
+// Claims the next position under the write lock, so concurrent connections never get the
+// same one.
+fn next_invocation(id: &str, shared: Option<&SharedInvocations>) -> usize {
+    let claim = |map: &mut HashMap<String, usize>| {
+        let current_idx = map.entry(id.to_string()).or_insert(0);
+        let invocation = *current_idx;
+        *current_idx += 1;
+        invocation
+    };
+
+    match shared {
+        Some(shared) => claim(&mut shared.write().unwrap_or_else(PoisonError::into_inner)),
+        None => STUBS_INVOCATION_COUNT.with(|invocations| claim(&mut invocations.borrow_mut())),
+    }
+}
+
+pub type HeadersFn = Box<dyn Fn(&HashMap<String, String>) -> u16 + Send + Sync>;
+
+/// Stub built by the `on_*` builders. It does nothing until registered, and dropping it
+/// unregistered is warned about:
+///
+/// ```compile_fail
+/// #![deny(unused_must_use)]
+/// use anymock::ws::builders::on_message;
+///
+/// on_message().returning_text("Never sent");
+/// ```
+#[must_use = "stubs do nothing until registered with `ServerHandle::register`"]
 pub enum Stub {
     Connect {
         headers: Option<HashMap<String, TextMatcher>>,
-        response: Body,
+        priority: i32,
+        headers_fn: Option<HeadersFn>,
+        response_headers: HeaderMap,
+        response: Response,
+        errors: Vec<BuildError>,
     },
     Message {
+        // Set for stubs answering ping or pong frames instead of data messages.
+        control: Option<Control>,
         request: RequestMatcher,
         delay: Delay,
-        response: Body,
+        throttle: Option<u32>,
+        response: Response,
+        state_updates: HashMap<String, JsonValue>,
+        // Id counting the answers on each connection, set for stubs answering only once.
+        once: Option<String>,
+        priority: i32,
+        errors: Vec<BuildError>,
     },
     Periodical {
         id: String,
         headers: Option<HashMap<String, TextMatcher>>,
+        priority: i32,
         delay: Delay,
         responses: Vec<Body>,
+        shared: bool,
+        looping: bool,
+        take: Option<usize>,
+        duration: Option<Duration>,
+        // Set the first time the stub is evaluated, which starts the `duration` window.
+        started_at: OnceLock<Instant>,
+        errors: Vec<BuildError>,
+    },
+    // Lock-step exchange, each step only matches once the previous ones were answered on the
+    // same connection.
+    Conversation {
+        id: String,
+        steps: Vec<(BodyMatcher, Body)>,
+        // Unexpected messages close the connection instead of being left to other stubs.
+        strict: bool,
+        priority: i32,
+        errors: Vec<BuildError>,
     },
 }
 
 impl Stub {
-    pub fn score(&self, payload: Option<&Body>, session_headers: &HashMap<String, String>) -> u16 {
+    /// Same as `handle.register(stub)`, to end a builder chain with the registration.
+    pub fn register_to(self, handle: &ServerHandle) -> Result<StubId, Vec<BuildError>> {
+        handle.register(self)
+    }
+
+    fn take_errors(&mut self) -> Vec<BuildError> {
         match self {
-            Self::Connect { headers, .. } => {
-                let mut score = 1;
+            Self::Connect { errors, .. }
+            | Self::Message { errors, .. }
+            | Self::Periodical { errors, .. }
+            | Self::Conversation { errors, .. } => std::mem::take(errors),
+        }
+    }
+
+    // Builders leave the ids they generate empty, they are drawn here once the stub is
+    // registered so seeded servers get the same ones on every run.
+    fn assign_ids(&mut self, rng: &mut StdRng) {
+        let mut assign = |id: &mut String| {
+            if id.is_empty() {
+                *id = Alphanumeric.sample_string(rng, 16);
+            }
+        };
+        match self {
+            Self::Message { once, response, .. } => {
+                if let Some(id) = once {
+                    assign(id);
+                }
+                if let Response::Sequence { id, .. } = response {
+                    assign(id);
+                }
+            }
+            Self::Periodical { id, .. } | Self::Conversation { id, .. } => assign(id),
+            Self::Connect { .. } => {}
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        match self {
+            Self::Connect { priority, .. }
+            | Self::Message { priority, .. }
+            | Self::Periodical { priority, .. }
+            | Self::Conversation { priority, .. } => *priority,
+        }
+    }
+
+    pub fn score(
+        &self,
+        payload: Option<&Body>,
+        session_headers: &HashMap<String, String>,
+        shared_invocations: &SharedInvocations,
+    ) -> u16 {
+        self.score_raw(payload, None, session_headers, shared_invocations)
+    }
+
+    // Highest score `score` can return, whatever the message and the connection state.
+    fn max_score(&self) -> u16 {
+        fn headers_max_score(headers: Option<&HashMap<String, TextMatcher>>) -> u16 {
+            headers.map_or(0, |headers| {
+                headers
+                    .values()
+                    .map(TextMatcher::max_score)
+                    .fold(0, u16::saturating_add)
+            })
+        }
+
+        match self {
+            Self::Connect {
+                headers,
+                headers_fn,
+                ..
+            } => match headers_fn {
+                Some(_) => u16::MAX,
+                None => headers_max_score(headers.as_ref()).saturating_add(1),
+            },
+            Self::Message { request, .. } => {
+                let state = request.state.as_ref().map_or(0, |state| {
+                    state
+                        .values()
+                        .map(JsonMatcher::max_score)
+                        .fold(0, u16::saturating_add)
+                });
+                [
+                    1,
+                    headers_max_score(request.headers.as_ref()),
+                    state,
+                    u16::from(request.monotonic.is_some()),
+                    request.payload.as_ref().map_or(0, BodyMatcher::max_score),
+                ]
+                .into_iter()
+                .fold(0, u16::saturating_add)
+            }
+            Self::Periodical { headers, .. } => {
+                headers_max_score(headers.as_ref()).saturating_add(1)
+            }
+            Self::Conversation { steps, .. } => steps
+                .iter()
+                .map(|(matcher, _)| matcher.max_score().saturating_add(1))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    fn score_raw(
+        &self,
+        payload: Option<&Body>,
+        raw_text: Option<&str>,
+        session_headers: &HashMap<String, String>,
+        shared_invocations: &SharedInvocations,
+    ) -> u16 {
+        match self {
+            Self::Connect {
+                headers,
+                headers_fn,
+                ..
+            } => {
+                let mut score: u16 = 1;
                 if let Some(header_matchers) = headers {
                     for (k, matcher) in header_matchers.iter() {
                         let header_score = matcher.score(session_headers.get(k));
@@ -122,9 +672,40 @@ impl Stub {
                         }
                     }
                 }
+                if let Some(headers_fn) = headers_fn {
+                    match headers_fn(session_headers) {
+                        0 => return 0,
+                        headers_score => score = score.saturating_add(headers_score),
+                    }
+                }
                 score
             }
-            Self::Message { request, .. } => {
+            Self::Message {
+                request,
+                response,
+                once,
+                ..
+            } => {
+                if once.as_ref().is_some_and(|id| invocations(id, None) > 0) {
+                    return 0;
+                }
+
+                if let Response::Sequence {
+                    id,
+                    responses,
+                    cycle,
+                } = response
+                {
+                    let is_message_available = if *cycle {
+                        !responses.is_empty()
+                    } else {
+                        invocations(id, None) < responses.len()
+                    };
+                    if !is_message_available {
+                        return 0;
+                    }
+                }
+
                 let mut score = 1;
 
                 if let Some(header_matchers) = request.headers.as_ref() {
@@ -138,8 +719,35 @@ impl Stub {
                     }
                 }
 
+                if let Some(state_matchers) = request.state.as_ref() {
+                    let state_score = SESSION_STATE.with(|state| {
+                        let state = state.borrow();
+                        state_matchers.iter().try_fold(0, |total, (k, matcher)| {
+                            let score = matcher.score(state.get(k));
+                            (score != 0).then_some(total + score)
+                        })
+                    });
+                    match state_score {
+                        Some(state_score) => score += state_score,
+                        None => return 0,
+                    }
+                }
+
+                if let Some(path) = request.monotonic.as_ref() {
+                    let Some(number) = sequence_number(path, payload) else {
+                        return 0;
+                    };
+                    let is_next = LAST_SEQUENCE_NUMBERS.with(|numbers| {
+                        numbers.borrow().get(path).is_none_or(|&last| number > last)
+                    });
+                    if !is_next {
+                        return 0;
+                    }
+                    score += 1;
+                }
+
                 if let Some(payload_matcher) = request.payload.as_ref() {
-                    let payload_score = payload_matcher.score(payload);
+                    let payload_score = payload_matcher.score_raw(payload, raw_text);
                     if payload_score == 0 {
                         return 0;
                     }
@@ -152,6 +760,11 @@ impl Stub {
                 id,
                 headers,
                 responses,
+                shared,
+                looping,
+                take,
+                duration,
+                started_at,
                 ..
             } => {
                 let mut score = 1;
@@ -166,81 +779,372 @@ impl Stub {
                     }
                 }
 
-                let is_message_available =
-                    PERIODICALLY_STUBS_INVOCATION_COUNT.with(|invocations| {
-                        let map = invocations.borrow();
-                        map.get(id.as_str())
-                            .is_none_or(|&invocation| invocation < responses.len())
-                    });
-                if !is_message_available {
+                let invocation = invocations(id, shared.then_some(shared_invocations));
+                let is_expired = duration.is_some_and(|duration| {
+                    started_at.get_or_init(Instant::now).elapsed() >= duration
+                });
+                let is_message_available = if *looping {
+                    LOOPING_STUBS_NEXT_AT.with(|next_at| {
+                        next_at
+                            .borrow()
+                            .get(id.as_str())
+                            .is_none_or(|&next_at| next_at <= Instant::now())
+                    })
+                } else {
+                    invocation < responses.len()
+                };
+                if is_expired
+                    || take.is_some_and(|take| invocation >= take)
+                    || !is_message_available
+                {
                     return 0;
                 }
 
                 score
             }
+            Self::Conversation {
+                id, steps, strict, ..
+            } => {
+                let Some((matcher, _)) = steps.get(invocations(id, None)) else {
+                    return 0;
+                };
+                match matcher.score_raw(payload, raw_text) {
+                    0 if *strict => 1,
+                    0 => 0,
+                    score => score.saturating_add(1),
+                }
+            }
         }
     }
 
-    pub fn message(&self) -> Msg {
+    // Empty when a shared sequence was exhausted by another connection after scoring.
+    pub(crate) fn messages(
+        &self,
+        payload: Option<&Body>,
+        raw: Option<&Message>,
+        shared_invocations: &SharedInvocations,
+        rng: &SharedRng,
+    ) -> Vec<Msg> {
         let available_at = match self {
-            Self::Connect { .. } => Instant::now(),
+            Self::Connect { .. } | Self::Conversation { .. } => Instant::now(),
             Self::Message { delay, .. } | Self::Periodical { delay, .. } => match delay {
-                Delay::Fixed(delay) => Instant::now()
-                    .checked_add(*delay)
-                    .unwrap_or_else(Instant::now),
+                Delay::Fixed(delay) => after(*delay),
 
+                // Drawn as an offset from the lower bound, in nanoseconds so sub-millisecond
+                // bounds keep their precision. An empty range uses the lower bound.
                 Delay::Interval(from, to) => {
-                    let from_as_millis: u64 = from.as_millis().try_into().unwrap_or_default();
-                    let to_as_millis: u64 = to.as_millis().try_into().unwrap_or_default();
-                    Instant::now()
-                        .checked_add(Duration::from_millis(
-                            rand::rng().random_range(from_as_millis..to_as_millis),
-                        ))
-                        .unwrap_or_else(Instant::now)
+                    let width_as_nanos: u64 = to
+                        .saturating_sub(*from)
+                        .as_nanos()
+                        .try_into()
+                        .unwrap_or(u64::MAX);
+                    let offset = if width_as_nanos > 0 {
+                        rng.lock().random_range(0..width_as_nanos)
+                    } else {
+                        0
+                    };
+                    after(from.saturating_add(Duration::from_nanos(offset)))
+                }
+
+                // Drawn in [0, 2 * jitter] and shifted back, so deviations below zero clamp.
+                Delay::Jitter(base, jitter) => {
+                    let jitter_as_nanos: u64 = jitter.as_nanos().try_into().unwrap_or(u64::MAX);
+                    let offset = Duration::from_nanos(
+                        rng.lock()
+                            .random_range(0..=jitter_as_nanos.saturating_mul(2)),
+                    );
+                    after(base.saturating_add(offset).saturating_sub(*jitter))
                 }
             },
         };
-        let response = match self {
-            Self::Connect { response, .. } | Self::Message { response, .. } => response,
-            Self::Periodical { id, responses, .. } => {
-                let message_idx = PERIODICALLY_STUBS_INVOCATION_COUNT.with(|invocations| {
-                    let mut map = invocations.borrow_mut();
-                    let current_idx = map.entry(id.to_string()).or_insert(0);
-                    let message_idx = *current_idx;
-                    *current_idx += 1;
-                    message_idx
-                });
-                responses
-                    .get(message_idx)
-                    .expect("Always should exist message")
+        if let Self::Message { state_updates, .. } = self
+            && !state_updates.is_empty()
+        {
+            SESSION_STATE.with(|state| state.borrow_mut().extend(state_updates.clone()));
+        }
+        if let Self::Message { once: Some(id), .. } = self {
+            next_invocation(id, None);
+        }
+        if let Self::Message {
+            request:
+                RequestMatcher {
+                    monotonic: Some(path),
+                    ..
+                },
+            ..
+        } = self
+            && let Some(number) = sequence_number(path, payload)
+        {
+            LAST_SEQUENCE_NUMBERS.with(|numbers| numbers.borrow_mut().insert(path.clone(), number));
+        }
+
+        let message = match self {
+            Self::Connect { response, .. } | Self::Message { response, .. } => match response {
+                Response::Body(body) => Self::body_message(body),
+                Response::Sequence { id, responses, .. } => {
+                    Self::body_message(&responses[next_invocation(id, None) % responses.len()])
+                }
+                Response::Template(template, missing) => {
+                    match render_template(template, payload, missing) {
+                        Ok(text) => Message::Text(Utf8Bytes::from(text)),
+                        Err(path) => Message::Close(Some(CloseFrame {
+                            code: CloseCode::Error,
+                            reason: Utf8Bytes::from(format!("unknown placeholder '{path}'")),
+                        })),
+                    }
+                }
+                Response::Echo => match (raw, payload) {
+                    (Some(raw), _) => raw.clone(),
+                    (None, Some(payload)) => Self::body_message(payload),
+                    (None, None) => return Vec::new(),
+                },
+                Response::Many(responses) => {
+                    return responses
+                        .iter()
+                        .map(|(offset, body)| {
+                            let when = available_at.checked_add(*offset).unwrap_or(available_at);
+                            self.scheduled(Self::body_message(body), when)
+                        })
+                        .collect();
+                }
+                Response::Close(code, reason) => {
+                    return vec![Msg::new(
+                        Action::Send(Message::Close(Some(CloseFrame {
+                            code: (*code).into(),
+                            reason: Utf8Bytes::from(reason.as_str()),
+                        }))),
+                        available_at,
+                    )];
+                }
+                // Rejections are answered during the handshake, before any frame is sent.
+                Response::Disconnect | Response::Reject(_) => {
+                    return vec![Msg::new(Action::Disconnect, available_at)];
+                }
+            },
+            Self::Periodical {
+                id,
+                responses,
+                shared,
+                looping,
+                take,
+                ..
+            } => {
+                let mut message_idx = next_invocation(id, shared.then_some(shared_invocations));
+                if take.is_some_and(|take| message_idx >= take) {
+                    return Vec::new();
+                }
+                if *looping {
+                    message_idx %= responses.len();
+                    LOOPING_STUBS_NEXT_AT.with(|next_at| {
+                        next_at.borrow_mut().insert(id.to_string(), available_at);
+                    });
+                }
+                let Some(body) = responses.get(message_idx) else {
+                    return Vec::new();
+                };
+                Self::body_message(body)
+            }
+            Self::Conversation { id, steps, .. } => {
+                let Some((matcher, reply)) = steps.get(invocations(id, None)) else {
+                    return Vec::new();
+                };
+                if matcher.score(payload) == 0 {
+                    return vec![Msg::new(
+                        Action::Send(Message::Close(Some(CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: Utf8Bytes::from_static("unexpected message"),
+                        }))),
+                        available_at,
+                    )];
+                }
+                next_invocation(id, None);
+                Self::body_message(reply)
             }
         };
 
-        match response {
-            Body::Json(json) => Msg(
-                Message::Text(Utf8Bytes::from(&Value::from(json).to_string())),
-                available_at,
-            ),
-            Body::PlainText(text) => {
-                Msg(Message::Text(Utf8Bytes::from(text.as_str())), available_at)
-            }
-            Body::Binary(binary) => Msg(Message::Binary(Bytes::from(binary.clone())), available_at),
+        vec![self.scheduled(message, available_at)]
+    }
+
+    fn scheduled(&self, message: Message, when: Instant) -> Msg {
+        match self {
+            Self::Message {
+                throttle: Some(bytes_per_sec),
+                ..
+            } => Msg::new(Action::Throttle(message, *bytes_per_sec), when),
+            _ => Msg::new(Action::Send(message), when),
+        }
+    }
+
+    pub(crate) fn body_message(body: &Body) -> Message {
+        match body {
+            Body::Json(json) => Message::Text(Utf8Bytes::from(&Value::from(json).to_string())),
+            Body::PlainText(text) => Message::Text(Utf8Bytes::from(text.as_str())),
+            Body::Binary(binary) => Message::Binary(Bytes::from(binary.clone())),
+            Body::Encoded {
+                value,
+                codec: Codec::Json,
+            } => Message::Text(Utf8Bytes::from(&Value::from(value).to_string())),
+            #[cfg(feature = "cbor")]
+            Body::Encoded {
+                value,
+                codec: Codec::Cbor,
+            } => Message::Binary(Bytes::from(
+                value.to_cbor().expect("JSON values always encode to CBOR"),
+            )),
+            #[cfg(feature = "msgpack")]
+            Body::Encoded {
+                value,
+                codec: Codec::MessagePack,
+            } => Message::Binary(Bytes::from(
+                value
+                    .to_msgpack()
+                    .expect("JSON values always encode to MessagePack"),
+            )),
+            Body::File(path) => file_message(path),
+        }
+    }
+}
+
+// Files are read once and kept for as long as the process runs. Failed reads are not kept,
+// and close the connection instead of responding.
+fn file_message(path: &Path) -> Message {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Message>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Mutex::default);
+
+    if let Some(message) = cache.lock().ok().and_then(|cache| cache.get(path).cloned()) {
+        return message;
+    }
+    let message = match fs::read(path) {
+        Ok(contents) => match String::from_utf8(contents) {
+            Ok(text) => Message::Text(Utf8Bytes::from(text)),
+            Err(err) => Message::Binary(Bytes::from(err.into_bytes())),
+        },
+        Err(err) => {
+            trace_event!(
+                warn,
+                path = %path.display(),
+                error = %err,
+                "failed to read response file"
+            );
+            #[cfg(not(feature = "tracing"))]
+            let _ = err;
+            return Message::Close(Some(CloseFrame {
+                code: CloseCode::Error,
+                reason: Utf8Bytes::from_static("failed to read response file"),
+            }));
         }
+    };
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(path.to_path_buf(), message.clone());
     }
+    message
+}
+
+// Replaces each `{{path}}` with the value found at that dot path of the JSON payload. Err holds
+// the first unresolved path when missing placeholders are not allowed.
+fn render_template(
+    template: &str,
+    payload: Option<&Body>,
+    missing: &MissingPlaceholder,
+) -> Result<String, String> {
+    let json = match payload {
+        Some(Body::Json(json)) => Some(json),
+        _ => None,
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+
+        let path = rest[start + 2..end].trim();
+        match (json.and_then(|json| json.get_path(path)), missing) {
+            (Some(JsonValue::Str(text)), _) => rendered.push_str(text),
+            (Some(value), _) => rendered.push_str(&Value::from(value).to_string()),
+            (None, MissingPlaceholder::Empty) => {}
+            (None, MissingPlaceholder::Error) => return Err(path.to_string()),
+        }
+
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
 }
 
 pub struct RequestMatcher {
     pub(crate) headers: Option<HashMap<String, TextMatcher>>,
     pub(crate) payload: Option<BodyMatcher>,
+    pub(crate) state: Option<HashMap<String, JsonMatcher>>,
+    // Dot path of a number that must grow from one answered message to the next.
+    pub(crate) monotonic: Option<String>,
+}
+
+pub enum Response {
+    Body(Body),
+    Template(String, MissingPlaceholder),
+    Sequence {
+        id: String,
+        responses: Vec<Body>,
+        cycle: bool,
+    },
+    // Sends the incoming message back.
+    Echo,
+    // Bodies sent at their offset from the stub delay.
+    Many(Vec<(Duration, Body)>),
+    Close(u16, String),
+    Disconnect,
+    Reject(u16),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Control {
+    Ping,
+    Pong,
+    // Matched against `{"code": .., "reason": ..}`, the closing handshake is answered on its own.
+    Close,
 }
 
 pub enum Delay {
     Fixed(Duration),
     Interval(Duration, Duration),
+    // Base delay and the maximum deviation in either direction.
+    Jitter(Duration, Duration),
+}
+
+// Scheduled action, ordered for `BinaryHeap` so the earliest one pops first and actions
+// scheduled at the same instant pop in the order they were created.
+pub struct Msg(pub(crate) Action, pub(crate) Instant, pub(crate) u64);
+
+static NEXT_MSG_SEQ: AtomicU64 = AtomicU64::new(0);
+
+impl Msg {
+    pub(crate) fn new(action: Action, when: Instant) -> Self {
+        Msg(
+            action,
+            when,
+            NEXT_MSG_SEQ.fetch_add(1, AtomicOrdering::Relaxed),
+        )
+    }
 }
 
 #[derive(PartialEq, Eq)]
-pub struct Msg(pub(crate) Message, pub(crate) Instant);
+pub enum Action {
+    Send(Message),
+    Throttle(Message, u32),
+    Disconnect,
+}
+
+impl PartialEq for Msg {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Msg {}
 
 impl PartialOrd for Msg {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
@@ -250,10 +1154,132 @@ impl PartialOrd for Msg {
 
 impl Ord for Msg {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        if self.1 >= other.1 {
-            Ordering::Less
-        } else {
-            Ordering::Greater
+        other.1.cmp(&self.1).then_with(|| other.2.cmp(&self.2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BinaryHeap,
+        time::{Duration, Instant},
+    };
+
+    use tungstenite::Message;
+
+    use crate::ws::{
+        builders::{on_message, on_periodical},
+        stubs::{Action, Msg, SharedInvocations, SharedRng, Stub, StubsHandle},
+    };
+
+    // When the single message of the stub is scheduled, along with the instants just before
+    // and after computing it.
+    fn scheduled_at(stub: Stub) -> (Instant, Instant, Instant) {
+        let before = Instant::now();
+        let messages = stub.messages(
+            None,
+            None,
+            &SharedInvocations::default(),
+            &SharedRng::default(),
+        );
+        let after = Instant::now();
+        let [Msg(_, when, _)] = messages.as_slice() else {
+            panic!("expected a single message");
+        };
+        (before, *when, after)
+    }
+
+    #[test]
+    fn should_keep_sub_millisecond_interval_precision() {
+        for _ in 0..20 {
+            let (before, when, after) = scheduled_at(
+                on_message()
+                    .with_delay_interval_in(Duration::from_micros(500), Duration::from_micros(900))
+                    .returning_text("tick"),
+            );
+            assert!(when >= before + Duration::from_micros(500));
+            assert!(when < after + Duration::from_micros(900));
+        }
+    }
+
+    #[test]
+    fn should_not_truncate_long_delays() {
+        let two_hours = Duration::from_secs(2 * 60 * 60);
+        let (before, when, after) = scheduled_at(
+            on_message()
+                .with_fixed_delay(two_hours)
+                .returning_text("tick"),
+        );
+        assert!(when >= before + two_hours && when <= after + two_hours);
+
+        let (before, when, _) = scheduled_at(
+            on_message()
+                .with_delay_interval_in(two_hours, two_hours * 2)
+                .returning_text("tick"),
+        );
+        assert!(when >= before + two_hours);
+
+        let (before, when, _) = scheduled_at(
+            on_message()
+                .with_fixed_delay(Duration::MAX)
+                .returning_text("tick"),
+        );
+        assert!(when > before + two_hours);
+    }
+
+    #[test]
+    fn should_generate_the_same_ids_from_the_same_seed() {
+        for _ in 0..2 {
+            let handle = StubsHandle::new(Some(7));
+            handle
+                .register(on_periodical().returning_text("tick").build())
+                .unwrap();
+
+            let stubs = handle.on_periodical.snapshot();
+            let Stub::Periodical { id, .. } = &stubs[0].stub else {
+                unreachable!()
+            };
+            assert_eq!(id, "aBJTDJpinRv8mLvQ");
+        }
+    }
+
+    #[test]
+    fn should_pop_msgs_with_same_instant_in_insertion_order() {
+        let when = Instant::now();
+        let mut messages = BinaryHeap::new();
+        for idx in 0..10 {
+            messages.push(Msg::new(Action::Send(Message::text(idx.to_string())), when));
         }
+
+        let popped: Vec<String> = std::iter::from_fn(|| messages.pop())
+            .map(|Msg(action, ..)| match action {
+                Action::Send(msg) => msg.into_text().unwrap().to_string(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            popped,
+            (0..10).map(|idx| idx.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_pop_earliest_msgs_first() {
+        let now = Instant::now();
+        let mut messages = BinaryHeap::new();
+        messages.push(Msg::new(
+            Action::Disconnect,
+            now + Duration::from_millis(20),
+        ));
+        messages.push(Msg::new(Action::Disconnect, now));
+        messages.push(Msg::new(
+            Action::Disconnect,
+            now + Duration::from_millis(10),
+        ));
+
+        let popped: Vec<Instant> = std::iter::from_fn(|| messages.pop())
+            .map(|Msg(_, when, _)| when)
+            .collect();
+        assert!(popped.is_sorted());
     }
 }
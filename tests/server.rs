@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tungstenite::Message;
+
+const PORT: u16 = 18280;
+
+// Kills the server even when an assertion fails.
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn config(reply: &str) -> String {
+    format!(
+        "port: {PORT}
+path: /mock
+stubs:
+  - on: message
+    body: {{ text: {{ eq: ping }} }}
+    response: {{ text: {reply} }}
+"
+    )
+}
+
+#[test]
+fn should_serve_config_stubs_and_reload_them_on_sighup() {
+    let config_path: PathBuf = std::env::temp_dir().join(format!("anymock-{PORT}.yaml"));
+    fs::write(&config_path, config("pong")).unwrap();
+
+    let server = Running(
+        Command::new(env!("CARGO_BIN_EXE_anymock-server"))
+            .arg(&config_path)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let url = format!("ws://127.0.0.1:{PORT}/mock");
+    let started = Instant::now();
+    let (mut client, _) = loop {
+        match tungstenite::connect(&url) {
+            Ok(connected) => break connected,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(err) => panic!("server did not start: {err}"),
+        }
+    };
+    client.send(Message::text("ping")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("pong"));
+
+    fs::write(&config_path, config("reloaded")).unwrap();
+    let status = Command::new("kill")
+        .args(["-HUP", &server.0.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let started = Instant::now();
+    loop {
+        client.send(Message::text("ping")).unwrap();
+        if client.read().unwrap() == Message::text("reloaded") {
+            break;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
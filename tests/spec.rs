@@ -0,0 +1,107 @@
+use std::{sync::atomic::AtomicU16, time::Duration};
+
+use anymock::{
+    Body,
+    matchers::{TextMatcher, text_eq, text_fn},
+    ws::{
+        Server, ServerHandle, Stub,
+        builders::on_message,
+        spec::{SpecError, TextSpec},
+    },
+};
+use serde_json::json;
+use tungstenite::Message;
+
+static NEXT_PORT_ID: AtomicU16 = AtomicU16::new(18180);
+
+const FIXTURE: &str = include_str!("fixtures/stubs.yaml");
+
+#[test]
+fn should_behave_like_programmatic_stubs_when_loaded_from_spec() {
+    let from_spec = listen();
+    for stub in Stub::from_specs(FIXTURE).unwrap() {
+        from_spec.register(stub).unwrap();
+    }
+
+    let programmatic = listen();
+    programmatic
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("pong"),
+        )
+        .unwrap();
+    programmatic
+        .register(
+            on_message()
+                .with_json_body_eq(json!({"action": "subscribe", "channel": "trades"}))
+                .with_fixed_delay(Duration::from_millis(20))
+                .returning_many(vec![
+                    Body::Json(json!({"status": "subscribed"}).into()),
+                    Body::PlainText("ready".to_string()),
+                ]),
+        )
+        .unwrap();
+
+    let requests = [
+        (Message::text("ping"), 1),
+        (
+            Message::text(json!({"action": "subscribe", "channel": "trades"}).to_string()),
+            2,
+        ),
+    ];
+    let replies = |handle: &ServerHandle| {
+        let (mut client, _) = tungstenite::connect(handle.url()).unwrap();
+        let mut replies = Vec::new();
+        for (request, expected) in requests.iter() {
+            client.send(request.clone()).unwrap();
+            for _ in 0..*expected {
+                replies.push(client.read().unwrap());
+            }
+        }
+        replies
+    };
+
+    let replies_from_spec = replies(&from_spec);
+    assert_eq!(replies_from_spec, replies(&programmatic));
+    assert_eq!(replies_from_spec[0], Message::text("pong"));
+    assert_eq!(replies_from_spec[2], Message::text("ready"));
+}
+
+#[test]
+fn should_reject_invalid_specs() {
+    assert!(matches!(
+        Stub::from_spec("on: message\nresponse: { reject: 403 }"),
+        Err(SpecError::Unsupported(_))
+    ));
+    assert!(matches!(
+        Stub::from_spec("on: message\nbody: { text: { regex: '(' } }\nresponse: { text: x }"),
+        Err(SpecError::InvalidRegex { .. })
+    ));
+    assert!(matches!(
+        Stub::from_spec("on: message\nresponse: { binary_hex: abc }"),
+        Err(SpecError::InvalidHex { .. })
+    ));
+    assert!(matches!(
+        Stub::from_spec("on: message\nresponse: { fn: x }"),
+        Err(SpecError::Parse(_))
+    ));
+
+    let closure: TextMatcher = text_fn(|_: Option<&String>| 1);
+    assert!(matches!(
+        TextSpec::try_from(&closure),
+        Err(SpecError::Unsupported(_))
+    ));
+}
+
+fn listen() -> ServerHandle {
+    loop {
+        if let Ok(listener) = Server::default()
+            .port(NEXT_PORT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+            .poll_interval(Duration::from_millis(50))
+            .start()
+        {
+            return listener;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fmt,
+    sync::{Mutex, atomic::AtomicU16},
+    time::Duration,
+};
+
+use anymock::{
+    matchers::text_eq,
+    ws::{Server, ServerHandle, builders::on_message},
+};
+use tracing::{
+    Event, Metadata, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+
+static NEXT_PORT_ID: AtomicU16 = AtomicU16::new(18080);
+static EVENTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Keeps the message of every event emitted by any thread.
+struct Recorder;
+
+struct MessageVisitor(Option<String>);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = MessageVisitor(None);
+        event.record(&mut visitor);
+        if let Some(message) = visitor.0 {
+            EVENTS.lock().unwrap().push(message);
+        }
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn should_emit_stub_matched_event() {
+    tracing::subscriber::set_global_default(Recorder).unwrap();
+
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello"))
+                .returning_text("World"),
+        )
+        .unwrap();
+
+    let (mut client, _) = tungstenite::connect(handle.url()).unwrap();
+    client.send(tungstenite::Message::text("Hello")).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "World");
+
+    let events = EVENTS.lock().unwrap().clone();
+    assert!(events.iter().any(|event| event == "connection accepted"));
+    assert!(events.iter().any(|event| event == "message received"));
+    assert!(events.iter().any(|event| event == "stub matched"));
+    assert!(events.iter().any(|event| event == "message sent"));
+}
+
+fn listen() -> ServerHandle {
+    loop {
+        if let Ok(listener) = Server::default()
+            .port(NEXT_PORT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+            .poll_interval(Duration::from_millis(50))
+            .start()
+        {
+            return listener;
+        }
+    }
+}
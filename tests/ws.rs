@@ -1,20 +1,34 @@
 use std::{
     collections::HashMap,
-    net::TcpStream,
+    io::{Read, Write},
+    net::{Ipv6Addr, TcpStream},
     sync::atomic::AtomicU16,
     time::{Duration, Instant},
 };
 
 use anymock::{
-    json::JsonValue,
-    json_object,
-    matchers::{int_gt, text_contains, text_eq, text_len_eq},
+    AnymockError, Body,
+    json::{Codec, JsonValue},
+    json_object, json_value,
+    matchers::{
+        BodyMatcher, HexError, binary_eq, bool_eq, int_eq, int_gt, int_lt, json_optional,
+        text_contains, text_eq, text_len_eq,
+    },
     ws::{
-        Server, ServerHandle,
-        builders::{on_connect, on_message, on_periodical},
+        SendToError, Server, ServerEvent, ServerHandle, UnmatchedBehavior,
+        builders::{
+            BuildError, DelayError, MissingPlaceholder, conversation, on_close, on_connect,
+            on_message, on_periodical, on_ping, on_pong,
+        },
     },
 };
-use tungstenite::{Message, WebSocket, handshake::client::Request, stream::MaybeTlsStream};
+use tungstenite::{
+    Message, WebSocket,
+    error::ProtocolError,
+    handshake::client::Request,
+    protocol::{CloseFrame, Role, frame::coding::CloseCode},
+    stream::MaybeTlsStream,
+};
 
 macro_rules! map {
     ( $( $key:expr => $value:expr ),* $(,)? ) => {{
@@ -34,7 +48,9 @@ fn should_returns_on_connect_when_no_headers_matchers_defined() {
 
     let handle = listen();
 
-    handle.register(on_connect().returning_text(OUTPUT_MESSAGE));
+    handle
+        .register(on_connect().returning_text(OUTPUT_MESSAGE))
+        .unwrap();
 
     let mut client = connect(&handle);
     let msg = client.read().unwrap();
@@ -48,11 +64,13 @@ fn should_returns_on_connect_when_headers_matchers_defined() {
 
     let handle = listen();
 
-    handle.register(
-        on_connect()
-            .with_header("authorization", text_eq("AAABBBCCCDDD"))
-            .returning_text(OUTPUT_MESSAGE),
-    );
+    handle
+        .register(
+            on_connect()
+                .with_header("authorization", text_eq("AAABBBCCCDDD"))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
 
     let mut client = connect_hdr(
         &handle,
@@ -66,6 +84,231 @@ fn should_returns_on_connect_when_headers_matchers_defined() {
     assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
 }
 
+#[test]
+fn should_register_stub_at_the_end_of_the_builder_chain() {
+    let handle = listen();
+
+    let id = on_message()
+        .with_text_like(text_eq("ping"))
+        .returning_text("pong")
+        .register_to(&handle)
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::text("ping")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("pong"));
+    assert_eq!(handle.stub_hits(id), 1);
+
+    let errors = on_message()
+        .returning_binary_hex("abc")
+        .register_to(&handle)
+        .unwrap_err();
+    assert!(matches!(errors.as_slice(), [BuildError::InvalidHex { .. }]));
+}
+
+#[test]
+fn should_returns_on_connect_when_header_matching_regex() {
+    const OUTPUT_MESSAGE: &str = "Just works with header regex!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_connect()
+                .with_header_matching("authorization", "^Bearer .+$")
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect_hdr(
+        &handle,
+        map![
+            "Authorization" => "Bearer AAABBBCCCDDD",
+        ],
+    );
+
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+}
+
+#[test]
+fn should_returns_on_connect_when_header_present_or_absent() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_connect()
+                .with_header_present("authorization")
+                .returning_text("authenticated"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_connect()
+                .with_header_absent("authorization")
+                .returning_text("anonymous"),
+        )
+        .unwrap();
+
+    let mut client = connect_hdr(
+        &handle,
+        map![
+            "Authorization" => "",
+        ],
+    );
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "authenticated");
+
+    let mut client = connect(&handle);
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "anonymous");
+}
+
+#[test]
+fn should_returns_on_connect_when_header_not_ascii() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_connect()
+                .with_header("x-city", text_eq("Málaga"))
+                .returning_text("utf-8"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_connect()
+                .with_header("x-city", text_eq("M\u{FFFD}laga"))
+                .returning_text("replaced"),
+        )
+        .unwrap();
+
+    // Written by hand, the tungstenite client refuses header values outside visible ASCII.
+    let connect_with = |value: &[u8]| {
+        let mut stream = TcpStream::connect(("127.0.0.1", handle.port())).unwrap();
+        let mut request = b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: Upgrade\r\n\
+            Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nx-city: "
+            .to_vec();
+        request.extend_from_slice(value);
+        request.extend_from_slice(b"\r\n\r\n");
+        stream.write_all(&request).unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+        WebSocket::from_raw_socket(stream, Role::Client, None)
+    };
+
+    let mut client = connect_with("Málaga".as_bytes());
+    assert_eq!(client.read().unwrap(), Message::text("utf-8"));
+
+    let mut client = connect_with(b"M\xe1laga");
+    assert_eq!(client.read().unwrap(), Message::text("replaced"));
+}
+
+#[test]
+fn should_reject_registration_when_header_regex_invalid() {
+    let handle = listen();
+
+    let errors = handle
+        .register(
+            on_message()
+                .with_header_matching("authorization", "Bearer (")
+                .returning_text("Never returned"),
+        )
+        .unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        &errors[0],
+        BuildError::InvalidHeaderRegex { key, .. } if key == "authorization"
+    ));
+}
+
+#[test]
+fn should_convert_errors_into_anymock_error() {
+    fn register_invalid(handle: &ServerHandle) -> Result<(), AnymockError> {
+        handle.register(
+            on_message()
+                .with_header_matching("authorization", "Bearer (")
+                .returning_text("Never returned"),
+        )?;
+        Ok(())
+    }
+
+    let handle = listen();
+
+    match register_invalid(&handle) {
+        Err(AnymockError::Build(errors)) => assert!(matches!(
+            errors.as_slice(),
+            [BuildError::InvalidHeaderRegex { key, .. }] if key == "authorization"
+        )),
+        other => panic!("Expected build errors, received {other:?}"),
+    }
+    assert!(matches!(
+        Server::default().port(handle.port()).start(),
+        Err(AnymockError::Bind(_))
+    ));
+}
+
+#[test]
+fn should_convert_delay_and_timeout_errors_into_anymock_error() {
+    fn delayed() -> Result<(), AnymockError> {
+        on_periodical()
+            .try_with_delay_interval_in(Duration::from_secs(2), Duration::from_secs(1))?;
+        Ok(())
+    }
+    fn wait(handle: &ServerHandle) -> Result<(), AnymockError> {
+        handle.wait_for_message(
+            BodyMatcher::PlainText(text_eq("never sent")),
+            Duration::from_millis(50),
+        )?;
+        Ok(())
+    }
+
+    assert!(matches!(
+        delayed(),
+        Err(AnymockError::Delay(DelayError::Inverted { .. }))
+    ));
+    assert!(matches!(wait(&listen()), Err(AnymockError::Timeout(_))));
+}
+
+#[test]
+fn should_reject_connection_when_rejecting_stub_matches() {
+    let handle = listen();
+
+    handle
+        .register(on_connect().returning_text("Welcome!"))
+        .unwrap();
+    handle
+        .register(
+            on_connect()
+                .with_header("authorization", text_eq("expired"))
+                .rejecting(401),
+        )
+        .unwrap();
+
+    let result = try_connect_hdr(
+        &handle,
+        map![
+            "Authorization" => "expired",
+        ],
+    );
+    match result {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 401),
+        Err(err) => panic!("Expected HTTP error, received {err:?}"),
+        Ok(_) => panic!("Expected handshake to be rejected"),
+    }
+
+    let mut client = connect(&handle);
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), "Welcome!");
+}
+
 #[test]
 fn should_returns_on_connect_message_with_highest_priority() {
     const OUTPUT_MESSAGE: &str = "Lower priority stub";
@@ -74,25 +317,31 @@ fn should_returns_on_connect_message_with_highest_priority() {
 
     let handle = listen();
 
-    handle.register(
-        on_connect()
-            .with_header("authorization", text_eq("AAABBBCCCDDD"))
-            .returning_text(OUTPUT_MESSAGE),
-    );
+    handle
+        .register(
+            on_connect()
+                .with_header("authorization", text_eq("AAABBBCCCDDD"))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
 
-    handle.register(
-        on_connect()
-            .with_header("authorization", text_eq("AAABBBCCCDDD"))
-            .with_header("dummy-header", text_contains("mm"))
-            .returning_text(OUTPUT_MESSAGE_2),
-    );
+    handle
+        .register(
+            on_connect()
+                .with_header("authorization", text_eq("AAABBBCCCDDD"))
+                .with_header("dummy-header", text_contains("mm"))
+                .returning_text(OUTPUT_MESSAGE_2),
+        )
+        .unwrap();
 
-    handle.register(
-        on_connect()
-            .with_header("authorization", text_eq("AAABBBCCCDDD"))
-            .with_header("dummy-header", text_eq("Dummy"))
-            .returning_text(OUTPUT_MESSAGE_3),
-    );
+    handle
+        .register(
+            on_connect()
+                .with_header("authorization", text_eq("AAABBBCCCDDD"))
+                .with_header("dummy-header", text_eq("Dummy"))
+                .returning_text(OUTPUT_MESSAGE_3),
+        )
+        .unwrap();
 
     let mut client = connect_hdr(
         &handle,
@@ -107,6 +356,154 @@ fn should_returns_on_connect_message_with_highest_priority() {
     assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE_3);
 }
 
+#[test]
+fn should_returns_many_on_connect_in_order() {
+    let handle = listen();
+
+    handle
+        .register(on_connect().returning_many_delayed([
+            (Duration::ZERO, Body::PlainText("welcome".to_string())),
+            (
+                Duration::from_millis(200),
+                Body::Json(json_value!({"state": "ready"})),
+            ),
+        ]))
+        .unwrap();
+
+    let now = Instant::now();
+    let mut client = connect(&handle);
+
+    assert_eq!(client.read().unwrap(), Message::text("welcome"));
+    assert_eq!(
+        client.read().unwrap(),
+        Message::text(r#"{"state":"ready"}"#)
+    );
+    assert!(now.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn should_returns_on_message_only_from_message_stubs() {
+    const CONNECT_MESSAGE: &str = "Welcome!";
+    const OUTPUT_MESSAGE: &str = "Just works with message stubs!";
+
+    let handle = listen();
+
+    handle
+        .register(on_connect().returning_text(CONNECT_MESSAGE))
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("hello"))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), CONNECT_MESSAGE);
+
+    client.send(Message::Text("hello".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    // Connect stubs are never scored against incoming messages.
+    client.send(Message::Text("unknown".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_reject_replayed_sequence_numbers_on_message() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_monotonic_json_field("$.seq")
+                .returning_text("Accepted"),
+        )
+        .unwrap();
+    handle
+        .register(on_message().returning_text("Out of order"))
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for (request, expected) in [
+        (r#"{"seq": 1}"#, "Accepted"),
+        (r#"{"seq": 2}"#, "Accepted"),
+        (r#"{"seq": 2}"#, "Out of order"),
+        (r#"{"seq": 1}"#, "Out of order"),
+        (r#"{"seq": 5}"#, "Accepted"),
+        (r#"{"other": 6}"#, "Out of order"),
+    ] {
+        client.send(Message::Text(request.into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+    }
+
+    // Each connection has a sequence of its own.
+    let mut client = connect(&handle);
+
+    client.send(Message::Text(r#"{"seq": 1}"#.into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Accepted");
+}
+
+#[test]
+fn should_returns_on_message_when_session_state_required() {
+    const WELCOME_MESSAGE: &str = "Welcome!";
+    const DATA_MESSAGE: &str = "Here is your data";
+    const UNAUTHORIZED_MESSAGE: &str = "Login first";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["type" => text_eq("login")])
+                .setting_state("authed", true)
+                .returning_text(WELCOME_MESSAGE),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["type" => text_eq("data")])
+                .requiring_state("authed", bool_eq(true))
+                .returning_text(DATA_MESSAGE),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["type" => text_eq("data")])
+                .returning_text(UNAUTHORIZED_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for (request, expected) in [
+        (r#"{"type": "data"}"#, UNAUTHORIZED_MESSAGE),
+        (r#"{"type": "login"}"#, WELCOME_MESSAGE),
+        (r#"{"type": "data"}"#, DATA_MESSAGE),
+    ] {
+        client.send(Message::Text(request.into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+    }
+
+    // The session belongs to the connection, a new client starts logged out.
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::Text(r#"{"type": "data"}"#.into()))
+        .unwrap();
+    assert_eq!(
+        client.read().unwrap().into_text().unwrap(),
+        UNAUTHORIZED_MESSAGE
+    );
+}
+
 #[test]
 fn should_returns_on_message_when_json_body_eq() {
     const OUTPUT_MESSAGE: &str = "Just works!";
@@ -120,11 +517,13 @@ fn should_returns_on_message_when_json_body_eq() {
 
     let handle = listen();
 
-    handle.register(
-        on_message()
-            .with_json_body_eq(JsonValue::try_from(JSON).unwrap())
-            .returning_text(OUTPUT_MESSAGE),
-    );
+    handle
+        .register(
+            on_message()
+                .with_json_body_eq(JsonValue::try_from(JSON).unwrap())
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
 
     let mut client = connect(&handle);
 
@@ -147,11 +546,13 @@ fn should_returns_on_message_when_json_body_like() {
 
     let handle = listen();
 
-    handle.register(
-        on_message()
-            .with_json_body_like(json_object!["name" => text_len_eq(4), "age" => int_gt(20) ])
-            .returning_text(OUTPUT_MESSAGE),
-    );
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["name" => text_len_eq(4), "age" => int_gt(20) ])
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
 
     let mut client = connect(&handle);
 
@@ -161,105 +562,2657 @@ fn should_returns_on_message_when_json_body_like() {
     assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
 }
 
+#[cfg(feature = "json-schema")]
 #[test]
-fn should_returns_on_message_when_fixed_delay_applied() {
-    const LOWER_DELAY_MESSAGE: &str = "Just works with lower delay!";
-    const HIGHER_DELAY_MESSAGE: &str = "Just works with higher delay!";
+fn should_returns_on_message_when_json_schema_valid() {
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "required": ["kind", "qty"],
+        "properties": {
+            "kind": { "enum": ["buy", "sell"] },
+            "qty": { "type": "integer", "minimum": 1 }
+        }
+    }"#;
 
-    let now = Instant::now();
-    let lower_delay = Duration::from_secs(1);
-    let higher_delay = Duration::from_secs(3);
     let handle = listen();
 
-    handle.register(
-        on_message()
-            .with_text_like(text_eq(LOWER_DELAY_MESSAGE))
-            .with_fixed_delay(lower_delay)
-            .returning_text(LOWER_DELAY_MESSAGE),
-    );
-
-    handle.register(
-        on_message()
-            .with_text_like(text_eq(HIGHER_DELAY_MESSAGE))
-            .with_fixed_delay(higher_delay)
-            .returning_text(HIGHER_DELAY_MESSAGE),
-    );
+    handle
+        .register(
+            on_message()
+                .with_json_schema(SCHEMA)
+                .returning_text("accepted"),
+        )
+        .unwrap();
 
     let mut client = connect(&handle);
 
     client
-        .send(Message::Text(LOWER_DELAY_MESSAGE.into()))
+        .send(Message::text(r#"{"kind": "sell", "qty": 3}"#))
         .unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("accepted"));
+
     client
-        .send(Message::Text(HIGHER_DELAY_MESSAGE.into()))
+        .send(Message::text(r#"{"kind": "sell", "qty": -3}"#))
         .unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
 
-    let msg = client.read().unwrap();
-    assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), LOWER_DELAY_MESSAGE);
-    assert!(now.checked_add(lower_delay).unwrap() <= Instant::now());
+    let errors = handle
+        .register(
+            on_message()
+                .with_json_schema(r#"{"type": 1}"#)
+                .returning_text("never"),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [BuildError::InvalidJsonSchema { .. }]
+    ));
+}
 
+#[test]
+fn should_returns_on_message_when_int_body_like() {
+    const OUTPUT_MESSAGE: &str = "Greater than forty!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_int_like(int_gt(40))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_int_like(int_lt(40))
+                .returning_text("Less than forty!"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_bool_like(bool_eq(true))
+                .returning_text("Boolean!"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("42".into())).unwrap();
     let msg = client.read().unwrap();
     assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), HIGHER_DELAY_MESSAGE);
-    assert!(now.checked_add(higher_delay).unwrap() <= Instant::now());
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
 }
 
+#[cfg(feature = "cbor")]
 #[test]
-fn should_returns_on_periodical() {
-    const MESSAGE_1: &str = "Just works with first message!";
-    const MESSAGE_2: &str = "Just works with second message!";
+fn should_returns_on_message_when_cbor_body_like() {
+    const OUTPUT_MESSAGE: &str = "Just works with CBOR!";
 
     let handle = listen();
 
-    handle.register(
-        on_periodical()
-            .with_fixed_delay(Duration::from_millis(200))
-            .returning_text(MESSAGE_1)
-            .returning_text(MESSAGE_2)
-            .build(),
-    );
+    handle
+        .register(
+            on_message()
+                .with_cbor_body_like(json_object!["name" => text_eq("John"), "age" => int_gt(20)])
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
 
-    let mut client_1 = connect(&handle);
-    let mut client_2 = connect(&handle);
+    let mut payload = Vec::new();
+    ciborium::into_writer(
+        &ciborium::Value::Map(vec![
+            ("name".into(), "John".into()),
+            ("age".into(), 30.into()),
+        ]),
+        &mut payload,
+    )
+    .unwrap();
 
-    let msg = client_1.read().unwrap();
-    assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), MESSAGE_1);
+    let mut client = connect(&handle);
 
-    let msg = client_1.read().unwrap();
+    client.send(Message::Binary(payload.into())).unwrap();
+    let msg = client.read().unwrap();
     assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), MESSAGE_2);
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+}
 
-    let msg = client_2.read().unwrap();
-    assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), MESSAGE_1);
+#[cfg(feature = "msgpack")]
+#[test]
+fn should_returns_msgpack_on_message_when_msgpack_body_like() {
+    let handle = listen();
 
-    let msg = client_2.read().unwrap();
-    assert!(msg.is_text());
-    assert_eq!(msg.into_text().unwrap(), MESSAGE_2);
+    handle
+        .register(
+            on_message()
+                .with_msgpack_body_like(
+                    json_object!["name" => text_eq("John"), "age" => int_gt(20)],
+                )
+                .returning_msgpack(JsonValue::Object(HashMap::from([(
+                    "status".to_string(),
+                    JsonValue::Str("ok".to_string()),
+                )]))),
+        )
+        .unwrap();
+
+    let payload = rmp_serde::to_vec(&serde_json::json!({"name": "John", "age": 30})).unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Binary(payload.into())).unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_binary());
+    let response = rmp_serde::from_slice::<serde_json::Value>(&msg.into_data()).unwrap();
+    assert_eq!(response, serde_json::json!({"status": "ok"}));
 }
 
-fn listen() -> ServerHandle {
-    loop {
-        if let Ok(listener) = Server::default()
-            .port(NEXT_PORT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
-            .start()
-        {
-            return listener;
+#[test]
+fn should_returns_on_message_when_custom_body_matcher_scores() {
+    // Value of the varint field 1 in a protobuf encoded message.
+    fn field_one(bytes: &[u8]) -> Option<u64> {
+        let (&tag, mut rest) = bytes.split_first()?;
+        if tag != 0x08 {
+            return None;
         }
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, tail) = rest.split_first()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+            rest = tail;
+        }
+        None
     }
-}
 
-fn connect(handle: &ServerHandle) -> WebSocket<MaybeTlsStream<TcpStream>> {
-    connect_hdr(handle, HashMap::new())
-}
+    let handle = listen();
 
-fn connect_hdr(
-    handle: &ServerHandle,
-    headers: HashMap<&str, &str>,
-) -> WebSocket<MaybeTlsStream<TcpStream>> {
-    let conn_string = format!("ws://{}:{}", handle.addr(), handle.port());
+    handle
+        .register(
+            on_message()
+                .with_body_fn(|body: Option<&Body>| match body {
+                    Some(Body::Binary(bytes)) if field_one(bytes) == Some(150) => 5,
+                    _ => 0,
+                })
+                .returning_text("order 150"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::binary(vec![0x08, 0x96, 0x01]))
+        .unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("order 150"));
+
+    client
+        .send(Message::binary(vec![0x08, 0x97, 0x01]))
+        .unwrap();
+    client.send(Message::text("150")).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_returns_null_on_message_when_json_float_not_finite() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("nan"))
+                .returning_json(JsonValue::List(vec![
+                    JsonValue::Float(f64::NAN),
+                    JsonValue::Float(f64::INFINITY),
+                    JsonValue::Float(1.5),
+                ])),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("nan".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), "[null,null,1.5]");
+}
+
+#[test]
+fn should_returns_text_on_message_when_encoded_as_json() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("status"))
+                .returning_encoded(
+                    JsonValue::Object(HashMap::from([(
+                        "status".to_string(),
+                        JsonValue::Str("ok".to_string()),
+                    )])),
+                    Codec::Json,
+                ),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("status".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), r#"{"status":"ok"}"#);
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn should_returns_cbor_on_message_when_encoded() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("status"))
+                .returning_encoded(
+                    JsonValue::Object(HashMap::from([(
+                        "status".to_string(),
+                        JsonValue::Str("ok".to_string()),
+                    )])),
+                    Codec::Cbor,
+                ),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("status".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_binary());
+    let response = ciborium::from_reader::<serde_json::Value, _>(&msg.into_data()[..]).unwrap();
+    assert_eq!(response, serde_json::json!({"status": "ok"}));
+}
+
+#[test]
+fn should_returns_on_message_when_fixed_delay_applied() {
+    const LOWER_DELAY_MESSAGE: &str = "Just works with lower delay!";
+    const HIGHER_DELAY_MESSAGE: &str = "Just works with higher delay!";
+
+    let now = Instant::now();
+    let lower_delay = Duration::from_secs(1);
+    let higher_delay = Duration::from_secs(3);
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(LOWER_DELAY_MESSAGE))
+                .with_fixed_delay(lower_delay)
+                .returning_text(LOWER_DELAY_MESSAGE),
+        )
+        .unwrap();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(HIGHER_DELAY_MESSAGE))
+                .with_fixed_delay(higher_delay)
+                .returning_text(HIGHER_DELAY_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::Text(LOWER_DELAY_MESSAGE.into()))
+        .unwrap();
+    client
+        .send(Message::Text(HIGHER_DELAY_MESSAGE.into()))
+        .unwrap();
+
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), LOWER_DELAY_MESSAGE);
+    assert!(now.checked_add(lower_delay).unwrap() <= Instant::now());
+
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), HIGHER_DELAY_MESSAGE);
+    assert!(now.checked_add(higher_delay).unwrap() <= Instant::now());
+}
+
+#[test]
+fn should_returns_shorter_delay_first_when_sent_last() {
+    const LOWER_DELAY_MESSAGE: &str = "Just works with lower delay!";
+    const HIGHER_DELAY_MESSAGE: &str = "Just works with higher delay!";
+
+    let now = Instant::now();
+    let lower_delay = Duration::from_millis(100);
+    let higher_delay = Duration::from_millis(600);
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(HIGHER_DELAY_MESSAGE))
+                .with_fixed_delay(higher_delay)
+                .returning_text(HIGHER_DELAY_MESSAGE),
+        )
+        .unwrap();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq(LOWER_DELAY_MESSAGE))
+                .with_fixed_delay(lower_delay)
+                .returning_text(LOWER_DELAY_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::Text(HIGHER_DELAY_MESSAGE.into()))
+        .unwrap();
+    client
+        .send(Message::Text(LOWER_DELAY_MESSAGE.into()))
+        .unwrap();
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), LOWER_DELAY_MESSAGE);
+    assert!(now + lower_delay <= Instant::now());
+    assert!(now + higher_delay > Instant::now());
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), HIGHER_DELAY_MESSAGE);
+    assert!(now + higher_delay <= Instant::now());
+}
+
+#[test]
+fn should_returns_file_contents_on_message() {
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/snapshot.json");
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("snapshot"))
+                .returning_file(fixture),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for _ in 0..2 {
+        client.send(Message::text("snapshot")).unwrap();
+        assert_eq!(
+            client.read().unwrap(),
+            Message::text(include_str!("fixtures/snapshot.json"))
+        );
+    }
+
+    let errors = handle
+        .register(on_message().returning_file("tests/fixtures/missing.json"))
+        .unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [BuildError::UnreadableFile { path, .. }] if path.ends_with("missing.json")
+    ));
+}
+
+#[test]
+fn should_returns_on_message_when_template_echoes_request() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["name" => text_len_eq(4)])
+                .returning_template(r#"{"echo": "{{name}}", "city": "{{address.city}}"}"#),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::Text(r#"{"name": "John"}"#.into()))
+        .unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), r#"{"echo": "John", "city": ""}"#);
+}
+
+#[test]
+fn should_close_connection_on_message_when_template_placeholder_missing() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object!["name" => text_len_eq(4)])
+                .returning_template_with("Hello {{surname}}", MissingPlaceholder::Error),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client
+        .send(Message::Text(r#"{"name": "John"}"#.into()))
+        .unwrap();
+    match client.read().unwrap() {
+        Message::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1011);
+            assert_eq!(frame.reason.as_str(), "unknown placeholder 'surname'");
+        }
+        other => panic!("Expected close frame, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_returns_sequence_on_message_until_exhausted() {
+    const FALLBACK_MESSAGE: &str = "No more replies";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("next"))
+                .returning_sequence(
+                    vec![
+                        Body::PlainText("first".to_string()),
+                        Body::PlainText("second".to_string()),
+                        Body::PlainText("third".to_string()),
+                    ],
+                    false,
+                ),
+        )
+        .unwrap();
+    handle
+        .register(on_message().returning_text(FALLBACK_MESSAGE))
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for expected in ["first", "second", "third", FALLBACK_MESSAGE] {
+        client.send(Message::Text("next".into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+    }
+}
+
+#[test]
+fn should_returns_sequence_on_message_cycling() {
+    let handle = listen();
+
+    handle
+        .register(on_message().returning_sequence(
+            vec![
+                Body::PlainText("ping".to_string()),
+                Body::PlainText("pong".to_string()),
+            ],
+            true,
+        ))
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for expected in ["ping", "pong", "ping"] {
+        client.send(Message::Text("next".into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+    }
+}
+
+#[test]
+fn should_close_connection_on_message_when_returning_close() {
+    const CLOSE_REASON: &str = "Internal error";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("close me"))
+                .returning_close(1011, CLOSE_REASON),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("close me".into())).unwrap();
+    let msg = client.read().unwrap();
+    match msg {
+        Message::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1011);
+            assert_eq!(frame.reason.as_str(), CLOSE_REASON);
+        }
+        other => panic!("Expected close frame, received {other:?}"),
+    }
+
+    assert!(matches!(
+        client.read(),
+        Err(tungstenite::Error::ConnectionClosed)
+    ));
+}
+
+#[test]
+fn should_drop_connection_when_close_is_not_acknowledged() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("close me"))
+                .returning_close(1011, "Internal error"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("close me".into())).unwrap();
+    assert!(client.read().unwrap().is_close());
+
+    // Reading again would flush the acknowledgement tungstenite queued, so the raw socket
+    // is read instead.
+    let MaybeTlsStream::Plain(stream) = client.get_mut() else {
+        panic!("Expected a plain TCP stream");
+    };
+    stream
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    wait_until(|| handle.connection_count() == 0);
+}
+
+#[test]
+fn should_drop_connection_on_message_when_returning_disconnect() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("drop me"))
+                .returning_disconnect(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Text("drop me".into())).unwrap();
+    match client.read() {
+        Err(tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake))
+        | Err(tungstenite::Error::Io(_)) => {}
+        other => panic!("Expected connection reset, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_keep_serving_when_client_leaves_before_response() {
+    const OUTPUT_MESSAGE: &str = "Just works after a client left!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_fixed_delay(Duration::from_millis(100))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("hello".into())).unwrap();
+    if let MaybeTlsStream::Plain(stream) = client.get_mut() {
+        stream.shutdown(std::net::Shutdown::Both).unwrap();
+    }
+    drop(client);
+    std::thread::sleep(Duration::from_millis(300));
+
+    assert!(handle.take_send_errors().is_empty());
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("hello".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+}
+
+#[test]
+fn should_returns_on_message_when_throttle_applied() {
+    const PAYLOAD_SIZE: usize = 1024 * 1024;
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("download"))
+                .with_throttle(PAYLOAD_SIZE as u32)
+                .returning_binary(vec![7u8; PAYLOAD_SIZE]),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    let now = Instant::now();
+    client.send(Message::Text("download".into())).unwrap();
+    let msg = client.read().unwrap();
+    let elapsed = now.elapsed();
+
+    assert!(msg.is_binary());
+    assert_eq!(msg.into_data().len(), PAYLOAD_SIZE);
+    assert!(elapsed >= Duration::from_millis(900), "elapsed {elapsed:?}");
+    assert!(elapsed < Duration::from_secs(3), "elapsed {elapsed:?}");
+}
+
+#[test]
+fn should_returns_on_periodical() {
+    const MESSAGE_1: &str = "Just works with first message!";
+    const MESSAGE_2: &str = "Just works with second message!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(200))
+                .returning_text(MESSAGE_1)
+                .returning_text(MESSAGE_2)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client_1 = connect(&handle);
+    let mut client_2 = connect(&handle);
+
+    let msg = client_1.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), MESSAGE_1);
+
+    let msg = client_1.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), MESSAGE_2);
+
+    let msg = client_2.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), MESSAGE_1);
+
+    let msg = client_2.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), MESSAGE_2);
+}
+
+#[test]
+fn should_returns_on_periodical_when_shared_sequence() {
+    const MESSAGE_1: &str = "Just works with first message!";
+    const MESSAGE_2: &str = "Just works with second message!";
+    const FALLBACK_MESSAGE: &str = "Sequence already consumed!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_periodical()
+                .shared_sequence()
+                .returning_text(MESSAGE_1)
+                .returning_text(MESSAGE_2)
+                .build(),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(200))
+                .returning_text(FALLBACK_MESSAGE)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client_1 = connect(&handle);
+
+    for expected in [MESSAGE_1, MESSAGE_2, FALLBACK_MESSAGE] {
+        let msg = client_1.read().unwrap();
+        assert_eq!(msg.into_text().unwrap(), expected);
+    }
+
+    // The shared messages were already consumed by the first client, so only the
+    // per-connection one is left.
+    let mut client_2 = connect(&handle);
+
+    let msg = client_2.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), FALLBACK_MESSAGE);
+}
+
+#[test]
+fn should_returns_on_periodical_when_looping() {
+    const MESSAGE_1: &str = "Tick!";
+    const MESSAGE_2: &str = "Tock!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(50))
+                .looping()
+                .returning_text(MESSAGE_1)
+                .returning_text(MESSAGE_2)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for expected in [MESSAGE_1, MESSAGE_2, MESSAGE_1, MESSAGE_2, MESSAGE_1] {
+        let msg = client.read().unwrap();
+        assert_eq!(msg.into_text().unwrap(), expected);
+    }
+}
+
+#[test]
+fn should_stop_on_periodical_when_take_reached() {
+    const MESSAGE_1: &str = "Tick!";
+    const MESSAGE_2: &str = "Tock!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(50))
+                .looping()
+                .take(3)
+                .returning_text(MESSAGE_1)
+                .returning_text(MESSAGE_2)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for expected in [MESSAGE_1, MESSAGE_2, MESSAGE_1] {
+        let msg = client.read().unwrap();
+        assert_eq!(msg.into_text().unwrap(), expected);
+    }
+
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_stop_on_periodical_when_duration_elapsed() {
+    const MESSAGE: &str = "Tick!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(100))
+                .looping()
+                .for_duration(Duration::from_millis(250))
+                .returning_text(MESSAGE)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    for _ in 0..3 {
+        let msg = client.read().unwrap();
+        assert_eq!(msg.into_text().unwrap(), MESSAGE);
+    }
+
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_replay_timeline_on_connect() {
+    let timeline = vec![
+        (
+            Duration::from_millis(0),
+            Body::PlainText("first".to_string()),
+        ),
+        (
+            Duration::from_millis(300),
+            Body::Json(json_value!({"seq": 2})),
+        ),
+        (Duration::from_millis(600), Body::Binary(vec![3])),
+    ];
+    let handle = listen_with(|server| server.replay(timeline.clone()));
+
+    let start = Instant::now();
+    let mut client = connect(&handle);
+
+    assert_eq!(client.read().unwrap(), Message::text("first"));
+    assert!(start.elapsed() < Duration::from_millis(300));
+
+    assert_eq!(client.read().unwrap(), Message::text(r#"{"seq":2}"#));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(600));
+
+    assert_eq!(client.read().unwrap(), Message::binary(vec![3]));
+    assert!(start.elapsed() >= Duration::from_millis(600));
+}
+
+#[test]
+fn should_fail_register_when_looping_without_delay() {
+    let handle = listen();
+
+    let errors = handle
+        .register(
+            on_periodical()
+                .looping()
+                .returning_text("Too fast!")
+                .build(),
+        )
+        .unwrap_err();
+
+    assert!(matches!(
+        errors.as_slice(),
+        [BuildError::LoopingWithoutDelay]
+    ));
+}
+
+#[test]
+fn should_returns_on_ping_when_payload_like() {
+    const OUTPUT_MESSAGE: &str = "Just works with ping frames!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_ping()
+                .with_payload_like(binary_eq(b"heartbeat".to_vec()))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Ping("other".into())).unwrap();
+    assert!(matches!(client.read().unwrap(), Message::Pong(_)));
+    assert_no_message(&mut client, Duration::from_millis(300));
+
+    // The automatic pong and the stub response may arrive in any order.
+    client.send(Message::Ping("heartbeat".into())).unwrap();
+    let msgs = [client.read().unwrap(), client.read().unwrap()];
+    assert!(msgs.iter().any(|msg| matches!(msg, Message::Pong(_))));
+    assert!(msgs.contains(&Message::Text(OUTPUT_MESSAGE.into())));
+}
+
+#[test]
+fn should_ignore_pings_when_auto_pong_disabled() {
+    let handle = listen_with(|server| server.auto_pong(false));
+
+    handle
+        .register(
+            on_ping()
+                .with_payload_like(binary_eq(b"heartbeat".to_vec()))
+                .returning_text("ping seen"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("hello"))
+                .returning_binary(vec![0xa0; 300]),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Ping("other".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
+
+    client.send(Message::Ping("heartbeat".into())).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("ping seen"));
+    assert_no_message(&mut client, Duration::from_millis(300));
+
+    // Frames around the dropped pongs go through untouched.
+    client.send(Message::Ping("other".into())).unwrap();
+    client.send(Message::text("hello")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::binary(vec![0xa0; 300]));
+}
+
+#[test]
+fn should_returns_on_pong_when_payload_like() {
+    const OUTPUT_MESSAGE: &str = "Just works with pong frames!";
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_pong()
+                .with_payload_like(binary_eq(b"unsolicited".to_vec()))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+
+    client.send(Message::Pong("unsolicited".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+}
+
+#[test]
+fn should_count_connected_clients() {
+    let handle = listen();
+
+    let client_1 = connect(&handle);
+    let _client_2 = connect(&handle);
+    let _client_3 = connect(&handle);
+    wait_until(|| handle.connection_count() == 3);
+
+    drop(client_1);
+    wait_until(|| handle.connection_count() == 2);
+}
+
+#[test]
+fn should_reject_connection_when_max_connections_open() {
+    let handle = listen_with(|server| server.max_connections(2));
+
+    let mut client_1 = connect(&handle);
+    let _client_2 = connect(&handle);
+    wait_until(|| handle.connection_count() == 2);
+
+    match try_connect_hdr(&handle, HashMap::new()) {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 503),
+        Err(err) => panic!("Expected HTTP error, received {err:?}"),
+        Ok(_) => panic!("Expected handshake to be rejected"),
+    }
+
+    client_1.close(None).unwrap();
+    while client_1.read().is_ok() {}
+    wait_until(|| handle.connection_count() == 1);
+
+    assert!(try_connect_hdr(&handle, HashMap::new()).is_ok());
+}
+
+#[test]
+fn should_reject_connection_when_origin_not_allowed() {
+    let handle = listen_with(|server| server.allowed_origins(["https://app.example.com"]));
+
+    handle
+        .register(
+            on_connect()
+                .with_origin(text_eq("https://app.example.com"))
+                .returning_text("Welcome!"),
+        )
+        .unwrap();
+
+    for headers in [map!["Origin" => "https://evil.example.com"], HashMap::new()] {
+        match try_connect_hdr(&handle, headers) {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 403),
+            Err(err) => panic!("Expected HTTP error, received {err:?}"),
+            Ok(_) => panic!("Expected handshake to be rejected"),
+        }
+    }
+
+    let mut client = connect_hdr(&handle, map!["Origin" => "https://app.example.com"]);
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Welcome!");
+}
+
+#[test]
+fn should_serve_queued_connections_when_worker_threads_free_up() {
+    let handle = listen_with(|server| server.max_worker_threads(2));
+
+    // Queued connections reuse the threads of closed ones, and still start the sequence over.
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_sequence(
+                    vec![
+                        Body::PlainText("pong".to_string()),
+                        Body::PlainText("pong again".to_string()),
+                    ],
+                    false,
+                ),
+        )
+        .unwrap();
+
+    // Handshakes run concurrently, so the workers are taken one connection at a time to know
+    // which ones are queued.
+    let mut clients: Vec<_> = (0..2)
+        .map(|_| {
+            let mut client = connect(&handle);
+            client.send(Message::text("ping")).unwrap();
+            assert_eq!(client.read().unwrap(), Message::text("pong"));
+            client
+        })
+        .collect();
+    for _ in 0..2 {
+        let mut client = connect(&handle);
+        client.send(Message::text("ping")).unwrap();
+        clients.push(client);
+    }
+    assert_no_message(&mut clients[2], Duration::from_millis(300));
+
+    for mut client in clients.drain(..2) {
+        client.close(None).unwrap();
+        while client.read().is_ok() {}
+    }
+    for client in clients.iter_mut() {
+        if let MaybeTlsStream::Plain(stream) = client.get_mut() {
+            stream.set_read_timeout(None).unwrap();
+        }
+        assert_eq!(client.read().unwrap(), Message::text("pong"));
+    }
+}
+
+#[test]
+fn should_start_servers_from_a_cloned_configuration() {
+    let configured =
+        Server::default()
+            .path("/feed".to_string())
+            .on_unmatched(UnmatchedBehavior::Reply(Body::PlainText(
+                "Unmatched".to_string(),
+            )));
+
+    let first = listen_with(|_| configured.clone());
+    let second = listen_with(|_| configured.clone());
+    assert_ne!(first.port(), second.port());
+
+    first
+        .register(on_message().returning_text("From the first server"))
+        .unwrap();
+
+    let mut client = connect(&first);
+    client.send(Message::text("hello")).unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        Message::text("From the first server")
+    );
+
+    let mut client = connect(&second);
+    assert!(second.url().ends_with("/feed"));
+    client.send(Message::text("hello")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("Unmatched"));
+}
+
+#[test]
+fn should_record_handshakes_even_when_no_stub_matches() {
+    let handle = listen_with(|server| server.with_subprotocols(["json.v1"]).require_subprotocol());
+
+    assert!(try_connect_hdr(&handle, map!["X-Api-Key" => "wrong"]).is_err());
+    let _client = connect_hdr(
+        &handle,
+        map![
+            "X-Api-Key" => "secret",
+            "Sec-WebSocket-Protocol" => "xml.v1, json.v1",
+        ],
+    );
+    wait_until(|| handle.handshakes().len() == 2);
+
+    let handshakes = handle.handshakes();
+    let rejected = &handshakes[0];
+    assert_eq!(rejected.connection, None);
+    assert_eq!(rejected.status, 400);
+    assert_eq!(rejected.header("x-api-key"), Some("wrong"));
+
+    let accepted = &handshakes[1];
+    assert_eq!(accepted.connection, Some(handle.connection_ids()[0]));
+    assert!(accepted.request_line.starts_with("GET "));
+    assert!(accepted.request_line.ends_with(" HTTP/1.1"));
+    assert_eq!(accepted.status, 101);
+    assert_eq!(accepted.header("X-API-KEY"), Some("secret"));
+    assert_eq!(
+        accepted.header("sec-websocket-protocol"),
+        Some("xml.v1, json.v1")
+    );
+    assert!(
+        accepted
+            .response_headers
+            .contains(&("sec-websocket-protocol".to_string(), "json.v1".to_string()))
+    );
+}
+
+#[test]
+fn should_push_message_to_connection_by_id() {
+    const PUSHED_MESSAGE: &str = "Just for the second client!";
+
+    let handle = listen();
+
+    let mut client_1 = connect(&handle);
+    let mut client_2 = connect(&handle);
+    wait_until(|| handle.connection_ids().len() == 2);
+    let ids = handle.connection_ids();
+
+    handle
+        .send_to(ids[1], Body::PlainText(PUSHED_MESSAGE.to_string()))
+        .unwrap();
+
+    let msg = client_2.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), PUSHED_MESSAGE);
+    assert_no_message(&mut client_1, Duration::from_millis(300));
+
+    drop(client_1);
+    wait_until(|| handle.connection_count() == 1);
+    assert!(matches!(
+        handle.send_to(ids[0], Body::PlainText(PUSHED_MESSAGE.to_string())),
+        Err(SendToError::NotConnected(id)) if id == ids[0]
+    ));
+}
+
+#[test]
+fn should_send_closely_scheduled_periodical_messages() {
+    const TOTAL_MESSAGES: usize = 1000;
+
+    let handle = listen();
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(1))
+                .returning_text("Just works!")
+                .looping()
+                .take(TOTAL_MESSAGES)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    for _ in 0..TOTAL_MESSAGES {
+        let msg = client.read().unwrap();
+        assert_eq!(msg.into_text().unwrap(), "Just works!");
+    }
+    assert_no_message(&mut client, Duration::from_millis(100));
+}
+
+#[test]
+fn should_send_scheduled_message_while_client_floods() {
+    const SCHEDULED_MESSAGE: &str = "Just works!";
+
+    let handle = listen();
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(300))
+                .returning_text(SCHEDULED_MESSAGE)
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    let now = Instant::now();
+    if let MaybeTlsStream::Plain(stream) = client.get_mut() {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(5)))
+            .unwrap();
+    }
+
+    let received_after = loop {
+        assert!(now.elapsed() < Duration::from_secs(3));
+        client.send(Message::Text("flood".into())).unwrap();
+        match client.read() {
+            Ok(msg) => {
+                assert_eq!(msg.into_text().unwrap(), SCHEDULED_MESSAGE);
+                break now.elapsed();
+            }
+            Err(tungstenite::Error::Io(_)) => {}
+            Err(err) => panic!("Unexpected error {err:?}"),
+        }
+    };
+    assert!(received_after < Duration::from_millis(450));
+}
+
+#[test]
+fn should_answer_uncompressed_when_client_offers_deflate() {
+    let handle = listen();
+    let response = serde_json::json!(vec!["Just works!"; 1000]);
+    handle
+        .register(on_message().returning_json(response.clone()))
+        .unwrap();
+
+    let conn_string = handle.url();
+    let req = Request::builder()
+        .method("GET")
+        .header("Host", conn_string.as_str())
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "Secret-Key")
+        .header("Sec-WebSocket-Extensions", "permessage-deflate")
+        .uri(conn_string.as_str())
+        .body(())
+        .unwrap();
+    let (mut client, handshake) = tungstenite::connect(req).unwrap();
+    assert!(
+        handshake
+            .headers()
+            .get("Sec-WebSocket-Extensions")
+            .is_none()
+    );
+
+    client.send(Message::Text("Hello".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(msg.into_text().unwrap().as_str()).unwrap(),
+        response
+    );
+}
+
+#[test]
+fn should_negotiate_first_offered_supported_subprotocol() {
+    let handle = listen_with(|server| server.with_subprotocols(["json.v1", "json.v2"]));
+    handle
+        .register(
+            on_connect()
+                .with_subprotocol(text_eq("json.v2"))
+                .returning_text("Speaking json.v2"),
+        )
+        .unwrap();
+
+    let conn_string = handle.url();
+    let req = Request::builder()
+        .method("GET")
+        .header("Host", conn_string.as_str())
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "Secret-Key")
+        .header("Sec-WebSocket-Protocol", "json.v3, json.v2, json.v1")
+        .uri(conn_string.as_str())
+        .body(())
+        .unwrap();
+    let (mut client, handshake) = tungstenite::connect(req).unwrap();
+    assert_eq!(
+        handshake.headers().get("Sec-WebSocket-Protocol").unwrap(),
+        "json.v2"
+    );
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), "Speaking json.v2");
+}
+
+#[test]
+fn should_returns_text_on_connect_when_exactly_one_auth_header_is_present() {
+    const OUTPUT_MESSAGE: &str = "Just works!";
+
+    let handle = listen();
+    handle
+        .register(
+            on_connect()
+                .with_headers_fn(|headers| {
+                    let api_key = headers.contains_key("x-api-key");
+                    let authorization = headers.contains_key("authorization");
+                    (api_key != authorization).into()
+                })
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect_hdr(&handle, map!["X-Api-Key" => "AAABBBCCCDDD"]);
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    let mut client = connect_hdr(&handle, map!["Authorization" => "AAABBBCCCDDD"]);
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    let mut client = connect_hdr(
+        &handle,
+        map![
+            "X-Api-Key" => "AAABBBCCCDDD",
+            "Authorization" => "AAABBBCCCDDD",
+        ],
+    );
+    assert_no_message(&mut client, Duration::from_millis(300));
+
+    let mut client = connect(&handle);
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_returns_text_on_message_when_optional_field_is_missing() {
+    const OUTPUT_MESSAGE: &str = "Just works!";
+
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_json_body_like(json_object![
+                    "name" => text_eq("John"),
+                    "age" => json_optional(int_gt(18)),
+                ])
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client
+        .send(Message::Text(r#"{"name":"John"}"#.into()))
+        .unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    client
+        .send(Message::Text(r#"{"name":"John","age":10}"#.into()))
+        .unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_returns_text_on_message_when_text_matches_pattern() {
+    const OUTPUT_MESSAGE: &str = "Just works!";
+
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_matching(r"^order-\d+$")
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("order-42".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    client.send(Message::Text("order-abc".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(300));
+}
+
+#[test]
+fn should_fail_registration_when_text_regex_is_invalid() {
+    let handle = listen();
+
+    let result = handle.register(
+        on_message()
+            .with_text_matching("order-(")
+            .returning_text("Never sent"),
+    );
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidTextRegex { .. }]
+    ));
+
+    let result = handle.register(
+        on_periodical()
+            .with_header_matching("authorization", "Bearer (")
+            .returning_text("Never sent")
+            .build(),
+    );
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidHeaderRegex { key, .. }] if key == "authorization"
+    ));
+}
+
+#[test]
+fn should_reject_inverted_or_zero_width_strict_delay_intervals() {
+    let (short, long) = (Duration::from_millis(10), Duration::from_millis(20));
+
+    assert_eq!(
+        on_message().try_with_delay_interval_in(long, short).err(),
+        Some(DelayError::Inverted {
+            lower: long,
+            upper: short
+        })
+    );
+    assert_eq!(
+        on_ping().try_with_delay_interval_in(short, short).err(),
+        Some(DelayError::ZeroWidth(short))
+    );
+    assert_eq!(
+        on_periodical().try_with_delay_interval_in(long, long).err(),
+        Some(DelayError::ZeroWidth(long))
+    );
+
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .try_with_delay_interval_in(short, long)
+                .unwrap()
+                .returning_text("pong"),
+        )
+        .unwrap();
+    let mut client = connect(&handle);
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+}
+
+#[test]
+fn should_returns_text_on_message_from_highest_priority_when_scores_tie() {
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("order"))
+                .returning_text("Catch-all"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("order"))
+                .with_priority(10)
+                .returning_text("Override"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("order"))
+                .returning_text("Same priority, registered later"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("order-42".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), "Override");
+}
+
+#[test]
+fn should_reply_default_body_when_no_stub_matches() {
+    let handle = listen_with(|server| {
+        server.on_unmatched(UnmatchedBehavior::Reply(Body::PlainText(
+            "No stub matched".to_string(),
+        )))
+    });
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello"))
+                .returning_text("Just works!"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("Hello".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Just works!");
+
+    client.send(Message::Text("Bye".into())).unwrap();
+    assert_eq!(
+        client.read().unwrap().into_text().unwrap(),
+        "No stub matched"
+    );
+}
+
+#[test]
+fn should_close_when_no_stub_matches() {
+    let handle = listen_with(|server| {
+        server.on_unmatched(UnmatchedBehavior::Close(4000, "unmatched".to_string()))
+    });
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("Hello".into())).unwrap();
+
+    match client.read().unwrap() {
+        Message::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 4000);
+            assert_eq!(frame.reason.as_str(), "unmatched");
+        }
+        other => panic!("Expected close frame, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_report_stub_scores_for_last_message() {
+    let handle = listen();
+    assert!(handle.last_match_report().is_none());
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello!"))
+                .returning_text("Near miss"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello"))
+                .returning_text("Specific"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("Hel"))
+                .returning_text("General"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("Hello".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Specific");
+
+    let report = handle.last_match_report().unwrap();
+    assert!(matches!(report.body, Body::PlainText(ref text) if text == "Hello"));
+    assert_eq!(report.winner, Some(1));
+    assert_eq!(report.stubs.len(), 3);
+    assert!(report.stubs[0].is_disqualified());
+    assert!(report.stubs[1].score > Some(0));
+    // A contains match cannot outscore an exact one, so the stub is not scored.
+    assert_eq!(report.stubs[2].score, None);
+    assert!(!report.stubs[2].is_disqualified());
+}
+
+#[test]
+fn should_returns_on_message_within_jitter() {
+    const BASE: Duration = Duration::from_millis(200);
+    const JITTER: Duration = Duration::from_millis(50);
+
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello"))
+                .with_jitter(BASE, JITTER)
+                .returning_text("World"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    for _ in 0..3 {
+        let start = Instant::now();
+        client.send(Message::Text("Hello".into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), "World");
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= BASE - JITTER,
+            "{elapsed:?} below the jitter window"
+        );
+        assert!(
+            elapsed <= BASE + JITTER * 2,
+            "{elapsed:?} above the jitter window"
+        );
+    }
+}
+
+#[test]
+fn should_answer_once_per_connection() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("hello"))
+                .returning_text("fallback"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("hello"))
+                .once()
+                .with_priority(1)
+                .returning_text("capabilities"),
+        )
+        .unwrap();
+
+    for _ in 0..2 {
+        let mut client = connect(&handle);
+        for expected in ["capabilities", "fallback"] {
+            client.send(Message::Text("hello".into())).unwrap();
+            assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+        }
+    }
+}
+
+#[test]
+fn should_echo_messages() {
+    let handle = listen();
+
+    handle.register(on_message().echoing()).unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text(r#"{"a":1}"#.into())).unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_text());
+    assert_eq!(msg.into_text().unwrap(), r#"{"a":1}"#);
+
+    client
+        .send(Message::Text(r#"{ "z": 1,  "a": [2, 3] }"#.into()))
+        .unwrap();
+    assert_eq!(
+        client.read().unwrap().into_text().unwrap(),
+        r#"{ "z": 1,  "a": [2, 3] }"#
+    );
+
+    client.send(Message::Text("Hello".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Hello");
+
+    client.send(Message::Binary(vec![0, 1, 2].into())).unwrap();
+    let msg = client.read().unwrap();
+    assert!(msg.is_binary());
+    assert_eq!(msg.into_data().as_ref(), [0, 1, 2]);
+}
+
+#[test]
+fn should_returns_many_messages_in_order() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("subscribe"))
+                .returning_many([
+                    Body::PlainText("ack".to_string()),
+                    Body::PlainText("first".to_string()),
+                    Body::PlainText("second".to_string()),
+                ]),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("subscribe".into())).unwrap();
+    for expected in ["ack", "first", "second"] {
+        assert_eq!(client.read().unwrap().into_text().unwrap(), expected);
+    }
+}
+
+#[test]
+fn should_returns_many_messages_at_their_offsets() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("subscribe"))
+                .returning_many_delayed([
+                    (
+                        Duration::from_millis(300),
+                        Body::PlainText("late".to_string()),
+                    ),
+                    (Duration::ZERO, Body::PlainText("ack".to_string())),
+                ]),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    let start = Instant::now();
+    client.send(Message::Text("subscribe".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "ack");
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "late");
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn should_drive_conversation_in_order() {
+    let handle = listen();
+
+    handle
+        .register(
+            conversation()
+                .expect(text_eq("A"))
+                .reply_text("B")
+                .expect(text_eq("C"))
+                .reply_text("D")
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("C".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(200));
+
+    for (request, reply) in [("A", "B"), ("C", "D")] {
+        client.send(Message::Text(request.into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), reply);
+    }
+
+    client.send(Message::Text("A".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(200));
+
+    let mut other = connect(&handle);
+    other.send(Message::Text("A".into())).unwrap();
+    assert_eq!(other.read().unwrap().into_text().unwrap(), "B");
+}
+
+#[test]
+fn should_close_conversation_on_unexpected_message_when_failing() {
+    let handle = listen();
+
+    handle
+        .register(
+            conversation()
+                .expect(text_eq("A"))
+                .reply_text("B")
+                .expect(text_eq("C"))
+                .reply_text("D")
+                .failing_on_unexpected()
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("A".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "B");
+    client.send(Message::Text("A".into())).unwrap();
+
+    match client.read().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1008),
+        other => panic!("Expected close frame, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_count_hits_per_stub() {
+    let handle = listen();
+
+    let ping = handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("pong"),
+        )
+        .unwrap();
+    let other = handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("other"))
+                .returning_text("other"),
+        )
+        .unwrap();
+    assert_ne!(ping, other);
+
+    let mut client = connect(&handle);
+    for _ in 0..3 {
+        client.send(Message::Text("ping".into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+    }
+
+    assert_eq!(handle.stub_hits(ping), 3);
+    assert_eq!(handle.stub_hits(other), 0);
+}
+
+#[test]
+fn should_stop_matching_cleared_stubs() {
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("pong"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+
+    handle.clear_stubs();
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_no_message(&mut client, Duration::from_millis(200));
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("reloaded"),
+        )
+        .unwrap();
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "reloaded");
+}
+
+#[test]
+fn should_replace_stubs_without_dropping_messages() {
+    // Registered before the first connection is accepted.
+    let handle = loop {
+        if let Ok(handle) = Server::default()
+            .port(NEXT_PORT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+            .start_with_stubs(vec![
+                on_message()
+                    .with_text_like(text_eq("ping"))
+                    .returning_text("pong"),
+            ])
+        {
+            break handle;
+        }
+    };
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+
+    // An invalid stub keeps the previous ones.
+    let invalid = on_message()
+        .with_text_matching("(")
+        .returning_text("broken");
+    assert!(handle.replace_stubs(vec![invalid]).is_err());
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+
+    let ids = handle
+        .replace_stubs(vec![
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("reloaded"),
+        ])
+        .unwrap();
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "reloaded");
+    assert_eq!(handle.stub_hits(ids[0]), 1);
+}
+
+#[test]
+fn should_stream_periodical_stubs_as_sse_to_plain_http_clients() {
+    let handle = listen_with(|server| server.sse_fallback());
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_millis(10))
+                .looping()
+                .returning_text("tick")
+                .build(),
+        )
+        .unwrap();
+
+    let mut http = TcpStream::connect(("127.0.0.1", handle.port())).unwrap();
+    http.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    http.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n")
+        .unwrap();
+
+    let mut received = String::new();
+    let mut buf = [0; 1024];
+    while !received.contains("data: tick\n\n") {
+        let read = http.read(&mut buf).unwrap();
+        assert!(read > 0, "stream closed after {received:?}");
+        received.push_str(std::str::from_utf8(&buf[..read]).unwrap());
+    }
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(received.contains("Content-Type: text/event-stream\r\n"));
+
+    let mut client = connect(&handle);
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "tick");
+}
+
+#[test]
+fn should_match_json_looking_text_with_text_matcher() {
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("\"action\": \"subscribe\""))
+                .returning_text("subscribed"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("42"))
+                .returning_text("answer"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client
+        .send(Message::Text(r#"{"action": "subscribe"}"#.into()))
+        .unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "subscribed");
+
+    client.send(Message::Text("42".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "answer");
+}
+
+#[test]
+fn should_match_json_looking_text_in_its_original_key_order() {
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_contains("\"b\":1,\"a\""))
+                .returning_text("unsorted"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client
+        .send(Message::Text(r#"{"b":1,"a":2}"#.into()))
+        .unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "unsorted");
+}
+
+#[test]
+fn should_select_stub_by_empty_or_non_empty_body() {
+    let handle = listen();
+    handle
+        .register(on_message().with_empty_body().returning_text("empty"))
+        .unwrap();
+    handle
+        .register(on_message().with_any_body().returning_text("non-empty"))
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "empty");
+
+    client.send(Message::Binary(Vec::new().into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "empty");
+
+    client.send(Message::Text("hello".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "non-empty");
+
+    client.send(Message::Binary(vec![0].into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "non-empty");
+}
+
+#[test]
+fn should_respond_when_interval_bounds_truncate_to_the_same_millisecond() {
+    let handle = listen();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .with_delay_interval_in(Duration::from_micros(1500), Duration::from_micros(1900))
+                .returning_text("pong"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("ping".into())).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "pong");
+}
+
+#[test]
+fn should_listen_on_ipv6() {
+    let handle = listen_with(|server| server.addr(Ipv6Addr::LOCALHOST));
+    handle
+        .register(on_connect().returning_text("Just works!"))
+        .unwrap();
+
+    assert_eq!(handle.url(), format!("ws://[::1]:{}/", handle.port()));
+    let (mut client, _) = tungstenite::connect(handle.url()).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Just works!");
+}
+
+#[test]
+fn should_serve_on_configured_path() {
+    let handle = listen_with(|server| server.path("/feed/v1".to_string()));
+    handle
+        .register(on_connect().returning_text("Just works!"))
+        .unwrap();
+
+    assert_eq!(
+        handle.url(),
+        format!("ws://127.0.0.1:{}/feed/v1", handle.port())
+    );
+    let (mut client, _) = tungstenite::connect(handle.url()).unwrap();
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Just works!");
+
+    match tungstenite::connect(format!("ws://127.0.0.1:{}/feed", handle.port())) {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 404),
+        other => panic!("Expected the handshake to be rejected, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_returns_on_connect_when_path_glob_matches() {
+    let handle = listen();
+    handle
+        .register(
+            on_connect()
+                .with_path_glob("/ws/*/events")
+                .returning_text("tenant events"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_connect()
+                .with_path_glob("/archive/**/latest")
+                .returning_text("archive"),
+        )
+        .unwrap();
+
+    let connect_to = |path: &str| {
+        tungstenite::connect(format!("ws://127.0.0.1:{}{path}", handle.port()))
+            .map(|(client, _)| client)
+    };
+
+    for path in [
+        "/ws/acme/events",
+        "/ws/globex/events/",
+        "/ws/acme/events?since=1",
+    ] {
+        let mut client = connect_to(path).unwrap();
+        assert_eq!(client.read().unwrap(), Message::text("tenant events"));
+    }
+    for path in ["/archive/latest", "/archive/2024/05/latest"] {
+        let mut client = connect_to(path).unwrap();
+        assert_eq!(client.read().unwrap(), Message::text("archive"));
+    }
+
+    for path in [
+        "/ws/events",
+        "/ws/acme/b/events",
+        "/ws/acme/events/old",
+        "/wss/acme/events",
+    ] {
+        match connect_to(path) {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 404),
+            other => panic!("Expected {path} to be rejected, received {other:?}"),
+        }
+    }
+
+    // The server path is still served, by stubs without a path glob.
+    let mut client = connect(&handle);
+    assert_no_message(&mut client, Duration::from_millis(200));
+}
+
+#[test]
+fn should_returns_on_path_matching_connections() {
+    let handle = listen();
+    handle
+        .register(
+            on_connect()
+                .with_path_matching(r"^/tenants/\d+$")
+                .returning_text("tenant"),
+        )
+        .unwrap();
+    handle
+        .register(
+            on_periodical()
+                .with_path_matching("^/tenants/")
+                .with_fixed_delay(Duration::from_millis(10))
+                .returning_text("tick")
+                .build(),
+        )
+        .unwrap();
+
+    let (mut client, _) =
+        tungstenite::connect(format!("ws://127.0.0.1:{}/tenants/42", handle.port())).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("tenant"));
+    assert_eq!(client.read().unwrap(), Message::text("tick"));
+
+    match tungstenite::connect(format!("ws://127.0.0.1:{}/tenants/acme", handle.port())) {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 404),
+        other => panic!("Expected the handshake to be rejected, received {other:?}"),
+    }
+
+    // Connections on the server path match neither stub.
+    let mut client = connect(&handle);
+    assert_no_message(&mut client, Duration::from_millis(200));
+
+    let result = handle.register(
+        on_connect()
+            .with_path_matching("/tenants/(")
+            .returning_text("Never sent"),
+    );
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidTextRegex { .. }]
+    ));
+}
+
+#[test]
+fn should_accept_connections_while_other_clients_are_silent() {
+    let handle = listen_with(|server| server.sse_fallback());
+    handle
+        .register(on_connect().returning_text("Welcome!"))
+        .unwrap();
+
+    let _silent = TcpStream::connect(("127.0.0.1", handle.port())).unwrap();
+    let mut partial = TcpStream::connect(("127.0.0.1", handle.port())).unwrap();
+    partial.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+
+    let mut client = connect(&handle);
+    assert_eq!(client.read().unwrap().into_text().unwrap(), "Welcome!");
+}
+
+#[test]
+fn should_close_idle_connection() {
+    let idle_timeout = Duration::from_millis(500);
+    let handle = listen_with(|server| server.idle_timeout(idle_timeout));
+
+    let mut client = connect(&handle);
+    let start = Instant::now();
+
+    match client.read().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1000),
+        other => panic!("Expected close frame, received {other:?}"),
+    }
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= idle_timeout - Duration::from_millis(50),
+        "{elapsed:?}"
+    );
+    assert!(elapsed < idle_timeout * 2, "{elapsed:?}");
+}
+
+#[test]
+fn should_not_close_connection_while_client_is_active() {
+    let handle = listen_with(|server| server.idle_timeout(Duration::from_millis(300)));
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("Hello"))
+                .returning_text("World"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    for _ in 0..4 {
+        std::thread::sleep(Duration::from_millis(150));
+        client.send(Message::Text("Hello".into())).unwrap();
+        assert_eq!(client.read().unwrap().into_text().unwrap(), "World");
+    }
+}
+
+#[test]
+fn should_close_with_message_too_big_when_exceeding_limits() {
+    for handle in [
+        listen_with(|server| server.max_message_size(1024)),
+        listen_with(|server| server.max_frame_size(1024)),
+    ] {
+        let mut client = connect(&handle);
+        client.send(Message::Text("a".repeat(2048).into())).unwrap();
+
+        match client.read().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1009),
+            other => panic!("Expected close frame, received {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn should_returns_json_built_with_json_value_macro() {
+    let handle = listen();
+    handle
+        .register(on_message().returning_json(json_value!({
+            "ok": true,
+            "n": 42,
+            "items": [1, 2.5, "three", null],
+            "user": {"name": "John", "tags": ["a", "b"]},
+        })))
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text("Hello".into())).unwrap();
+
+    let msg = client.read().unwrap();
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(msg.into_text().unwrap().as_str()).unwrap(),
+        serde_json::json!({
+            "ok": true,
+            "n": 42,
+            "items": [1, 2.5, "three", null],
+            "user": {"name": "John", "tags": ["a", "b"]},
+        })
+    );
+}
+
+#[test]
+fn should_returns_binary_from_hex_on_message() {
+    let handle = listen();
+    handle
+        .register(on_message().returning_binary_hex("de:ad:be:ef"))
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client
+        .send(Message::Binary(vec![0xCA, 0xFE].into()))
+        .unwrap();
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_data().as_ref(), [0xDE, 0xAD, 0xBE, 0xEF]);
+}
+
+#[test]
+fn should_fail_registration_when_hex_response_is_invalid() {
+    let handle = listen();
+
+    let result = handle.register(on_message().returning_binary_hex("abc"));
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidHex {
+            source: HexError::OddLength
+        }]
+    ));
+}
+
+#[test]
+fn should_returns_json_str_as_written_on_message() {
+    const FIXTURE: &str = "{\n  \"b\": 1,\n  \"a\": [1.50, 2e3]\n}";
+
+    let handle = listen();
+    handle
+        .register(on_message().returning_json_str(FIXTURE))
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::text("order")).unwrap();
+
+    assert_eq!(client.read().unwrap(), Message::text(FIXTURE));
+
+    let result = handle.register(on_message().returning_json_str(r#"{"a": 1,}"#));
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidJson { .. }]
+    ));
+}
+
+#[test]
+fn should_add_response_headers_to_handshake() {
+    let handle = listen_with(|server| server.with_subprotocols(["json.v1"]));
+    handle
+        .register(
+            on_connect()
+                .with_response_header("Set-Cookie", "session=abc")
+                .with_response_header("Set-Cookie", "theme=dark")
+                .with_response_header("X-Mock", "Just works!")
+                .returning_text("Connected"),
+        )
+        .unwrap();
+
+    let conn_string = handle.url();
+    let req = Request::builder()
+        .method("GET")
+        .header("Host", conn_string.as_str())
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "Secret-Key")
+        .header("Sec-WebSocket-Protocol", "json.v1")
+        .uri(conn_string.as_str())
+        .body(())
+        .unwrap();
+    let (_client, handshake) = tungstenite::connect(req).unwrap();
+
+    let cookies: Vec<_> = handshake.headers().get_all("Set-Cookie").iter().collect();
+    assert_eq!(cookies, ["session=abc", "theme=dark"]);
+    assert_eq!(handshake.headers().get("X-Mock").unwrap(), "Just works!");
+    assert_eq!(
+        handshake.headers().get("Sec-WebSocket-Protocol").unwrap(),
+        "json.v1"
+    );
+}
+
+#[test]
+fn should_fail_registration_when_response_header_is_invalid() {
+    let handle = listen();
+
+    let result = handle.register(
+        on_connect()
+            .with_response_header("Bad Header", "value")
+            .returning_text("Connected"),
+    );
+    assert!(matches!(
+        result.unwrap_err().as_slice(),
+        [BuildError::InvalidResponseHeader { name }] if name == "Bad Header"
+    ));
+}
+
+#[test]
+fn should_reject_unsupported_subprotocol_when_required() {
+    let handle = listen_with(|server| server.with_subprotocols(["json.v1"]).require_subprotocol());
+
+    match try_connect_hdr(&handle, map!["Sec-WebSocket-Protocol" => "json.v3"]) {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 400),
+        other => panic!("Expected rejected handshake, received {other:?}"),
+    }
+}
+
+#[test]
+fn should_push_message_within_poll_interval() {
+    const PUSHED_MESSAGE: &str = "Just works!";
+
+    let handle = listen_with(|server| server.poll_interval(Duration::from_millis(50)));
+    handle
+        .register(
+            on_periodical()
+                .with_fixed_delay(Duration::from_secs(10))
+                .returning_text("Too late")
+                .build(),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    wait_until(|| handle.connection_count() == 1);
+    std::thread::sleep(Duration::from_millis(100));
+
+    let now = Instant::now();
+    handle
+        .send_to(
+            handle.connection_ids()[0],
+            Body::PlainText(PUSHED_MESSAGE.to_string()),
+        )
+        .unwrap();
+
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), PUSHED_MESSAGE);
+    assert!(now.elapsed() < Duration::from_millis(500));
+}
+
+#[test]
+fn should_emit_connection_lifecycle_events() {
+    let handle = listen();
+    let events = handle.events();
+    let timeout = Duration::from_secs(3);
+
+    let mut client = connect_hdr(&handle, map!["x-client" => "events"]);
+
+    let id = match events.recv_timeout(timeout).unwrap() {
+        ServerEvent::Connected { id, headers } => {
+            assert_eq!(headers.get("x-client").map(String::as_str), Some("events"));
+            id
+        }
+        _ => panic!("Expected connected event"),
+    };
+
+    client.send(Message::Text("hello".into())).unwrap();
+    match events.recv_timeout(timeout).unwrap() {
+        ServerEvent::MessageReceived {
+            id: message_id,
+            body: Body::PlainText(text),
+        } => {
+            assert_eq!(message_id, id);
+            assert_eq!(text, "hello");
+        }
+        _ => panic!("Expected message received event"),
+    }
+
+    drop(client);
+    assert!(matches!(
+        events.recv_timeout(timeout).unwrap(),
+        ServerEvent::Disconnected { id: disconnected_id } if disconnected_id == id
+    ));
+}
+
+#[test]
+fn should_route_client_close_frames() {
+    let handle = listen();
+    let events = handle.events();
+    let timeout = Duration::from_secs(3);
+
+    let normal = handle
+        .register(
+            on_close()
+                .with_code_like(int_eq(1000))
+                .with_reason_like(text_eq("bye"))
+                .build(),
+        )
+        .unwrap();
+    let other = handle
+        .register(on_close().with_code_like(int_eq(1001)).build())
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client
+        .close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "bye".into(),
+        }))
+        .unwrap();
+
+    match client.read() {
+        Ok(Message::Close(Some(frame))) => assert_eq!(frame.code, CloseCode::Normal),
+        other => panic!("Expected the close to be acknowledged, received {other:?}"),
+    }
+    assert!(matches!(
+        client.read(),
+        Err(tungstenite::Error::ConnectionClosed)
+    ));
+
+    let mut close = None;
+    while let Ok(event) = events.recv_timeout(timeout) {
+        match event {
+            ServerEvent::CloseReceived { code, reason, .. } => close = Some((code, reason)),
+            ServerEvent::Disconnected { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(close, Some((1000, "bye".to_string())));
+    assert_eq!(handle.stub_hits(normal), 1);
+    assert_eq!(handle.stub_hits(other), 0);
+}
+
+#[test]
+fn should_record_raw_message_alongside_decoded_body() {
+    const RAW: &str = r#"{ "b": 1, "a": 2 }"#;
+
+    let handle = listen();
+
+    let mut client = connect(&handle);
+    client.send(Message::Text(RAW.into())).unwrap();
+
+    let recorded = handle
+        .wait_for_message(
+            BodyMatcher::Json(json_object!["a" => int_gt(1)]),
+            Duration::from_secs(3),
+        )
+        .unwrap();
+    assert!(matches!(recorded.body, Body::Json(_)));
+    assert_eq!(recorded.raw.into_text().unwrap(), RAW);
+}
+
+#[test]
+fn should_keep_serving_after_threads_panic() {
+    let handle = listen();
+
+    handle
+        .register(
+            on_message()
+                .with_body_fn(|_: Option<&Body>| -> u16 { panic!("matcher failed") })
+                .returning_text("Never returned"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::text("boom")).unwrap();
+    assert!(client.read().is_err());
+
+    // Panicking while the recorded messages are locked poisons their lock.
+    let waiting = handle.clone();
+    let waited = std::thread::spawn(move || {
+        waiting.wait_for_message(
+            BodyMatcher::Custom(Box::new(|_: Option<&Body>| -> u16 {
+                panic!("matcher failed")
+            })),
+            Duration::from_secs(3),
+        )
+    })
+    .join();
+    assert!(waited.is_err());
+
+    handle.clear_stubs();
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping"))
+                .returning_text("pong"),
+        )
+        .unwrap();
+
+    let mut client = connect(&handle);
+    client.send(Message::text("ping")).unwrap();
+    assert_eq!(client.read().unwrap(), Message::text("pong"));
+    assert!(
+        handle
+            .wait_for_message(
+                BodyMatcher::PlainText(text_eq("ping")),
+                Duration::from_secs(3)
+            )
+            .is_ok()
+    );
+}
+
+#[test]
+fn should_verify_client_send_rate() {
+    let handle = listen();
+
+    let mut steady = connect(&handle);
+    let events = handle.events();
+    for _ in 0..3 {
+        steady.send(Message::text("order")).unwrap();
+        std::thread::sleep(Duration::from_millis(400));
+    }
+    let mut bursty = connect(&handle);
+    for _ in 0..5 {
+        bursty.send(Message::text("order")).unwrap();
+        bursty.send(Message::text("heartbeat")).unwrap();
+    }
+    let mut received = 0;
+    while received < 13 {
+        if let ServerEvent::MessageReceived { .. } =
+            events.recv_timeout(Duration::from_secs(3)).unwrap()
+        {
+            received += 1;
+        }
+    }
+
+    handle.verify_rate_below(BodyMatcher::PlainText(text_eq("order")), 5);
+    let violation = std::panic::catch_unwind(|| {
+        handle.verify_rate_below(BodyMatcher::PlainText(text_eq("order")), 4);
+    })
+    .unwrap_err();
+    let message = violation.downcast_ref::<String>().unwrap();
+    assert!(message.contains("sent 5 matching messages"), "{message}");
+}
+
+#[test]
+fn should_wait_for_message_until_it_arrives() {
+    let handle = listen();
+
+    let client_handle = handle.clone();
+    let sender = std::thread::spawn(move || {
+        let mut client = connect(&client_handle);
+        std::thread::sleep(Duration::from_millis(100));
+        client.send(Message::Text("other".into())).unwrap();
+        client.send(Message::Text("late".into())).unwrap();
+        client
+    });
+
+    let now = Instant::now();
+    let recorded = handle
+        .wait_for_message(
+            BodyMatcher::PlainText(text_eq("late")),
+            Duration::from_secs(3),
+        )
+        .unwrap();
+    assert!(now.elapsed() < Duration::from_secs(1));
+    assert!(matches!(recorded.body, Body::PlainText(text) if text == "late"));
+
+    assert!(
+        handle
+            .wait_for_message(
+                BodyMatcher::PlainText(text_eq("never")),
+                Duration::from_millis(100),
+            )
+            .is_err()
+    );
+
+    drop(sender.join().unwrap());
+}
+
+#[test]
+fn should_send_ping_frames_on_interval() {
+    const OUTPUT_MESSAGE: &str = "Still alive!";
+
+    let ping_interval = Duration::from_millis(100);
+    let handle = listen_with(|server| server.ping_interval(ping_interval));
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("ping?"))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let now = Instant::now();
+    let mut client = connect(&handle);
+
+    for _ in 0..2 {
+        let msg = client.read().unwrap();
+        assert!(msg.is_ping());
+    }
+    assert!(now.checked_add(ping_interval * 2).unwrap() <= Instant::now());
+
+    client.send(Message::Text("ping?".into())).unwrap();
+    let msg = loop {
+        let msg = client.read().unwrap();
+        if !msg.is_ping() {
+            break msg;
+        }
+    };
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+}
+
+#[cfg(unix)]
+#[test]
+fn should_serve_over_unix_socket() {
+    const OUTPUT_MESSAGE: &str = "Just works over a Unix socket!";
+
+    let path = std::env::temp_dir().join(format!("anymock-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let handle = Server::default().unix_socket(&path).start().unwrap();
+    assert_eq!(handle.unix_socket(), Some(path.as_path()));
+
+    handle
+        .register(
+            on_message()
+                .with_text_like(text_eq("hello"))
+                .returning_text(OUTPUT_MESSAGE),
+        )
+        .unwrap();
+
+    let stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    let (mut client, _) = tungstenite::client::client(handle.url(), stream).unwrap();
+    client.send(Message::Text("hello".into())).unwrap();
+    let msg = client.read().unwrap();
+    assert_eq!(msg.into_text().unwrap(), OUTPUT_MESSAGE);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn listen() -> ServerHandle {
+    listen_with(|server| server)
+}
+
+fn listen_with(configure: impl Fn(Server) -> Server) -> ServerHandle {
+    loop {
+        if let Ok(listener) = configure(Server::default())
+            .port(NEXT_PORT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst))
+            .start()
+        {
+            return listener;
+        }
+    }
+}
+
+fn wait_until(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Condition not met in time");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn assert_no_message(client: &mut WebSocket<MaybeTlsStream<TcpStream>>, wait: Duration) {
+    if let MaybeTlsStream::Plain(stream) = client.get_mut() {
+        stream.set_read_timeout(Some(wait)).unwrap();
+    }
+
+    match client.read() {
+        Err(tungstenite::Error::Io(err)) => assert!(matches!(
+            err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        )),
+        other => panic!("Expected no message, received {other:?}"),
+    }
+}
+
+fn connect(handle: &ServerHandle) -> WebSocket<MaybeTlsStream<TcpStream>> {
+    connect_hdr(handle, HashMap::new())
+}
+
+fn connect_hdr(
+    handle: &ServerHandle,
+    headers: HashMap<&str, &str>,
+) -> WebSocket<MaybeTlsStream<TcpStream>> {
+    try_connect_hdr(handle, headers).unwrap()
+}
+
+fn try_connect_hdr(
+    handle: &ServerHandle,
+    headers: HashMap<&str, &str>,
+) -> tungstenite::Result<WebSocket<MaybeTlsStream<TcpStream>>> {
+    let conn_string = handle.url();
     let mut req_builder = Request::builder()
         .method("GET")
         .header("Host", conn_string.as_str())
@@ -273,5 +3226,5 @@ fn connect_hdr(
     }
 
     let req = req_builder.uri(conn_string.as_str()).body(()).unwrap();
-    tungstenite::connect(req).unwrap().0
+    tungstenite::connect(req).map(|(websocket, _)| websocket)
 }